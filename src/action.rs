@@ -0,0 +1,305 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use uuid::Uuid;
+use crate::binding::Binding;
+use crate::marking::Marking;
+use crate::net::ColoredPetriNet;
+use crate::transition::{FiringError, Transition};
+
+/// A running external action, which resolves to an error message if the action failed.
+pub type Action = Pin<Box<dyn Future<Output = Result<(), String>>>>;
+
+/// Starts the real-world action behind a firing, such as a robot motion or a human task.
+pub trait Executor {
+    fn start(&mut self, transition: &Transition, binding: &Binding) -> Action;
+}
+
+/// Where the tokens reserved by a firing go when its action fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Recovery {
+    // Back to the places they were taken from
+    Return,
+    // Into an error place
+    Route(Uuid),
+}
+
+/// Reasons a firing cannot be started, completed or failed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ActionError {
+    Firing(FiringError),
+    UnknownFiring(Uuid),
+}
+
+impl fmt::Display for ActionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ActionError::Firing(error) => write!(f, "{}", error),
+            ActionError::UnknownFiring(firing) => write!(f, "no firing {} is in flight", firing),
+        }
+    }
+}
+
+impl std::error::Error for ActionError {}
+
+impl From<FiringError> for ActionError {
+    fn from(error: FiringError) -> Self {
+        ActionError::Firing(error)
+    }
+}
+
+/// A firing whose action has started but not finished. Its consumed tokens are reserved: they
+/// are out of the marking, so no other firing can take them.
+pub struct InFlight {
+    pub id: Uuid,
+    pub transition: Uuid,
+    pub binding: Binding,
+    pub reserved: Marking,
+    pub produced: Marking,
+    action: Action,
+}
+
+impl fmt::Debug for InFlight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InFlight").field("id", &self.id).field("transition", &self.transition).field("binding", &self.binding).finish()
+    }
+}
+
+/// A firing whose action finished, with the error message if it failed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Finished {
+    pub firing: Uuid,
+    pub transition: Uuid,
+    pub result: Result<(), String>,
+}
+
+/// Runs firings as external actions that finish later, several at a time.
+///
+/// Starting a firing checks it as [`ColoredPetriNet::fire`] would and takes its consumed tokens
+/// out of the current marking. When the action completes, the outputs are put into their places;
+/// when it fails, the reserved tokens are recovered as set for the transition (returned by
+/// default). Actions are futures, polled by [`Dispatcher::poll`] from whatever runtime drives
+/// the net; outcomes can also be reported directly with [`Dispatcher::complete`] and
+/// [`Dispatcher::fail`].
+#[derive(Debug)]
+pub struct Dispatcher<E: Executor> {
+    pub executor: E,
+    pub recovery: HashMap<Uuid, Recovery>,
+    pub in_flight: Vec<InFlight>,
+}
+
+impl<E: Executor> Dispatcher<E> {
+    pub fn new(executor: E) -> Self {
+        Self { executor, recovery: HashMap::new(), in_flight: vec![] }
+    }
+
+    /// Sets where the reserved tokens of a transition go when its action fails.
+    pub fn on_failure(mut self, transition: Uuid, recovery: Recovery) -> Self {
+        self.recovery.insert(transition, recovery);
+        self
+    }
+
+    /// Reserves the tokens of a firing and starts its action, returning the id of the firing.
+    pub fn start(&mut self, net: &mut ColoredPetriNet, transition: &Uuid, binding: &Binding) -> Result<Uuid, ActionError> {
+        net.fire(transition, binding)?;
+        // Taken from the binding rather than the change in the marking, which misses tokens a
+        // transition puts back where it took them from
+        let fired = &net.transitions[transition];
+        let mut reserved = Marking::new();
+        for (symbol, (place, token)) in &binding.tokens {
+            if fired.input.get(place).is_some_and(|s| s.symbols.contains(symbol)) {
+                reserved.add_token(*place, token.clone());
+            }
+        }
+        let mut produced = Marking::new();
+        for (place, signature) in &fired.output {
+            for token in signature.symbols.iter().filter_map(|s| binding.token(s)) {
+                produced.add_token(*place, token.clone());
+            }
+        }
+        net.current_marking = &net.current_marking - &produced;
+        let action = self.executor.start(&net.transitions[transition], binding);
+        let id = Uuid::new_v4();
+        self.in_flight.push(InFlight { id, transition: *transition, binding: binding.clone(), reserved, produced, action });
+        Ok(id)
+    }
+
+    fn take(&mut self, firing: &Uuid) -> Result<InFlight, ActionError> {
+        let index = self.in_flight.iter().position(|f| f.id == *firing).ok_or(ActionError::UnknownFiring(*firing))?;
+        Ok(self.in_flight.remove(index))
+    }
+
+    /// Puts the outputs of a firing into their places. If that would break the colors or
+    /// capacity of a place, the firing stays in flight, so that it can be failed instead.
+    pub fn complete(&mut self, net: &mut ColoredPetriNet, firing: &Uuid) -> Result<(), ActionError> {
+        let flight = self.take(firing)?;
+        let next = &net.current_marking + &flight.produced;
+        for place in flight.produced.places() {
+            if let Some(p) = net.places.get(&place) {
+                if let Err(violation) = p.check(&next[&place]) {
+                    self.in_flight.push(flight);
+                    return Err(FiringError::PlaceViolation { place, violation }.into());
+                }
+            }
+        }
+        net.current_marking = next;
+        Ok(())
+    }
+
+    /// Recovers the reserved tokens of a firing whose action failed.
+    pub fn fail(&mut self, net: &mut ColoredPetriNet, firing: &Uuid) -> Result<(), ActionError> {
+        let flight = self.take(firing)?;
+        let reserved = match self.recovery.get(&flight.transition) {
+            Some(Recovery::Route(place)) => flight.reserved.values().flat_map(|t| t.values()).map(|t| (*place, t.clone())).collect(),
+            _ => flight.reserved,
+        };
+        net.current_marking = &net.current_marking + &reserved;
+        Ok(())
+    }
+
+    /// Polls every action in flight once, completing or failing the firings whose actions
+    /// finished. A completion that would break a place constraint fails the firing.
+    pub fn poll(&mut self, net: &mut ColoredPetriNet, cx: &mut Context<'_>) -> Vec<Finished> {
+        let ready = self
+            .in_flight
+            .iter_mut()
+            .filter_map(|flight| match flight.action.as_mut().poll(cx) {
+                Poll::Ready(result) => Some(Finished { firing: flight.id, transition: flight.transition, result }),
+                Poll::Pending => None,
+            })
+            .collect::<Vec<_>>();
+        ready
+            .into_iter()
+            .map(|mut finished| {
+                if finished.result.is_ok() {
+                    if let Err(error) = self.complete(net, &finished.firing) {
+                        finished.result = Err(error.to_string());
+                    }
+                }
+                if finished.result.is_err() {
+                    self.fail(net, &finished.firing).expect("the firing is in flight");
+                }
+                finished
+            })
+            .collect()
+    }
+
+    /// Polls the actions in flight with a waker that does nothing, for callers that poll in a
+    /// loop of their own rather than from an async runtime.
+    pub fn poll_now(&mut self, net: &mut ColoredPetriNet) -> Vec<Finished> {
+        struct Noop;
+        impl Wake for Noop {
+            fn wake(self: Arc<Self>) {}
+        }
+        let waker = Waker::from(Arc::new(Noop));
+        self.poll(net, &mut Context::from_waker(&waker))
+    }
+}
+
+/// An executor for tests, whose actions finish only when told to, in any order.
+///
+/// Actions are numbered in the order they were started.
+#[derive(Clone, Debug, Default)]
+pub struct MockExecutor {
+    pub started: Vec<(Uuid, Binding)>,
+    outcomes: Rc<RefCell<BTreeMap<usize, Result<(), String>>>>,
+}
+
+impl MockExecutor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lets the action started as the given number finish, successfully or with an error.
+    pub fn finish(&self, action: usize, result: Result<(), String>) {
+        self.outcomes.borrow_mut().insert(action, result);
+    }
+}
+
+impl Executor for MockExecutor {
+    fn start(&mut self, transition: &Transition, binding: &Binding) -> Action {
+        let action = self.started.len();
+        self.started.push((transition.id, binding.clone()));
+        let outcomes = self.outcomes.clone();
+        Box::pin(std::future::poll_fn(move |_| match outcomes.borrow_mut().remove(&action) {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }))
+    }
+}
+
+#[test]
+pub fn actions_in_flight() {
+    use std::collections::HashSet;
+    use crate::clade::Clade;
+    use crate::place::{Capacity, Place};
+    use crate::signature::Signature;
+    use crate::token::Token;
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    let idle = net.add_place(Place::new("idle".into()));
+    let done = net.add_place(Place::new("done".into()).with_capacity(Capacity::Total(1)));
+    let faulted = net.add_place(Place::new("faulted".into()));
+    for name in ["r1", "r2", "r3"] {
+        net.add_token(idle, Token::new(name.into(), robot1.clone()));
+    }
+    let sig = || Signature::new(HashSet::from(["x".into()]));
+    let work = net.add_transition(Transition::new(
+        "work".into(),
+        Some(HashMap::from([(idle, sig())])),
+        Some(HashMap::from([(done, sig())])),
+        None,
+        None,
+    ));
+
+    let mut dispatcher = Dispatcher::new(MockExecutor::new()).on_failure(work, Recovery::Route(faulted));
+    let mut firings = vec![];
+    for _ in 0..3 {
+        let binding = net.enabled()[0].1.clone();
+        firings.push(dispatcher.start(&mut net, &work, &binding).unwrap());
+    }
+    // Every robot is reserved by an action in flight
+    assert_eq!(net.current_marking.count(&idle), 0);
+    assert!(net.enabled().is_empty());
+    assert!(dispatcher.poll_now(&mut net).is_empty());
+
+    // Actions finish out of order; the third fails and its robot is routed to the error place
+    dispatcher.executor.finish(1, Ok(()));
+    dispatcher.executor.finish(2, Err("collision".into()));
+    let finished = dispatcher.poll_now(&mut net);
+    assert_eq!(finished.iter().map(|f| f.firing).collect::<Vec<_>>(), vec![firings[1], firings[2]]);
+    assert_eq!(finished[1].result, Err("collision".into()));
+    assert_eq!(net.current_marking.count(&done), 1);
+    assert_eq!(net.current_marking.count(&faulted), 1);
+
+    // The place is full, so the first robot cannot be put there and is failed instead
+    assert!(matches!(dispatcher.complete(&mut net, &firings[0]), Err(ActionError::Firing(FiringError::PlaceViolation { .. }))));
+    assert_eq!(dispatcher.in_flight.len(), 1);
+    dispatcher.recovery.clear();
+    dispatcher.fail(&mut net, &firings[0]).unwrap();
+    assert_eq!(net.current_marking.count(&idle), 1);
+    assert_eq!(dispatcher.fail(&mut net, &firings[0]), Err(ActionError::UnknownFiring(firings[0])));
+    assert_eq!(net.current_marking.total(), 3);
+
+    // A robot that stays in its place while working is still reserved, so it cannot start twice
+    let stay = net.add_transition(Transition::new(
+        "stay".into(),
+        Some(HashMap::from([(faulted, sig())])),
+        Some(HashMap::from([(faulted, sig())])),
+        None,
+        None,
+    ));
+    let binding = net.enabled().into_iter().find(|(t, _)| *t == stay).unwrap().1;
+    let firing = dispatcher.start(&mut net, &stay, &binding).unwrap();
+    assert_eq!(net.current_marking.count(&faulted), 0);
+    assert!(!net.enabled().iter().any(|(t, _)| *t == stay));
+    assert!(matches!(dispatcher.start(&mut net, &stay, &binding), Err(ActionError::Firing(FiringError::MissingToken { .. }))));
+    dispatcher.complete(&mut net, &firing).unwrap();
+    assert_eq!(net.current_marking.count(&faulted), 1);
+}
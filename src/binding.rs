@@ -0,0 +1,38 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::clade::Clade;
+use crate::symbol::Symbol;
+use crate::token::Token;
+
+/// An assignment of concrete tokens to the input symbols of a transition.
+///
+/// Each symbol is bound to a token together with the id of the place the token is taken from.
+/// Bindings are ordered by symbol so that they hash and print consistently.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct Binding {
+    pub tokens: BTreeMap<Symbol, (Uuid, Token)>,
+}
+
+impl Binding {
+    pub fn new(tokens: BTreeMap<Symbol, (Uuid, Token)>) -> Self {
+        Self { tokens }
+    }
+
+    /// The clade bound to each symbol, which is what guards are evaluated against.
+    pub fn clades(&self) -> HashMap<Symbol, Clade> {
+        self.tokens.iter().map(|(symbol, (_, token))| (symbol.clone(), token.clade.clone())).collect()
+    }
+
+    pub fn token(&self, symbol: &Symbol) -> Option<&Token> {
+        self.tokens.get(symbol).map(|(_, token)| token)
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let assignments: Vec<String> = self.tokens.iter().map(|(symbol, (_, token))| format!("{}={}", symbol, token.name)).collect();
+        write!(f, "{}", assignments.join(", "))
+    }
+}
@@ -138,6 +138,25 @@ impl Clade {
             }
         }
     }
+
+    /// Returns this clade and all of its descendents, in depth-first order.
+    pub fn flatten(&self) -> Vec<Clade> {
+        let mut clades = vec![self.clone()];
+        if let Clade::Branch { children, .. } = self {
+            for child in children {
+                clades.extend(child.flatten());
+            }
+        }
+        clades
+    }
+
+    /// Returns the leaf clades below (or equal to) this clade, in depth-first order.
+    pub fn leaves(&self) -> Vec<Clade> {
+        match self {
+            Clade::Branch { children, .. } => children.iter().flat_map(|c| c.leaves()).collect(),
+            Clade::Leaf { .. } => vec![self.clone()],
+        }
+    }
 }
 
 impl PartialOrd for Clade {
@@ -0,0 +1,437 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use itertools::Itertools;
+use uuid::Uuid;
+use crate::clade::Clade;
+use crate::guard::Guard;
+use crate::net::ColoredPetriNet;
+use crate::place::Capacity;
+use crate::signature::Signature;
+use crate::symbol::Symbol;
+use crate::transition::Transition;
+
+/// Something composing two nets had to settle one way, reported so that it can be reviewed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompositionConflict {
+    // A fusion names a place that is not in the left or the right net
+    UnknownPlace(Uuid),
+    // Fused places with different colors or capacities; those of the left place are kept
+    PlaceMismatch { left: Uuid, right: Uuid },
+    // Synchronized transitions use a shared place with signatures that cannot be matched up, so
+    // their tokens in that place are kept apart
+    SignatureMismatch { left: Uuid, right: Uuid, place: Uuid },
+    // A clade of the right net has the name of a left clade under a different parent
+    CladeMismatch { name: String },
+    // An element of the right net has the name of a left element, and was renamed
+    NameClash { element: Uuid, renamed: String },
+}
+
+impl fmt::Display for CompositionConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompositionConflict::UnknownPlace(place) => write!(f, "fused place {} is not in either net", place),
+            CompositionConflict::PlaceMismatch { left, right } => {
+                write!(f, "fused places {} and {} differ in colors or capacity; kept those of {}", left, right, left)
+            }
+            CompositionConflict::SignatureMismatch { left, right, place } => {
+                write!(f, "synchronized transitions {} and {} use place {} with different signatures", left, right, place)
+            }
+            CompositionConflict::CladeMismatch { name } => write!(f, "clade '{}' has different parents in the two nets", name),
+            CompositionConflict::NameClash { element, renamed } => write!(f, "{} was renamed to '{}' to keep names unique", element, renamed),
+        }
+    }
+}
+
+/// How two nets are put together: which places are fused into one. Transitions are synchronized
+/// when both nets have transitions with the same label.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Composition {
+    // Fuse places of the two nets that have the same name
+    pub by_name: bool,
+    // Pairs of a place of the left net and a place of the right net to fuse
+    pub fusions: Vec<(Uuid, Uuid)>,
+}
+
+impl Composition {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn by_name(mut self) -> Self {
+        self.by_name = true;
+        self
+    }
+
+    pub fn with_fusion(mut self, left: Uuid, right: Uuid) -> Self {
+        self.fusions.push((left, right));
+        self
+    }
+
+    /// Composes two nets into one, along with the conflicts found on the way.
+    ///
+    /// Registered clades of the right net are merged into those of the left net by name, and
+    /// tokens, colors and guards of both nets are rewritten to the merged hierarchy. A fused place
+    /// keeps the id and constraints of its left place, and holds the tokens of both. Every pair of
+    /// a left and a right transition with a shared label becomes one transition, whose arcs are
+    /// the union of both; tokens taken from (or read in) the same place are identified when both
+    /// signatures have the same size. Labels only one net uses are left free.
+    pub fn compose(&self, left: &ColoredPetriNet, right: &ColoredPetriNet) -> (ColoredPetriNet, Vec<CompositionConflict>) {
+        let mut conflicts = vec![];
+        let push = |conflicts: &mut Vec<CompositionConflict>, conflict| {
+            if !conflicts.contains(&conflict) {
+                conflicts.push(conflict);
+            }
+        };
+
+        // Clades
+        let mut clades = left.clades.clone();
+        let mut ids = HashMap::new();
+        for clade in &right.clades {
+            merge_clade(&mut clades, None, clade, &mut ids, &mut conflicts);
+        }
+        let refresh = |clade: &Clade| {
+            let id = ids.get(&clade.id()).copied().unwrap_or(clade.id());
+            clades.iter().find_map(|c| c.get(&id)).unwrap_or_else(|| clade.clone())
+        };
+        let mut net = left.clone();
+        let mut right = right.clone();
+        recolor(&mut net, &refresh);
+        recolor(&mut right, &refresh);
+        net.id = Uuid::new_v5(&left.id, right.id.as_bytes());
+        net.name = format!("{}+{}", left.name, right.name);
+        net.clades = clades.clone();
+        net.subnets.extend(right.subnets.clone());
+
+        // Places
+        let mut fusions = self.fusions.clone();
+        if self.by_name {
+            for place in right.places.values().sorted_by_key(|p| (p.name.clone(), p.id)) {
+                if let Some(fused) = left.places.values().find(|p| p.name == place.name) {
+                    fusions.push((fused.id, place.id));
+                }
+            }
+        }
+        let mut places: HashMap<Uuid, Uuid> = right.places.keys().map(|p| (*p, *p)).collect();
+        for (l, r) in fusions {
+            let (Some(fused), Some(place)) = (net.places.get(&l), right.places.get(&r)) else {
+                let missing = if net.places.contains_key(&l) { r } else { l };
+                push(&mut conflicts, CompositionConflict::UnknownPlace(missing));
+                continue;
+            };
+            let colors = |cs: &Vec<Clade>| cs.iter().map(|c| c.id()).sorted().collect_vec();
+            if colors(&fused.colors) != colors(&place.colors) || fused.capacity != place.capacity {
+                push(&mut conflicts, CompositionConflict::PlaceMismatch { left: l, right: r });
+            }
+            places.insert(r, l);
+        }
+        let mut names: HashSet<String> = net.places.values().map(|p| p.name.clone()).collect();
+        names.extend(net.transitions.values().map(|t| t.name.clone()));
+        let mut unique = |id: Uuid, name: &String, conflicts: &mut Vec<CompositionConflict>| {
+            let mut unique = name.clone();
+            if names.contains(&unique) {
+                unique = format!("{}.{}", right.name, name);
+                conflicts.push(CompositionConflict::NameClash { element: id, renamed: unique.clone() });
+            }
+            names.insert(unique.clone());
+            unique
+        };
+        for place in right.places.values().sorted_by_key(|p| (p.name.clone(), p.id)) {
+            if places[&place.id] == place.id {
+                let mut place = place.clone();
+                place.name = unique(place.id, &place.name, &mut conflicts);
+                net.places.insert(place.id, place);
+            }
+        }
+        for (marking, from) in [(&mut net.initial_marking, &right.initial_marking), (&mut net.current_marking, &right.current_marking)] {
+            for (place, tokens) in from {
+                let place = places.get(place).copied().unwrap_or(*place);
+                marking.entry(place).or_default().extend(tokens.clone());
+            }
+        }
+
+        // Transitions
+        let remap = |t: &Transition| {
+            let mut t = t.clone();
+            let place = |p: Uuid| places.get(&p).copied().unwrap_or(p);
+            t.input = t.input.into_iter().map(|(p, s)| (place(p), s)).collect();
+            t.output = t.output.into_iter().map(|(p, s)| (place(p), s)).collect();
+            t.reads = t.reads.into_iter().map(|(p, s)| (place(p), s)).collect();
+            t.inhibitors = t.inhibitors.into_iter().map(|(p, c)| (place(p), c)).collect();
+            t.resets = t.resets.into_iter().map(place).collect();
+            if let Some(substitution) = t.substitution.as_mut() {
+                substitution.ports.values_mut().for_each(|socket| *socket = place(*socket));
+            }
+            t
+        };
+        let labels = |n: &ColoredPetriNet| n.transitions.values().filter_map(|t| t.label.clone()).collect::<HashSet<_>>();
+        let shared: HashSet<String> = labels(left).intersection(&labels(&right)).cloned().collect();
+        let synchronized = |t: &Transition| t.label.as_ref().is_some_and(|l| shared.contains(l));
+        let right_transitions = right.declared_transitions().into_iter().map(remap).collect_vec();
+        let mut order = vec![];
+        for a in left.declared_transitions() {
+            if !synchronized(a) {
+                order.push(a.id);
+                continue;
+            }
+            let a = net.transitions.remove(&a.id).unwrap_or_else(|| recolored(a, &refresh));
+            for b in right_transitions.iter().filter(|b| b.label == a.label) {
+                let merged = synchronize(&a, b, &mut conflicts);
+                order.push(merged.id);
+                net.transitions.insert(merged.id, merged);
+            }
+        }
+        for mut b in right_transitions.into_iter().filter(|b| !synchronized(b)) {
+            b.name = unique(b.id, &b.name, &mut conflicts);
+            order.push(b.id);
+            net.transitions.insert(b.id, b);
+        }
+        net.transition_order = order;
+        net.name_lookup = net
+            .places
+            .values()
+            .map(|p| (p.id, p.name.clone()))
+            .chain(net.transitions.values().map(|t| (t.id, t.name.clone())))
+            .collect();
+        (net, conflicts)
+    }
+}
+
+// Adds a clade (and its descendants) to the hierarchy, under the given parent or as a new root,
+// unless a clade of the same name exists already, in which case the two are identified.
+fn merge_clade(
+    clades: &mut Vec<Clade>,
+    parent: Option<Uuid>,
+    clade: &Clade,
+    ids: &mut HashMap<Uuid, Uuid>,
+    conflicts: &mut Vec<CompositionConflict>,
+) {
+    let existing = clades.iter().find_map(|c| c.query(&clade.name()).map(|id| (id, c.parentage(&id).unwrap_or_default())));
+    let target = match (existing, parent) {
+        (Some((id, parentage)), Some(parent)) => {
+            if parentage.first() != Some(&parent) {
+                conflicts.push(CompositionConflict::CladeMismatch { name: clade.name() });
+            }
+            id
+        }
+        (Some((id, _)), None) => id,
+        (None, Some(parent)) => {
+            let leaf = Clade::Leaf { uuid: clade.id(), name: clade.name() };
+            *clades = clades.iter().map(|c| attach(c, &parent, &leaf)).collect();
+            clade.id()
+        }
+        (None, None) => {
+            clades.push(Clade::Leaf { uuid: clade.id(), name: clade.name() });
+            clade.id()
+        }
+    };
+    ids.insert(clade.id(), target);
+    for child in clade.children().into_iter().flatten() {
+        merge_clade(clades, Some(target), child, ids, conflicts);
+    }
+}
+
+// Rebuilds a clade with a child added below the given parent.
+fn attach(clade: &Clade, parent: &Uuid, child: &Clade) -> Clade {
+    let mut children = clade.children().cloned().unwrap_or_default().iter().map(|c| attach(c, parent, child)).collect_vec();
+    if clade.id() == *parent {
+        children.push(child.clone());
+    }
+    if children.is_empty() {
+        clade.clone()
+    } else {
+        Clade::Branch { uuid: clade.id(), name: clade.name(), children }
+    }
+}
+
+fn recolored(transition: &Transition, refresh: &dyn Fn(&Clade) -> Clade) -> Transition {
+    let mut transition = transition.clone();
+    transition.guard = transition.guard.map(&|s| s.clone(), refresh);
+    transition.inhibitors.values_mut().flatten().for_each(|c| *c = refresh(c));
+    transition
+}
+
+// Rewrites every clade the net refers to.
+fn recolor(net: &mut ColoredPetriNet, refresh: &dyn Fn(&Clade) -> Clade) {
+    for tokens in net.initial_marking.values_mut().chain(net.current_marking.values_mut()) {
+        tokens.values_mut().for_each(|t| t.clade = refresh(&t.clade));
+    }
+    for place in net.places.values_mut() {
+        place.colors = place.colors.iter().map(refresh).collect();
+        if let Some(Capacity::PerClade(limits)) = place.capacity.as_mut() {
+            limits.iter_mut().for_each(|(c, _)| *c = refresh(c));
+        }
+    }
+    for transition in net.transitions.values_mut() {
+        *transition = recolored(transition, refresh);
+    }
+}
+
+fn symbols(transition: &Transition) -> HashSet<Symbol> {
+    let arcs = transition.input.values().chain(transition.output.values()).chain(transition.reads.values());
+    arcs.flat_map(|s| s.symbols.iter().cloned()).chain(transition.guard.symbols()).collect()
+}
+
+// Merges two transitions that fire together. Symbols of the right transition are renamed to the
+// matching symbols of the left one where they take tokens from the same place, and otherwise to
+// fresh symbols where they would clash.
+fn synchronize(a: &Transition, b: &Transition, conflicts: &mut Vec<CompositionConflict>) -> Transition {
+    let mismatch = |place: &Uuid| CompositionConflict::SignatureMismatch { left: a.id, right: b.id, place: *place };
+    let mut rename: HashMap<Symbol, Symbol> = HashMap::new();
+    let taken = b.input.iter().chain(b.reads.iter()).sorted_by_key(|(p, _)| **p);
+    for (place, signature) in taken {
+        let Some(other) = a.input.get(place).or(a.reads.get(place)) else { continue };
+        if other.symbols.len() != signature.symbols.len() {
+            conflicts.push(mismatch(place));
+            continue;
+        }
+        for (x, y) in other.symbols.iter().sorted().zip(signature.symbols.iter().sorted()) {
+            if rename.get(y).is_some_and(|z| z != x) || rename.iter().any(|(w, z)| z == x && w != y) {
+                conflicts.push(mismatch(place));
+            } else {
+                rename.insert(y.clone(), x.clone());
+            }
+        }
+    }
+    let mut used: HashSet<Symbol> = symbols(a).into_iter().chain(symbols(b)).collect();
+    for symbol in symbols(b).into_iter().sorted() {
+        if !rename.contains_key(&symbol) && symbols(a).contains(&symbol) {
+            let mut fresh = symbol.name().to_string();
+            while used.contains(&Symbol::new(fresh.clone())) {
+                fresh.push('\'');
+            }
+            used.insert(Symbol::new(fresh.clone()));
+            rename.insert(symbol, Symbol::new(fresh));
+        }
+    }
+    let renamed = |s: &Symbol| rename.get(s).cloned().unwrap_or_else(|| s.clone());
+    let signature = |s: &Signature| Signature::new(s.symbols.iter().map(renamed).collect());
+
+    let mut merged = a.clone();
+    merged.id = Uuid::new_v5(&a.id, b.id.as_bytes());
+    if a.name != b.name {
+        merged.name = format!("{}|{}", a.name, b.name);
+    }
+    for (place, s) in &b.input {
+        merged.input.entry(*place).or_default().symbols.extend(signature(s).symbols);
+    }
+    for (place, s) in &b.output {
+        let s = signature(s);
+        if a.output.get(place).is_some_and(|o| *o != s) {
+            conflicts.push(mismatch(place));
+        }
+        merged.output.entry(*place).or_default().symbols.extend(s.symbols);
+    }
+    for (place, s) in &b.reads {
+        merged.reads.entry(*place).or_default().symbols.extend(signature(s).symbols);
+    }
+    // A token one transition consumes and the other only reads is consumed
+    for (place, read) in merged.reads.iter_mut() {
+        if let Some(input) = merged.input.get(place) {
+            read.symbols.retain(|s| !input.symbols.contains(s));
+        }
+    }
+    merged.reads.retain(|_, s| !s.symbols.is_empty());
+    merged.guard = match (&a.guard, b.guard.map(&renamed, &|c| c.clone())) {
+        (guard, Guard::Empty) => guard.clone(),
+        (Guard::Empty, guard) => guard,
+        (left, right) => Guard::All(vec![left.clone(), right]),
+    };
+    merged.inhibitors.extend(b.inhibitors.clone());
+    merged.resets.extend(b.resets.iter().copied());
+    merged.priority = a.priority.max(b.priority);
+    merged
+}
+
+#[test]
+pub fn compose_robot_and_conveyor() {
+    use std::collections::HashMap;
+    use crate::place::Place;
+    use crate::statespace::StateSpace;
+    use crate::token::Token;
+
+    let sig = |symbols: &[&str]| Signature::new(symbols.iter().map(|s| (*s).into()).collect());
+
+    // The robot picks parts from the end of the belt, and drops them when done
+    let r1 = Clade::new("r1".into(), None);
+    let part = Clade::new("part".into(), None);
+    let mut robot = ColoredPetriNet::new("robot".into(), None, None, None);
+    let robots = Clade::new("robot".into(), Some(vec![r1.clone()]));
+    robot.add_clade(Clade::new("thing".into(), Some(vec![robots.clone(), part.clone()])));
+    let idle = robot.add_place(Place::new("idle".into()));
+    let holding = robot.add_place(Place::new("holding".into()));
+    let end = robot.add_place(Place::new("belt_end".into()).with_colors(vec![part.clone()]));
+    let done = robot.add_place(Place::new("done".into()));
+    robot.add_token(idle, Token::new("r1".into(), r1.clone()));
+    let pick = robot.add_transition(
+        Transition::new(
+            "pick".into(),
+            Some(HashMap::from([(idle, sig(&["r"])), (end, sig(&["x"]))])),
+            Some(HashMap::from([(holding, sig(&["r", "x"]))])),
+            None,
+            None,
+        )
+        .with_label("handoff".into()),
+    );
+    robot.add_transition(Transition::new(
+        "drop".into(),
+        Some(HashMap::from([(holding, sig(&["r", "x"]))])),
+        Some(HashMap::from([(idle, sig(&["r"])), (done, sig(&["x"]))])),
+        Some(Guard::LessThanOrEqual("r".into(), robots.clone())),
+        None,
+    ));
+
+    // The conveyor, modelled separately with its own clade of parts, moves widgets along
+    let widget = Clade::new("widget".into(), None);
+    let conveyor_part = Clade::new("part".into(), Some(vec![widget.clone()]));
+    let mut conveyor = ColoredPetriNet::new("conveyor".into(), None, None, None);
+    conveyor.add_clade(Clade::new("thing".into(), Some(vec![conveyor_part.clone()])));
+    let start = conveyor.add_place(Place::new("belt_start".into()));
+    let belt_end = conveyor.add_place(Place::new("belt_end".into()).with_colors(vec![conveyor_part.clone()]));
+    conveyor.add_token(start, Token::new("w1".into(), widget.clone()));
+    conveyor.add_transition(Transition::new(
+        "move".into(),
+        Some(HashMap::from([(start, sig(&["x"]))])),
+        Some(HashMap::from([(belt_end, sig(&["x"]))])),
+        None,
+        None,
+    ));
+    let release = conveyor.add_transition(
+        Transition::new(
+            "release".into(),
+            Some(HashMap::from([(belt_end, sig(&["y"]))])),
+            None,
+            Some(Guard::LessThanOrEqual("y".into(), conveyor_part.clone())),
+            None,
+        )
+        .with_label("handoff".into()),
+    );
+    let scrap = conveyor.add_place(Place::new("scrap".into()));
+    let drop = conveyor.add_transition(Transition::new("drop".into(), Some(HashMap::from([(scrap, sig(&["x"]))])), None, None, None));
+
+    let (net, conflicts) = Composition::new().by_name().compose(&robot, &conveyor);
+    assert_eq!(conflicts, vec![CompositionConflict::NameClash { element: drop, renamed: "conveyor.drop".into() }]);
+    assert_eq!(net.places.len(), 6);
+    assert_eq!(net.clades.len(), 1);
+    assert!(net.find_clade("part").unwrap().descendent(&widget.id()));
+    let handoff = net.id_of("pick|release").unwrap();
+    assert_eq!(handoff, Uuid::new_v5(&pick, release.as_bytes()));
+    // Both transitions take the same part from the end of the belt
+    assert_eq!(net.transitions[&handoff].input[&end], sig(&["x"]));
+    let order = net.declared_transitions().iter().map(|t| t.name.clone()).collect_vec();
+    assert_eq!(order, ["pick|release", "drop", "move", "conveyor.drop"]);
+
+    let space = StateSpace::explore(&net, None);
+    assert_eq!(space.markings.len(), 4);
+    let last = &space.markings[space.dead()[0]];
+    assert_eq!(last[&done].values().map(|t| t.name.clone()).collect_vec(), ["w1"]);
+
+    // Signatures of different sizes on the shared place keep the tokens apart
+    let mut greedy = conveyor.clone();
+    greedy.transitions.get_mut(&release).unwrap().input.insert(belt_end, sig(&["y", "z"]));
+    let (_, conflicts) = Composition::new().with_fusion(end, belt_end).compose(&robot, &greedy);
+    assert!(conflicts.contains(&CompositionConflict::SignatureMismatch { left: pick, right: release, place: end }));
+    let mut capped = conveyor.clone();
+    capped.places.get_mut(&belt_end).unwrap().capacity = Some(Capacity::Total(1));
+    let (_, conflicts) = Composition::new().by_name().compose(&robot, &capped);
+    assert!(conflicts.contains(&CompositionConflict::PlaceMismatch { left: end, right: belt_end }));
+}
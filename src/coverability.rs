@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Add;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::incidence::Unsupported;
+use crate::net::ColoredPetriNet;
+use crate::unfold::{unfold, PtNet};
+
+/// A token count that may be unbounded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Count {
+    Finite(u64),
+    Omega,
+}
+
+impl Add for Count {
+    type Output = Count;
+
+    fn add(self, other: Count) -> Count {
+        match (self, other) {
+            (Count::Finite(a), Count::Finite(b)) => Count::Finite(a + b),
+            _ => Count::Omega,
+        }
+    }
+}
+
+impl fmt::Display for Count {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Count::Finite(n) => write!(f, "{}", n),
+            Count::Omega => write!(f, "ω"),
+        }
+    }
+}
+
+/// A node of the coverability tree, with counts indexed by the places of the unfolded net.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CoverabilityNode {
+    pub marking: Vec<Count>,
+    pub parent: Option<usize>,
+    // The unfolded transition fired to reach this node from its parent
+    pub transition: Option<usize>,
+    // Set when the same marking already occurs on the path from the root
+    pub duplicate: bool,
+    // The pumps, by index in the tree, that set the ω places of this marking
+    #[serde(default)]
+    pub pumps: Vec<usize>,
+}
+
+/// A firing sequence that can be repeated to grow the token count of an unfolded place without
+/// bound: fire `prefix` from the initial marking, then `cycle` any number of times.
+///
+/// The sequence follows the tree, so it may pass through markings where earlier pumps set
+/// places to ω. It can then only be fired as written once each pump in `requires` (by index in
+/// the tree) has been repeated often enough to supply the tokens it consumes from those places.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Pump {
+    pub place: usize,
+    pub prefix: Vec<usize>,
+    pub cycle: Vec<usize>,
+    #[serde(default)]
+    pub requires: Vec<usize>,
+}
+
+/// The Karp–Miller coverability tree of a net, built over token counts per clade.
+///
+/// Unlike the reachability graph it is always finite: whenever a marking strictly covers one
+/// of its ancestors, the places that grew are set to ω. Transitions of a colored net only move
+/// tokens, so the trees of the nets [`CoverabilityTree::build`] accepts have no ω; unbounded
+/// places come from place/transition nets given to [`CoverabilityTree::from_pt`].
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CoverabilityTree {
+    pub net: PtNet,
+    pub nodes: Vec<CoverabilityNode>,
+    pub pumps: Vec<Pump>,
+    // Whether construction stopped at the node limit
+    pub truncated: bool,
+}
+
+fn fire(net: &PtNet, marking: &[Count], transition: usize) -> Option<Vec<Count>> {
+    let t = &net.transitions[transition];
+    let enabled = t.input.iter().all(|(p, w)| match marking[*p] {
+        Count::Finite(n) => n >= *w,
+        Count::Omega => true,
+    });
+    if !enabled {
+        return None;
+    }
+    let mut next = marking.to_vec();
+    for (p, w) in &t.input {
+        if let Count::Finite(n) = next[*p] {
+            next[*p] = Count::Finite(n - w);
+        }
+    }
+    for (p, w) in &t.output {
+        next[*p] = next[*p] + Count::Finite(*w);
+    }
+    Some(next)
+}
+
+// Fails for nets whose behaviour depends on more than token counts per clade, or that put a
+// token in a second place while it stays in the first
+fn counted(net: &ColoredPetriNet) -> Result<(), Unsupported> {
+    let lowest = net.transitions.values().map(|t| t.priority).min();
+    let transitions = net
+        .transitions
+        .values()
+        .filter(|t| {
+            let produced = t.output.values().flat_map(|s| s.symbols.iter()).collect_vec();
+            let copies = produced.iter().duplicates().next().is_some() || t.reads.values().flat_map(|s| s.symbols.iter()).any(|s| produced.contains(&s));
+            copies || !t.inhibitors.is_empty() || Some(t.priority) != lowest
+        })
+        .map(|t| t.id)
+        .sorted()
+        .collect_vec();
+    let mut holders: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    for (place, tokens) in &net.initial_marking {
+        for token in tokens.keys() {
+            holders.entry(*token).or_default().push(*place);
+        }
+    }
+    let shared = holders.into_values().filter(|places| places.len() > 1).flatten();
+    let places = net.places.values().filter(|p| p.capacity.is_some()).map(|p| p.id).chain(shared).sorted().unique().collect_vec();
+    if transitions.is_empty() && places.is_empty() {
+        Ok(())
+    } else {
+        Err(Unsupported { transitions, places, reason: "capacities, inhibitors, priorities and tokens in two places are not counted exactly" })
+    }
+}
+
+impl CoverabilityTree {
+    /// Unfolds the net and builds the tree from its initial marking, stopping after `limit`
+    /// nodes (if given). Fails for nets with reset arcs, which cannot be unfolded, and for nets
+    /// the unfolded net does not count exactly: with capacities, inhibitors, priorities, or
+    /// tokens in more than one place, which the colored marking holds once.
+    pub fn build(net: &ColoredPetriNet, limit: Option<usize>) -> Result<Self, Unsupported> {
+        let unfolded = unfold(net)?;
+        counted(net)?;
+        Ok(Self::from_pt(unfolded, limit))
+    }
+
+    pub fn from_pt(net: PtNet, limit: Option<usize>) -> Self {
+        let root = CoverabilityNode {
+            marking: net.initial_marking.iter().map(|n| Count::Finite(*n)).collect(),
+            parent: None,
+            transition: None,
+            duplicate: false,
+            pumps: vec![],
+        };
+        let mut tree = CoverabilityTree { net, nodes: vec![root], pumps: vec![], truncated: false };
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            if tree.nodes[node].duplicate {
+                continue;
+            }
+            for transition in 0..tree.net.transitions.len() {
+                if limit.is_some_and(|l| tree.nodes.len() >= l) {
+                    tree.truncated = true;
+                    return tree;
+                }
+                let mut marking = match fire(&tree.net, &tree.nodes[node].marking, transition) {
+                    Some(m) => m,
+                    None => continue,
+                };
+                let child = tree.nodes.len();
+                let ancestors = tree.ancestors(node);
+                // ω places only grow along a path, so those of the node cover the whole cycle
+                let requires = tree.nodes[node].pumps.clone();
+                let mut pumps = requires.clone();
+                for ancestor in ancestors.iter().copied() {
+                    let covered = &tree.nodes[ancestor].marking;
+                    if covered.iter().zip(marking.iter()).all(|(a, m)| a <= m) {
+                        for place in 0..marking.len() {
+                            if covered[place] < marking[place] && marking[place] != Count::Omega {
+                                marking[place] = Count::Omega;
+                                let path = tree.path(node).into_iter().chain([transition]).collect_vec();
+                                let prefix = tree.path(ancestor);
+                                let cycle = path[prefix.len()..].to_vec();
+                                pumps.push(tree.pumps.len());
+                                tree.pumps.push(Pump { place, prefix, cycle, requires: requires.clone() });
+                            }
+                        }
+                    }
+                }
+                let duplicate = ancestors.iter().any(|a| tree.nodes[*a].marking == marking);
+                tree.nodes.push(CoverabilityNode { marking, parent: Some(node), transition: Some(transition), duplicate, pumps });
+                stack.push(child);
+            }
+        }
+        tree
+    }
+
+    // The node itself and its ancestors, up to the root
+    fn ancestors(&self, node: usize) -> Vec<usize> {
+        let mut ancestors = vec![node];
+        while let Some(parent) = self.nodes[*ancestors.last().unwrap()].parent {
+            ancestors.push(parent);
+        }
+        ancestors
+    }
+
+    /// The unfolded transitions fired from the root to reach a node.
+    pub fn path(&self, node: usize) -> Vec<usize> {
+        self.ancestors(node).into_iter().rev().filter_map(|n| self.nodes[n].transition).collect()
+    }
+
+    /// The largest count of an unfolded place over all reachable markings.
+    pub fn bound(&self, place: usize) -> Count {
+        self.nodes.iter().map(|n| n.marking[place]).max().unwrap_or(Count::Finite(0))
+    }
+
+    /// The largest number of tokens, of any clade, a colored place can hold.
+    pub fn place_bound(&self, place: &Uuid) -> Count {
+        let rows = self.net.places_of(place);
+        self.nodes
+            .iter()
+            .map(|n| rows.iter().fold(Count::Finite(0), |sum, r| sum + n.marking[*r]))
+            .max()
+            .unwrap_or(Count::Finite(0))
+    }
+
+    /// Counts per clade of each colored place at a node.
+    pub fn project(&self, node: usize) -> HashMap<Uuid, HashMap<Uuid, Count>> {
+        let mut projected: HashMap<Uuid, HashMap<Uuid, Count>> = HashMap::new();
+        for (place, count) in self.net.places.iter().zip(&self.nodes[node].marking) {
+            if *count != Count::Finite(0) {
+                projected.entry(place.place).or_default().insert(place.clade.id(), *count);
+            }
+        }
+        projected
+    }
+
+    /// Colored places that can hold unboundedly many tokens.
+    pub fn unbounded_places(&self) -> Vec<Uuid> {
+        self.pumps.iter().map(|p| self.net.places[p.place].place).unique().sorted().collect()
+    }
+
+    /// The first pump found for each unbounded unfolded place.
+    pub fn unbounded(&self) -> Vec<&Pump> {
+        self.pumps.iter().unique_by(|p| p.place).sorted_by_key(|p| p.place).collect()
+    }
+
+    /// Names the transitions of a firing sequence, e.g. `start[x=robot1] stop[x=robot1]`.
+    pub fn describe(&self, sequence: &[usize]) -> String {
+        sequence.iter().map(|t| self.net.transitions[*t].name.clone()).join(" ")
+    }
+}
+
+#[test]
+pub fn karp_miller() {
+    use std::collections::{BTreeMap, HashSet};
+    use crate::clade::Clade;
+    use crate::place::Place;
+    use crate::signature::Signature;
+    use crate::statespace::StateSpace;
+    use crate::token::Token;
+    use crate::transition::Transition;
+    use crate::unfold::{PtPlace, PtTransition};
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    net.add_clade(Clade::new("robot".into(), Some(vec![robot1.clone()])));
+    let idle = net.add_place(Place::new("idle".into()));
+    let busy = net.add_place(Place::new("busy".into()));
+    let log = net.add_place(Place::new("log".into()));
+    net.add_token(idle, Token::new("r1".into(), robot1.clone()));
+    let sig = || Signature::new(HashSet::from(["x".into()]));
+    net.add_transition(Transition::new("start".into(), Some(HashMap::from([(idle, sig())])), Some(HashMap::from([(busy, sig())])), None, None));
+    let stop = net.add_transition(Transition::new(
+        "stop".into(),
+        Some(HashMap::from([(busy, sig())])),
+        Some(HashMap::from([(idle, sig()), (log, sig())])),
+        None,
+        None,
+    ));
+
+    // Stopping puts the robot in the log while it also goes back to idle; the log holds it
+    // once however often it stops, but the unfolded net would count it every time
+    assert_eq!(CoverabilityTree::build(&net, None).map_err(|e| e.transitions), Err(vec![stop]));
+
+    // Without the copy, the tree agrees with the state space
+    net.transitions.get_mut(&stop).unwrap().output.remove(&log);
+    let tree = CoverabilityTree::build(&net, None).unwrap();
+    assert!(!tree.truncated);
+    assert!(tree.unbounded_places().is_empty());
+    let space = StateSpace::explore(&net, None);
+    for place in [idle, busy, log] {
+        let most = space.markings.iter().map(|m| m.count(&place) as u64).max().unwrap();
+        assert_eq!(tree.place_bound(&place), Count::Finite(most));
+    }
+
+    // A place/transition net where a keeps producing b, which is moved on to c
+    let place = |name: &str| PtPlace { name: name.into(), place: Uuid::new_v4(), clade: robot1.clone() };
+    let transition = |name: &str, input: &[usize], output: &[usize]| PtTransition {
+        name: name.into(),
+        transition: Uuid::new_v4(),
+        assignment: BTreeMap::new(),
+        input: input.iter().map(|p| (*p, 1)).collect(),
+        output: output.iter().map(|p| (*p, 1)).collect(),
+    };
+    let pt = PtNet {
+        name: "producer".into(),
+        places: vec![place("a"), place("b"), place("c")],
+        transitions: vec![transition("produce", &[0], &[0, 1]), transition("move", &[1], &[2])],
+        initial_marking: vec![1, 0, 0],
+    };
+    let tree = CoverabilityTree::from_pt(pt, None);
+    assert_eq!((0..3).map(|p| tree.bound(p)).collect_vec(), [Count::Finite(1), Count::Omega, Count::Omega]);
+    let pumps = tree.unbounded();
+    assert_eq!(pumps.len(), 2);
+    assert_eq!((tree.describe(&pumps[0].prefix), tree.describe(&pumps[0].cycle)), ("".into(), "produce".into()));
+    assert!(pumps[0].requires.is_empty());
+    // Moving b on only grows c once b has been pumped, which the pump records
+    assert_eq!((tree.describe(&pumps[1].prefix), tree.describe(&pumps[1].cycle)), ("produce".into(), "move".into()));
+    assert!(!pumps[1].requires.is_empty() && pumps[1].requires.iter().all(|r| tree.pumps[*r].place == 1));
+}
@@ -0,0 +1,300 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use itertools::Itertools;
+use uuid::Uuid;
+use crate::binding::Binding;
+use crate::marking::Marking;
+use crate::net::ColoredPetriNet;
+use crate::symbol::Symbol;
+use crate::token::Token;
+use crate::transition::{FiringError, Transition};
+
+// Beyond this many assignments of clades to the symbols of a guard, tokens are not prefiltered
+const MAX_ASSIGNMENTS: usize = 4096;
+
+/// Keeps track of the bindings of every transition of a net as its marking changes, giving
+/// the same answers as [`ColoredPetriNet::enabled`] without enumerating every binding of every
+/// transition after each firing.
+///
+/// Only the transitions that look at a place whose tokens changed are checked again: those with
+/// an input, read or inhibitor arc from it, or an output arc into it if it has a capacity. The
+/// tokens of each place are indexed by clade, so that a symbol is only tried with tokens of the
+/// clades its guard can accept. The transitions of the net must not change while it is tracked.
+#[derive(Clone, Debug)]
+pub struct Enabling {
+    net: Uuid,
+    // The marking the bindings are for
+    marking: Marking,
+    // Tokens of each place by clade
+    index: HashMap<Uuid, BTreeMap<Uuid, Vec<Token>>>,
+    // Bindings with which each transition can fire, priorities aside
+    bindings: HashMap<Uuid, Vec<Binding>>,
+    // Transitions to check again when the tokens of a place change
+    watchers: HashMap<Uuid, Vec<Uuid>>,
+    // Clades each symbol of a transition's guard can be bound to, when known
+    accepted: HashMap<Uuid, HashMap<Symbol, HashSet<Uuid>>>,
+    // Clades of the tokens the analysis of guards took into account
+    clades: HashSet<Uuid>,
+    // Transitions with their priority, by decreasing priority, then name
+    order: Vec<(i32, Uuid)>,
+    // How many times the bindings of a transition were enumerated
+    pub checks: usize,
+}
+
+impl Enabling {
+    /// Starts tracking the current marking of the net.
+    pub fn new(net: &ColoredPetriNet) -> Self {
+        let universe = net
+            .current_marking
+            .values()
+            .flat_map(|tokens| tokens.values())
+            .map(|t| t.clade.clone())
+            .unique_by(|c| c.id())
+            .sorted_by_key(|c| (c.name(), c.id()))
+            .collect_vec();
+        let mut watchers: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+        for transition in net.transitions.values().sorted_by_key(|t| t.id) {
+            let capacities = transition.output.keys().filter(|p| net.places.get(p).is_some_and(|p| p.capacity.is_some()));
+            let places = transition.input.keys().chain(transition.reads.keys()).chain(transition.inhibitors.keys()).chain(capacities);
+            for place in places.unique() {
+                watchers.entry(*place).or_default().push(transition.id);
+            }
+        }
+        let accepted = net
+            .transitions
+            .values()
+            .map(|t| (t.id, t.guard.satisfying_clades(&universe, MAX_ASSIGNMENTS).unwrap_or_default()))
+            .collect();
+        let order = net
+            .transitions
+            .values()
+            .sorted_by_key(|t| (std::cmp::Reverse(t.priority), t.name.clone(), t.id))
+            .map(|t| (t.priority, t.id))
+            .collect();
+        let mut enabling = Self {
+            net: net.id,
+            marking: net.current_marking.clone(),
+            index: HashMap::new(),
+            bindings: HashMap::new(),
+            watchers,
+            accepted,
+            clades: universe.iter().map(|c| c.id()).collect(),
+            order,
+            checks: 0,
+        };
+        for place in net.current_marking.places() {
+            enabling.reindex(&place);
+        }
+        for transition in net.transitions.values() {
+            let bindings = enabling.enumerate(net, transition);
+            enabling.bindings.insert(transition.id, bindings);
+        }
+        enabling
+    }
+
+    /// The enabled transitions of the tracked marking with each of their bindings, ordered as
+    /// [`ColoredPetriNet::enabled_in`] orders them.
+    pub fn enabled(&self) -> Vec<(Uuid, Binding)> {
+        let Some((priority, _)) = self.order.iter().find(|(_, t)| !self.bindings[t].is_empty()) else {
+            return vec![];
+        };
+        self.order
+            .iter()
+            .filter(|(p, _)| p == priority)
+            .flat_map(|(_, t)| self.bindings[t].iter().map(move |b| (*t, b.clone())))
+            .collect()
+    }
+
+    /// Fires a transition of the net with a binding, as [`ColoredPetriNet::fire`] does, and
+    /// brings the tracked bindings up to date.
+    pub fn fire(&mut self, net: &mut ColoredPetriNet, transition: &Uuid, binding: &Binding) -> Result<(), FiringError> {
+        self.sync(net);
+        let fired = net.transitions.get(transition).ok_or(FiringError::UnknownTransition(*transition))?;
+        let next = net.successor(fired, &net.current_marking, binding)?;
+        let higher = self.order.iter().take_while(|(p, _)| *p > fired.priority);
+        if let Some((_, higher)) = higher.into_iter().find(|(_, t)| !self.bindings[t].is_empty()) {
+            return Err(FiringError::Preempted(*higher));
+        }
+        let changed = fired.input.keys().chain(fired.output.keys()).chain(fired.resets.iter()).copied().unique().collect_vec();
+        net.current_marking = next;
+        self.update(net, changed);
+        Ok(())
+    }
+
+    /// Catches up with a marking of the net set by other means than [`Enabling::fire`], such as
+    /// undoing a firing or resetting the net, checking again only what changed.
+    pub fn sync(&mut self, net: &ColoredPetriNet) {
+        if net.id != self.net || net.transitions.len() != self.bindings.len() {
+            self.restart(net);
+            return;
+        }
+        if net.current_marking == self.marking {
+            return;
+        }
+        let empty = HashMap::new();
+        let changed = self
+            .marking
+            .places()
+            .into_iter()
+            .chain(net.current_marking.places())
+            .unique()
+            .filter(|p| self.marking.get(p).unwrap_or(&empty) != net.current_marking.get(p).unwrap_or(&empty))
+            .collect_vec();
+        self.update(net, changed);
+    }
+
+    fn update(&mut self, net: &ColoredPetriNet, changed: Vec<Uuid>) {
+        self.marking = net.current_marking.clone();
+        // Guards were only analyzed for the clades there were, so a new one calls for a fresh start
+        let new_clade = changed.iter().flat_map(|p| net.current_marking.tokens(p)).any(|t| !self.clades.contains(&t.clade.id()));
+        if new_clade {
+            self.restart(net);
+            return;
+        }
+        for place in &changed {
+            self.reindex(place);
+        }
+        let stale = changed.iter().flat_map(|p| self.watchers.get(p).into_iter().flatten()).copied().unique().collect_vec();
+        for transition in stale {
+            let bindings = self.enumerate(net, &net.transitions[&transition]);
+            self.bindings.insert(transition, bindings);
+        }
+    }
+
+    fn restart(&mut self, net: &ColoredPetriNet) {
+        let checks = self.checks;
+        *self = Self::new(net);
+        self.checks += checks;
+    }
+
+    fn reindex(&mut self, place: &Uuid) {
+        let mut by_clade: BTreeMap<Uuid, Vec<Token>> = BTreeMap::new();
+        for token in self.marking.tokens(place) {
+            by_clade.entry(token.clade.id()).or_default().push(token.clone());
+        }
+        if by_clade.is_empty() {
+            self.index.remove(place);
+        } else {
+            self.index.insert(*place, by_clade);
+        }
+    }
+
+    // The bindings of a transition in the tracked marking, in the order and with the checks of
+    // ColoredPetriNet::bindings
+    fn enumerate(&mut self, net: &ColoredPetriNet, transition: &Transition) -> Vec<Binding> {
+        self.checks += 1;
+        if transition.substitution.is_some() || transition.inhibited(&self.marking).is_some() {
+            return vec![];
+        }
+        let accepted = &self.accepted[&transition.id];
+        let mut symbols_by_place: BTreeMap<Uuid, Vec<&Symbol>> = BTreeMap::new();
+        for (place, signature) in transition.input.iter().chain(transition.reads.iter()) {
+            symbols_by_place.entry(*place).or_default().extend(signature.symbols.iter());
+        }
+        let per_place = symbols_by_place
+            .into_iter()
+            .map(|(place, symbols)| {
+                let symbols = symbols.into_iter().sorted().collect_vec();
+                // Tokens that no symbol could be bound to cannot be part of a binding
+                let wanted = |clade: &Uuid| symbols.iter().any(|s| accepted.get(*s).is_none_or(|a| a.contains(clade)));
+                let tokens = self
+                    .index
+                    .get(&place)
+                    .into_iter()
+                    .flatten()
+                    .filter(|(clade, _)| wanted(clade))
+                    .flat_map(|(_, tokens)| tokens)
+                    .sorted_by_key(|t| (t.name.clone(), t.id))
+                    .collect_vec();
+                tokens
+                    .into_iter()
+                    .permutations(symbols.len())
+                    .map(|chosen| symbols.iter().zip(chosen).map(|(symbol, token)| ((*symbol).clone(), (place, token.clone()))).collect_vec())
+                    .collect_vec()
+            })
+            .collect_vec();
+        let bindings = if per_place.is_empty() {
+            vec![Binding::default()]
+        } else {
+            per_place
+                .into_iter()
+                .multi_cartesian_product()
+                .map(|assignments| Binding::new(assignments.into_iter().flatten().collect()))
+                .collect()
+        };
+        bindings
+            .into_iter()
+            .filter(|binding| transition.guard.eval(&binding.clades()))
+            .filter(|binding| net.successor(transition, &self.marking, binding).is_ok())
+            .collect()
+    }
+}
+
+
+#[test]
+pub fn incremental_enabling() {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use crate::clade::Clade;
+    use crate::guard::Guard;
+    use crate::place::{Capacity, Place};
+    use crate::signature::Signature;
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let robot2 = Clade::new("robot2".into(), None);
+    let mut net = ColoredPetriNet::new("plant".into(), None, None, None);
+    net.add_clade(Clade::new("robot".into(), Some(vec![robot1.clone(), robot2.clone()])));
+    let sig = |symbols: &[&str]| Signature::new(symbols.iter().map(|s| Symbol::new(s.to_string())).collect());
+    let arc = |place: Uuid, symbols: &[&str]| Some(HashMap::from([(place, sig(symbols))]));
+
+    // Cells that robots of either kind cycle through on their own
+    let mut cells = vec![];
+    for i in 0..6 {
+        let idle = net.add_place(Place::new(format!("idle{}", i)));
+        let busy = net.add_place(Place::new(format!("busy{}", i)));
+        net.add_token(idle, Token::new(format!("r{}", i), if i % 2 == 0 { robot1.clone() } else { robot2.clone() }));
+        cells.push(net.add_transition(Transition::new(format!("start{}", i), arc(idle, &["x"]), arc(busy, &["x"]), None, None)));
+        net.add_transition(Transition::new(format!("stop{}", i), arc(busy, &["x"]), arc(idle, &["x"]), None, None));
+    }
+    // A shared pool feeding a bounded buffer, with guards picking pairs of robots by kind
+    let pool = net.add_place(Place::new("pool".into()));
+    let buffer = net.add_place(Place::new("buffer".into()).with_capacity(Capacity::Total(2)));
+    let alarm = net.add_place(Place::new("alarm".into()));
+    for i in 0..3 {
+        net.add_token(pool, Token::new(format!("a{}", i), robot1.clone()));
+        net.add_token(pool, Token::new(format!("b{}", i), robot2.clone()));
+    }
+    let pair = Guard::All(vec![Guard::Is("x".into(), robot1.clone()), Guard::Not("y".into(), robot1.clone())]);
+    net.add_transition(Transition::new("pair".into(), arc(pool, &["x", "y"]), arc(buffer, &["x", "y"]), None, None).with_guard(pair));
+    net.add_transition(Transition::new("drain".into(), arc(buffer, &["x"]), arc(pool, &["x"]), None, None));
+    net.add_transition(Transition::new("ring".into(), arc(alarm, &["s"]), arc(pool, &["s"]), None, None).with_inhibitor(buffer, None).with_priority(1));
+
+    let mut enabling = Enabling::new(&net);
+    assert_eq!(enabling.enabled(), net.enabled());
+    let mut rng = StdRng::seed_from_u64(11);
+    for _ in 0..200 {
+        let enabled = enabling.enabled();
+        assert_eq!(enabled, net.enabled());
+        let (transition, binding) = enabled[rng.gen_range(0..enabled.len())].clone();
+        enabling.fire(&mut net, &transition, &binding).unwrap();
+    }
+
+    // Firing in one cell checks only the transitions of that cell again
+    net.reset();
+    enabling.sync(&net);
+    assert_eq!(enabling.enabled(), net.enabled());
+    let checks = enabling.checks;
+    let binding = enabling.bindings[&cells[3]][0].clone();
+    enabling.fire(&mut net, &cells[3], &binding).unwrap();
+    assert_eq!(enabling.checks - checks, 2);
+    assert_eq!(enabling.enabled(), net.enabled());
+
+    // The alarm preempts everything until the buffer is empty
+    net.add_token(alarm, Token::new("siren".into(), robot2.clone()));
+    enabling.sync(&net);
+    let ring = net.id_of("ring").unwrap();
+    assert_eq!(enabling.enabled().iter().map(|(t, _)| *t).unique().collect_vec(), vec![ring]);
+    let stop = net.id_of("stop3").unwrap();
+    let binding = enabling.bindings[&stop][0].clone();
+    assert_eq!(enabling.fire(&mut net, &stop, &binding), Err(FiringError::Preempted(ring)));
+    assert_eq!(enabling.enabled(), net.enabled());
+}
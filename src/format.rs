@@ -0,0 +1,973 @@
+//! The file format for saved nets.
+//!
+//! A file is a JSON object holding the version of the format and the net:
+//!
+//! ```json
+//! { "formatVersion": 1, "net": { "id": "…", "name": "cell", "clades": [], "places": [], … } }
+//! ```
+//!
+//! The format is written from types of its own rather than derived from the types of the crate,
+//! so that it only changes on purpose. Every change bumps [`FORMAT_VERSION`] and adds a
+//! migration from the previous version, which [`from_json`] applies to older files. Files saved
+//! before the format was versioned, with the serialization [`ColoredPetriNet`] derived then, are
+//! read as version 0, whose shape is likewise kept in types of its own. [`schema`] describes the
+//! current version as a JSON Schema.
+//!
+//! In version 1, all keys are camelCase, elements are listed in arrays rather than maps, and
+//! clades are written once, in the `clades` taxonomies, and referred to by id everywhere else.
+//! Clades used by the net but in none of its taxonomies are written to `otherClades`. Transitions
+//! are listed in declaration order, and guards are objects tagged by their `op`.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use crate::clade::Clade;
+use crate::function::Function;
+use crate::guard::Guard;
+use crate::hierarchy::Substitution;
+use crate::marking::Marking;
+use crate::net::ColoredPetriNet;
+use crate::place::{Capacity, Place, Port};
+use crate::signature::Signature;
+use crate::symbol::Symbol;
+use crate::token::Token;
+use crate::transition::Transition;
+
+/// The version of the file format that [`to_json`] writes.
+pub const FORMAT_VERSION: u64 = 1;
+
+// Migrations, each taking a file from the version of its index to the next
+const MIGRATIONS: [fn(Value) -> Result<Value, FormatError>; FORMAT_VERSION as usize] = [from_unversioned];
+
+/// Reasons a file cannot be read as a net.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FormatError {
+    // Not JSON, or not shaped as the format requires
+    Json(String),
+    // Written by a newer version of the crate
+    UnsupportedVersion(u64),
+    UnknownClade(Uuid),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Json(message) => write!(f, "{}", message),
+            FormatError::UnsupportedVersion(version) => {
+                write!(f, "format version {} is newer than the supported version {}", version, FORMAT_VERSION)
+            }
+            FormatError::UnknownClade(clade) => write!(f, "clade {} is not defined in the file", clade),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl From<serde_json::Error> for FormatError {
+    fn from(error: serde_json::Error) -> Self {
+        FormatError::Json(error.to_string())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct File {
+    format_version: u64,
+    net: NetDoc,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct NetDoc {
+    id: Uuid,
+    name: String,
+    clades: Vec<CladeDoc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    other_clades: Vec<CladeDoc>,
+    places: Vec<PlaceDoc>,
+    transitions: Vec<TransitionDoc>,
+    initial_marking: Vec<TokensDoc>,
+    current_marking: Vec<TokensDoc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    subnets: Vec<NetDoc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct CladeDoc {
+    id: Uuid,
+    name: String,
+    // Absent for leaves
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    children: Option<Vec<CladeDoc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct PlaceDoc {
+    id: Uuid,
+    name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    colors: Vec<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    capacity: Option<CapacityDoc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    port: Option<Port>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+enum CapacityDoc {
+    Total(usize),
+    PerClade(Vec<CladeLimitDoc>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct CladeLimitDoc {
+    clade: Uuid,
+    limit: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct TransitionDoc {
+    id: Uuid,
+    name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    input: Vec<ArcDoc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    output: Vec<ArcDoc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    reads: Vec<ArcDoc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    inhibitors: Vec<InhibitorDoc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    resets: Vec<Uuid>,
+    guard: GuardDoc,
+    function: Uuid,
+    #[serde(default)]
+    priority: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    substitution: Option<SubstitutionDoc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct ArcDoc {
+    place: Uuid,
+    symbols: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct InhibitorDoc {
+    place: Uuid,
+    // Any token inhibits when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clade: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct SubstitutionDoc {
+    subnet: Uuid,
+    ports: Vec<PortBindingDoc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct PortBindingDoc {
+    port: Uuid,
+    socket: Uuid,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase", deny_unknown_fields)]
+enum GuardDoc {
+    Is { symbol: String, clade: Uuid },
+    IsNot { symbol: String, clade: Uuid },
+    LessThan { symbol: String, clade: Uuid },
+    LessThanOrEqual { symbol: String, clade: Uuid },
+    GreaterThan { symbol: String, clade: Uuid },
+    GreaterThanOrEqual { symbol: String, clade: Uuid },
+    All { guards: Vec<GuardDoc> },
+    Any { guards: Vec<GuardDoc> },
+    None { guards: Vec<GuardDoc> },
+    True,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct TokensDoc {
+    place: Uuid,
+    tokens: Vec<TokenDoc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct TokenDoc {
+    id: Uuid,
+    name: String,
+    clade: Uuid,
+}
+
+/// Writes a net in the current version of the file format.
+pub fn to_json(net: &ColoredPetriNet) -> String {
+    let file = File { format_version: FORMAT_VERSION, net: write_net(net) };
+    serde_json::to_string_pretty(&file).expect("the file format serializes to JSON")
+}
+
+/// Reads a net from a file of the current or an older version of the format.
+pub fn from_json(text: &str) -> Result<ColoredPetriNet, FormatError> {
+    let file: File = serde_json::from_value(migrate(serde_json::from_str(text)?)?)?;
+    read_net(file.net)
+}
+
+/// Brings a file of any supported version up to the current version.
+pub fn migrate(mut file: Value) -> Result<Value, FormatError> {
+    let version = match file.get("formatVersion") {
+        Some(version) => version.as_u64().ok_or_else(|| FormatError::Json("formatVersion is not a number".into()))?,
+        None => 0,
+    };
+    if version > FORMAT_VERSION {
+        return Err(FormatError::UnsupportedVersion(version));
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        file = migration(file)?;
+    }
+    Ok(file)
+}
+
+// Version 0 is the serialization the net derived before files were versioned, as it was then
+#[derive(Debug, Deserialize)]
+struct NetDocV0 {
+    id: Uuid,
+    name: String,
+    places: HashMap<Uuid, PlaceDocV0>,
+    transitions: HashMap<Uuid, TransitionDocV0>,
+    initial_marking: HashMap<Uuid, TokensDocV0>,
+    current_marking: HashMap<Uuid, TokensDocV0>,
+    #[serde(default)]
+    clades: Vec<CladeDocV0>,
+    #[serde(default)]
+    transition_order: Vec<Uuid>,
+    #[serde(default)]
+    subnets: HashMap<Uuid, NetDocV0>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+enum CladeDocV0 {
+    Branch { uuid: Uuid, name: String, children: Vec<CladeDocV0> },
+    Leaf { uuid: Uuid, name: String },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlaceDocV0 {
+    id: Uuid,
+    name: String,
+    #[serde(default)]
+    colors: Vec<CladeDocV0>,
+    #[serde(default)]
+    capacity: Option<CapacityDocV0>,
+    #[serde(default)]
+    port: Option<Port>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum CapacityDocV0 {
+    Total(usize),
+    PerClade(Vec<(CladeDocV0, usize)>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransitionDocV0 {
+    id: Uuid,
+    name: String,
+    input: HashMap<Uuid, SignatureDocV0>,
+    output: HashMap<Uuid, SignatureDocV0>,
+    guard: GuardDocV0,
+    function: FunctionDocV0,
+    #[serde(default)]
+    reads: HashMap<Uuid, SignatureDocV0>,
+    #[serde(default)]
+    inhibitors: HashMap<Uuid, Option<CladeDocV0>>,
+    #[serde(default)]
+    resets: Vec<Uuid>,
+    #[serde(default)]
+    priority: i32,
+    #[serde(default)]
+    substitution: Option<SubstitutionDocV0>,
+    #[serde(default)]
+    label: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignatureDocV0 {
+    symbols: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FunctionDocV0 {
+    id: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubstitutionDocV0 {
+    subnet: Uuid,
+    ports: HashMap<Uuid, Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+enum GuardDocV0 {
+    Is(String, CladeDocV0),
+    GreaterThan(String, CladeDocV0),
+    LessThan(String, CladeDocV0),
+    GreaterThanOrEqual(String, CladeDocV0),
+    LessThanOrEqual(String, CladeDocV0),
+    Not(String, CladeDocV0),
+    All(Vec<GuardDocV0>),
+    Any(Vec<GuardDocV0>),
+    None(Vec<GuardDocV0>),
+    Empty,
+}
+
+// The tokens of a place, listed or, in the oldest files, by id
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TokensDocV0 {
+    List(Vec<TokenDocV0>),
+    Map(HashMap<Uuid, TokenDocV0>),
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenDocV0 {
+    id: Uuid,
+    name: String,
+    clade: CladeDocV0,
+}
+
+impl CladeDocV0 {
+    fn id(&self) -> Uuid {
+        match self {
+            CladeDocV0::Branch { uuid, .. } | CladeDocV0::Leaf { uuid, .. } => *uuid,
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            CladeDocV0::Branch { name, .. } | CladeDocV0::Leaf { name, .. } => name,
+        }
+    }
+
+    // Ids of the clade and all of its descendants
+    fn ids(&self) -> Vec<Uuid> {
+        match self {
+            CladeDocV0::Branch { uuid, children, .. } => [*uuid].into_iter().chain(children.iter().flat_map(|c| c.ids())).collect(),
+            CladeDocV0::Leaf { uuid, .. } => vec![*uuid],
+        }
+    }
+
+    fn migrate(&self) -> CladeDoc {
+        match self {
+            CladeDocV0::Branch { uuid, name, children } => {
+                CladeDoc { id: *uuid, name: name.clone(), children: Some(children.iter().map(|c| c.migrate()).collect()) }
+            }
+            CladeDocV0::Leaf { uuid, name } => CladeDoc { id: *uuid, name: name.clone(), children: None },
+        }
+    }
+}
+
+// Clades are written in place in version 0; `used` collects them for `otherClades`
+fn migrate_guard(guard: GuardDocV0, used: &mut Vec<CladeDocV0>) -> GuardDoc {
+    let mut refer = |clade: CladeDocV0| {
+        let id = clade.id();
+        used.push(clade);
+        id
+    };
+    match guard {
+        GuardDocV0::Is(symbol, c) => GuardDoc::Is { symbol, clade: refer(c) },
+        GuardDocV0::Not(symbol, c) => GuardDoc::IsNot { symbol, clade: refer(c) },
+        GuardDocV0::LessThan(symbol, c) => GuardDoc::LessThan { symbol, clade: refer(c) },
+        GuardDocV0::LessThanOrEqual(symbol, c) => GuardDoc::LessThanOrEqual { symbol, clade: refer(c) },
+        GuardDocV0::GreaterThan(symbol, c) => GuardDoc::GreaterThan { symbol, clade: refer(c) },
+        GuardDocV0::GreaterThanOrEqual(symbol, c) => GuardDoc::GreaterThanOrEqual { symbol, clade: refer(c) },
+        GuardDocV0::All(guards) => GuardDoc::All { guards: guards.into_iter().map(|g| migrate_guard(g, used)).collect() },
+        GuardDocV0::Any(guards) => GuardDoc::Any { guards: guards.into_iter().map(|g| migrate_guard(g, used)).collect() },
+        GuardDocV0::None(guards) => GuardDoc::None { guards: guards.into_iter().map(|g| migrate_guard(g, used)).collect() },
+        GuardDocV0::Empty => GuardDoc::True,
+    }
+}
+
+fn migrate_arcs(arcs: HashMap<Uuid, SignatureDocV0>) -> Vec<ArcDoc> {
+    arcs.into_iter()
+        .sorted_by_key(|(place, _)| *place)
+        .map(|(place, signature)| ArcDoc { place, symbols: signature.symbols.into_iter().sorted().collect() })
+        .collect()
+}
+
+fn migrate_marking(marking: HashMap<Uuid, TokensDocV0>, used: &mut Vec<CladeDocV0>) -> Vec<TokensDoc> {
+    let mut docs = vec![];
+    for (place, tokens) in marking.into_iter().sorted_by_key(|(place, _)| *place) {
+        let tokens = match tokens {
+            TokensDocV0::List(tokens) => tokens,
+            TokensDocV0::Map(tokens) => tokens.into_values().collect(),
+        };
+        if tokens.is_empty() {
+            continue;
+        }
+        let tokens = tokens
+            .into_iter()
+            .sorted_by_key(|t| t.id)
+            .map(|t| {
+                let clade = t.clade.id();
+                used.push(t.clade);
+                TokenDoc { id: t.id, name: t.name, clade }
+            })
+            .collect();
+        docs.push(TokensDoc { place, tokens });
+    }
+    docs
+}
+
+fn migrate_net(mut net: NetDocV0) -> NetDoc {
+    let mut used = vec![];
+    let mut places = vec![];
+    for p in net.places.into_values().sorted_by_key(|p| (p.name.clone(), p.id)) {
+        let colors = p.colors.iter().map(|c| c.id()).collect();
+        used.extend(p.colors);
+        let capacity = p.capacity.map(|capacity| match capacity {
+            CapacityDocV0::Total(limit) => CapacityDoc::Total(limit),
+            CapacityDocV0::PerClade(limits) => CapacityDoc::PerClade(
+                limits
+                    .into_iter()
+                    .map(|(c, limit)| {
+                        let clade = c.id();
+                        used.push(c);
+                        CladeLimitDoc { clade, limit }
+                    })
+                    .collect(),
+            ),
+        });
+        places.push(PlaceDoc { id: p.id, name: p.name, colors, capacity, port: p.port });
+    }
+
+    // Declared transitions first, then the rest by name, as `write_net` lists them
+    let declared = net.transition_order.iter().filter_map(|id| net.transitions.remove(id)).collect_vec();
+    let rest = net.transitions.into_values().sorted_by_key(|t| (t.name.clone(), t.id));
+    let mut transitions = vec![];
+    for t in declared.into_iter().chain(rest) {
+        let mut inhibitors = vec![];
+        for (place, clade) in t.inhibitors.into_iter().sorted_by_key(|(place, _)| *place) {
+            inhibitors.push(InhibitorDoc { place, clade: clade.as_ref().map(|c| c.id()) });
+            used.extend(clade);
+        }
+        transitions.push(TransitionDoc {
+            id: t.id,
+            name: t.name,
+            input: migrate_arcs(t.input),
+            output: migrate_arcs(t.output),
+            reads: migrate_arcs(t.reads),
+            inhibitors,
+            resets: t.resets.into_iter().sorted().dedup().collect(),
+            guard: migrate_guard(t.guard, &mut used),
+            function: t.function.id,
+            priority: t.priority,
+            substitution: t.substitution.map(|s| SubstitutionDoc {
+                subnet: s.subnet,
+                ports: s.ports.into_iter().sorted().map(|(port, socket)| PortBindingDoc { port, socket }).collect(),
+            }),
+            label: t.label,
+        });
+    }
+    let initial_marking = migrate_marking(net.initial_marking, &mut used);
+    let current_marking = migrate_marking(net.current_marking, &mut used);
+
+    // Clades in use that no taxonomy of the net defines, without those another one contains
+    let defined: HashSet<Uuid> = net.clades.iter().flat_map(|c| c.ids()).collect();
+    let mut other: Vec<CladeDocV0> = vec![];
+    for clade in used.into_iter().sorted_by_key(|c| (c.name().to_string(), c.id())) {
+        if !defined.contains(&clade.id()) && !other.iter().any(|o| o.ids().contains(&clade.id())) {
+            other.retain(|o| !clade.ids().contains(&o.id()));
+            other.push(clade);
+        }
+    }
+    NetDoc {
+        id: net.id,
+        name: net.name,
+        clades: net.clades.iter().map(|c| c.migrate()).collect(),
+        other_clades: other.iter().map(|c| c.migrate()).collect(),
+        places,
+        transitions,
+        initial_marking,
+        current_marking,
+        subnets: net.subnets.into_values().sorted_by_key(|n| (n.name.clone(), n.id)).map(migrate_net).collect(),
+    }
+}
+
+fn from_unversioned(file: Value) -> Result<Value, FormatError> {
+    let net: NetDocV0 = serde_json::from_value(file)?;
+    Ok(serde_json::to_value(File { format_version: 1, net: migrate_net(net) })?)
+}
+
+fn guard_clades(guard: &Guard) -> Vec<Clade> {
+    match guard {
+        Guard::Is(_, c) | Guard::Not(_, c) | Guard::LessThan(_, c) | Guard::LessThanOrEqual(_, c) => vec![c.clone()],
+        Guard::GreaterThan(_, c) | Guard::GreaterThanOrEqual(_, c) => vec![c.clone()],
+        Guard::All(guards) | Guard::Any(guards) | Guard::None(guards) => guards.iter().flat_map(guard_clades).collect(),
+        Guard::Empty => vec![],
+    }
+}
+
+fn write_clade(clade: &Clade) -> CladeDoc {
+    CladeDoc { id: clade.id(), name: clade.name(), children: clade.children().map(|c| c.iter().map(write_clade).collect()) }
+}
+
+fn write_guard(guard: &Guard) -> GuardDoc {
+    let list = |guards: &Vec<Guard>| guards.iter().map(write_guard).collect();
+    match guard {
+        Guard::Is(s, c) => GuardDoc::Is { symbol: s.to_string(), clade: c.id() },
+        Guard::Not(s, c) => GuardDoc::IsNot { symbol: s.to_string(), clade: c.id() },
+        Guard::LessThan(s, c) => GuardDoc::LessThan { symbol: s.to_string(), clade: c.id() },
+        Guard::LessThanOrEqual(s, c) => GuardDoc::LessThanOrEqual { symbol: s.to_string(), clade: c.id() },
+        Guard::GreaterThan(s, c) => GuardDoc::GreaterThan { symbol: s.to_string(), clade: c.id() },
+        Guard::GreaterThanOrEqual(s, c) => GuardDoc::GreaterThanOrEqual { symbol: s.to_string(), clade: c.id() },
+        Guard::All(guards) => GuardDoc::All { guards: list(guards) },
+        Guard::Any(guards) => GuardDoc::Any { guards: list(guards) },
+        Guard::None(guards) => GuardDoc::None { guards: list(guards) },
+        Guard::Empty => GuardDoc::True,
+    }
+}
+
+fn write_arcs(arcs: &HashMap<Uuid, Signature>) -> Vec<ArcDoc> {
+    arcs.iter()
+        .sorted_by_key(|(place, _)| **place)
+        .map(|(place, signature)| ArcDoc { place: *place, symbols: signature.symbols.iter().map(|s| s.to_string()).sorted().collect() })
+        .collect()
+}
+
+fn write_marking(marking: &Marking) -> Vec<TokensDoc> {
+    marking
+        .places()
+        .into_iter()
+        .sorted()
+        .map(|place| TokensDoc {
+            place,
+            tokens: marking.tokens(&place).into_iter().map(|t| TokenDoc { id: t.id, name: t.name.clone(), clade: t.clade.id() }).collect(),
+        })
+        .collect()
+}
+
+fn write_net(net: &ColoredPetriNet) -> NetDoc {
+    // Clades in use that no taxonomy of the net defines
+    let defined: HashSet<Uuid> = net.clades.iter().flat_map(|c| c.flatten()).map(|c| c.id()).collect();
+    let tokens = net.initial_marking.values().chain(net.current_marking.values()).flat_map(|t| t.values()).map(|t| t.clade.clone());
+    let places = net.places.values().flat_map(|p| {
+        let limits = match &p.capacity {
+            Some(Capacity::PerClade(limits)) => limits.iter().map(|(c, _)| c.clone()).collect(),
+            _ => vec![],
+        };
+        p.colors.iter().cloned().chain(limits)
+    });
+    let transitions = net.transitions.values().flat_map(|t| guard_clades(&t.guard).into_iter().chain(t.inhibitors.values().flatten().cloned()));
+    let mut other: Vec<Clade> = vec![];
+    for clade in tokens.chain(places).chain(transitions).sorted_by_key(|c| (c.name(), c.id())) {
+        if !defined.contains(&clade.id()) && !other.iter().any(|o| o.descendent(&clade.id())) {
+            other.retain(|o| !clade.descendent(&o.id()));
+            other.push(clade);
+        }
+    }
+
+    let order = net.transition_order.iter().filter_map(|id| net.transitions.get(id));
+    let rest = net.transitions.values().filter(|t| !net.transition_order.contains(&t.id)).sorted_by_key(|t| (t.name.clone(), t.id));
+    NetDoc {
+        id: net.id,
+        name: net.name.clone(),
+        clades: net.clades.iter().map(write_clade).collect(),
+        other_clades: other.iter().map(write_clade).collect(),
+        places: net
+            .places
+            .values()
+            .sorted_by_key(|p| (p.name.clone(), p.id))
+            .map(|p| PlaceDoc {
+                id: p.id,
+                name: p.name.clone(),
+                colors: p.colors.iter().map(|c| c.id()).collect(),
+                capacity: p.capacity.as_ref().map(|capacity| match capacity {
+                    Capacity::Total(limit) => CapacityDoc::Total(*limit),
+                    Capacity::PerClade(limits) => {
+                        CapacityDoc::PerClade(limits.iter().map(|(c, limit)| CladeLimitDoc { clade: c.id(), limit: *limit }).collect())
+                    }
+                }),
+                port: p.port,
+            })
+            .collect(),
+        transitions: order
+            .chain(rest)
+            .map(|t| TransitionDoc {
+                id: t.id,
+                name: t.name.clone(),
+                input: write_arcs(&t.input),
+                output: write_arcs(&t.output),
+                reads: write_arcs(&t.reads),
+                inhibitors: t
+                    .inhibitors
+                    .iter()
+                    .sorted_by_key(|(place, _)| **place)
+                    .map(|(place, clade)| InhibitorDoc { place: *place, clade: clade.as_ref().map(|c| c.id()) })
+                    .collect(),
+                resets: t.resets.iter().copied().sorted().collect(),
+                guard: write_guard(&t.guard),
+                function: t.function.id,
+                priority: t.priority,
+                substitution: t.substitution.as_ref().map(|s| SubstitutionDoc {
+                    subnet: s.subnet,
+                    ports: s.ports.iter().sorted().map(|(port, socket)| PortBindingDoc { port: *port, socket: *socket }).collect(),
+                }),
+                label: t.label.clone(),
+            })
+            .collect(),
+        initial_marking: write_marking(&net.initial_marking),
+        current_marking: write_marking(&net.current_marking),
+        subnets: net.subnets.values().sorted_by_key(|n| (n.name.clone(), n.id)).map(write_net).collect(),
+    }
+}
+
+fn read_clade(doc: CladeDoc) -> Clade {
+    match doc.children {
+        Some(children) => Clade::Branch { uuid: doc.id, name: doc.name, children: children.into_iter().map(read_clade).collect() },
+        None => Clade::Leaf { uuid: doc.id, name: doc.name },
+    }
+}
+
+fn read_guard(doc: GuardDoc, clade: &dyn Fn(Uuid) -> Result<Clade, FormatError>) -> Result<Guard, FormatError> {
+    let list = |guards: Vec<GuardDoc>| guards.into_iter().map(|g| read_guard(g, clade)).collect::<Result<Vec<_>, _>>();
+    Ok(match doc {
+        GuardDoc::Is { symbol, clade: c } => Guard::Is(Symbol::new(symbol), clade(c)?),
+        GuardDoc::IsNot { symbol, clade: c } => Guard::Not(Symbol::new(symbol), clade(c)?),
+        GuardDoc::LessThan { symbol, clade: c } => Guard::LessThan(Symbol::new(symbol), clade(c)?),
+        GuardDoc::LessThanOrEqual { symbol, clade: c } => Guard::LessThanOrEqual(Symbol::new(symbol), clade(c)?),
+        GuardDoc::GreaterThan { symbol, clade: c } => Guard::GreaterThan(Symbol::new(symbol), clade(c)?),
+        GuardDoc::GreaterThanOrEqual { symbol, clade: c } => Guard::GreaterThanOrEqual(Symbol::new(symbol), clade(c)?),
+        GuardDoc::All { guards } => Guard::All(list(guards)?),
+        GuardDoc::Any { guards } => Guard::Any(list(guards)?),
+        GuardDoc::None { guards } => Guard::None(list(guards)?),
+        GuardDoc::True => Guard::Empty,
+    })
+}
+
+fn read_arcs(arcs: Vec<ArcDoc>) -> HashMap<Uuid, Signature> {
+    arcs.into_iter().map(|arc| (arc.place, Signature::new(arc.symbols.into_iter().map(Symbol::new).collect()))).collect()
+}
+
+fn read_marking(docs: Vec<TokensDoc>, clade: &dyn Fn(Uuid) -> Result<Clade, FormatError>) -> Result<Marking, FormatError> {
+    let mut marking = Marking::new();
+    for doc in docs {
+        for token in doc.tokens {
+            marking.add_token(doc.place, Token { id: token.id, name: token.name, clade: clade(token.clade)? });
+        }
+    }
+    Ok(marking)
+}
+
+fn read_net(doc: NetDoc) -> Result<ColoredPetriNet, FormatError> {
+    let roots = doc.clades.into_iter().map(read_clade).collect_vec();
+    let other = doc.other_clades.into_iter().map(read_clade).collect_vec();
+    let clades: HashMap<Uuid, Clade> = roots.iter().chain(other.iter()).flat_map(|c| c.flatten()).map(|c| (c.id(), c)).collect();
+    let clade = |id: Uuid| clades.get(&id).cloned().ok_or(FormatError::UnknownClade(id));
+
+    let mut net = ColoredPetriNet::new(doc.name, None, None, None);
+    net.id = doc.id;
+    net.clades = roots;
+    for p in doc.places {
+        let mut place = Place::new(p.name).with_colors(p.colors.into_iter().map(clade).collect::<Result<_, _>>()?);
+        place.id = p.id;
+        place.port = p.port;
+        place.capacity = match p.capacity {
+            Some(CapacityDoc::Total(limit)) => Some(Capacity::Total(limit)),
+            Some(CapacityDoc::PerClade(limits)) => {
+                Some(Capacity::PerClade(limits.into_iter().map(|l| Ok((clade(l.clade)?, l.limit))).collect::<Result<_, FormatError>>()?))
+            }
+            None => None,
+        };
+        net.add_place(place);
+    }
+    for t in doc.transitions {
+        // Fields are set directly, so that nothing the constructor would drop is lost
+        let mut transition = Transition::new(t.name, None, None, None, None);
+        transition.id = t.id;
+        transition.input = read_arcs(t.input);
+        transition.output = read_arcs(t.output);
+        transition.reads = read_arcs(t.reads);
+        transition.inhibitors = t.inhibitors.into_iter().map(|i| Ok((i.place, i.clade.map(clade).transpose()?))).collect::<Result<_, FormatError>>()?;
+        transition.resets = t.resets.into_iter().collect();
+        transition.guard = read_guard(t.guard, &clade)?;
+        transition.function = Function { id: t.function };
+        transition.priority = t.priority;
+        transition.substitution = t.substitution.map(|s| Substitution::new(s.subnet, s.ports.into_iter().map(|p| (p.port, p.socket)).collect()));
+        transition.label = t.label;
+        net.add_transition(transition);
+    }
+    net.initial_marking = read_marking(doc.initial_marking, &clade)?;
+    net.current_marking = read_marking(doc.current_marking, &clade)?;
+    for subnet in doc.subnets {
+        net.add_subnet(read_net(subnet)?);
+    }
+    Ok(net)
+}
+
+/// A JSON Schema (draft 2020-12) of the current version of the file format.
+pub fn schema() -> Value {
+    let uuid = json!({ "type": "string", "format": "uuid" });
+    let object = |properties: Value, required: &[&str]| {
+        json!({ "type": "object", "properties": properties, "required": required, "additionalProperties": false })
+    };
+    let comparison = |op: &str| {
+        object(json!({ "op": { "const": op }, "symbol": { "type": "string" }, "clade": { "$ref": "#/$defs/id" } }), &["op", "symbol", "clade"])
+    };
+    let combination = |op: &str| {
+        object(json!({ "op": { "const": op }, "guards": { "type": "array", "items": { "$ref": "#/$defs/guard" } } }), &["op", "guards"])
+    };
+    let list = |definition: &str| json!({ "type": "array", "items": { "$ref": format!("#/$defs/{}", definition) } });
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "Colored Petri net",
+        "type": "object",
+        "properties": {
+            "formatVersion": { "const": FORMAT_VERSION },
+            "net": { "$ref": "#/$defs/net" },
+        },
+        "required": ["formatVersion", "net"],
+        "additionalProperties": false,
+        "$defs": {
+            "id": uuid,
+            "net": object(json!({
+                "id": { "$ref": "#/$defs/id" },
+                "name": { "type": "string" },
+                "clades": list("clade"),
+                "otherClades": list("clade"),
+                "places": list("place"),
+                "transitions": list("transition"),
+                "initialMarking": list("tokens"),
+                "currentMarking": list("tokens"),
+                "subnets": list("net"),
+            }), &["id", "name", "clades", "places", "transitions", "initialMarking", "currentMarking"]),
+            "clade": object(json!({
+                "id": { "$ref": "#/$defs/id" },
+                "name": { "type": "string" },
+                "children": list("clade"),
+            }), &["id", "name"]),
+            "place": object(json!({
+                "id": { "$ref": "#/$defs/id" },
+                "name": { "type": "string" },
+                "colors": list("id"),
+                "capacity": { "oneOf": [
+                    object(json!({ "total": { "type": "integer", "minimum": 0 } }), &["total"]),
+                    object(json!({ "perClade": { "type": "array", "items": object(json!({
+                        "clade": { "$ref": "#/$defs/id" },
+                        "limit": { "type": "integer", "minimum": 0 },
+                    }), &["clade", "limit"]) } }), &["perClade"]),
+                ] },
+                "port": { "enum": ["in", "out", "inOut"] },
+            }), &["id", "name"]),
+            "arc": object(json!({
+                "place": { "$ref": "#/$defs/id" },
+                "symbols": { "type": "array", "items": { "type": "string" } },
+            }), &["place", "symbols"]),
+            "transition": object(json!({
+                "id": { "$ref": "#/$defs/id" },
+                "name": { "type": "string" },
+                "input": list("arc"),
+                "output": list("arc"),
+                "reads": list("arc"),
+                "inhibitors": { "type": "array", "items": object(json!({
+                    "place": { "$ref": "#/$defs/id" },
+                    "clade": { "$ref": "#/$defs/id" },
+                }), &["place"]) },
+                "resets": list("id"),
+                "guard": { "$ref": "#/$defs/guard" },
+                "function": { "$ref": "#/$defs/id" },
+                "priority": { "type": "integer" },
+                "substitution": object(json!({
+                    "subnet": { "$ref": "#/$defs/id" },
+                    "ports": { "type": "array", "items": object(json!({
+                        "port": { "$ref": "#/$defs/id" },
+                        "socket": { "$ref": "#/$defs/id" },
+                    }), &["port", "socket"]) },
+                }), &["subnet", "ports"]),
+                "label": { "type": "string" },
+            }), &["id", "name", "guard", "function"]),
+            "guard": { "oneOf": [
+                comparison("is"),
+                comparison("isNot"),
+                comparison("lessThan"),
+                comparison("lessThanOrEqual"),
+                comparison("greaterThan"),
+                comparison("greaterThanOrEqual"),
+                combination("all"),
+                combination("any"),
+                combination("none"),
+                object(json!({ "op": { "const": "true" } }), &["op"]),
+            ] },
+            "tokens": object(json!({
+                "place": { "$ref": "#/$defs/id" },
+                "tokens": { "type": "array", "items": object(json!({
+                    "id": { "$ref": "#/$defs/id" },
+                    "name": { "type": "string" },
+                    "clade": { "$ref": "#/$defs/id" },
+                }), &["id", "name", "clade"]) },
+            }), &["place", "tokens"]),
+        },
+    })
+}
+
+#[test]
+pub fn versioned_files() {
+    // Checks a value against the parts of JSON Schema that `schema` uses
+    fn conforms(value: &Value, schema: &Value, root: &Value) -> bool {
+        if let Some(reference) = schema.get("$ref").and_then(|r| r.as_str()) {
+            let name = reference.trim_start_matches("#/$defs/");
+            return conforms(value, &root["$defs"][name], root);
+        }
+        if let Some(options) = schema.get("oneOf").and_then(|o| o.as_array()) {
+            return options.iter().filter(|o| conforms(value, o, root)).count() == 1;
+        }
+        if let Some(constant) = schema.get("const") {
+            return value == constant;
+        }
+        if let Some(options) = schema.get("enum").and_then(|o| o.as_array()) {
+            return options.contains(value);
+        }
+        match schema.get("type").and_then(|t| t.as_str()) {
+            Some("string") => value.as_str().is_some_and(|s| schema.get("format").is_none() || Uuid::parse_str(s).is_ok()),
+            Some("integer") => value.as_i64().is_some_and(|n| schema.get("minimum").is_none() || n >= 0),
+            Some("array") => value.as_array().is_some_and(|items| items.iter().all(|i| conforms(i, &schema["items"], root))),
+            Some("object") => {
+                let (Some(fields), Some(properties)) = (value.as_object(), schema["properties"].as_object()) else { return false };
+                let required = schema["required"].as_array().unwrap();
+                required.iter().all(|r| fields.contains_key(r.as_str().unwrap()))
+                    && fields.iter().all(|(key, field)| properties.get(key).is_some_and(|p| conforms(field, p, root)))
+            }
+            _ => true,
+        }
+    }
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let drone = Clade::new("drone".into(), None);
+    let part = Clade::new("part".into(), None);
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    net.add_clade(Clade::new("robot".into(), Some(vec![robot1.clone()])));
+    let idle = net.add_place(Place::new("idle".into()).with_capacity(Capacity::PerClade(vec![(robot1.clone(), 2)])));
+    let done = net.add_place(Place::new("done".into()).with_colors(vec![robot1.clone()]).with_port(Port::Out));
+    net.add_token(idle, Token::new("r1".into(), robot1.clone()));
+    net.add_token(idle, Token::new("d1".into(), drone.clone()));
+    let sig = || Signature::new(HashSet::from(["x".into()]));
+    let work = Transition::new(
+        "work".into(),
+        Some(HashMap::from([(idle, sig())])),
+        Some(HashMap::from([(done, sig())])),
+        Some(Guard::Any(vec![Guard::Is("x".into(), robot1.clone()), Guard::Not("x".into(), part.clone())])),
+        None,
+    )
+    .with_inhibitor(done, Some(drone.clone()))
+    .with_priority(2)
+    .with_label("step".into());
+    let work = net.add_transition(work);
+    let binding = net.enabled()[0].1.clone();
+    net.fire(&work, &binding).unwrap();
+
+    let text = to_json(&net);
+    let file: Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(file["formatVersion"], json!(FORMAT_VERSION));
+    assert!(conforms(&file, &schema(), &schema()));
+    assert_eq!(file["net"]["otherClades"].as_array().unwrap().len(), 2);
+    assert_eq!(from_json(&text).unwrap(), net);
+
+    // Files from before versioning are migrated, including the oldest markings kept by token id
+    let legacy = r#"{
+        "id": "00000000-0000-0000-0000-0000000000c1", "name": "cell",
+        "places": {
+            "00000000-0000-0000-0000-0000000000a1": {
+                "id": "00000000-0000-0000-0000-0000000000a1", "name": "idle", "colors": [],
+                "capacity": { "perClade": [[{ "Leaf": { "uuid": "00000000-0000-0000-0000-0000000000d1", "name": "robot1" } }, 2]] },
+                "port": null
+            },
+            "00000000-0000-0000-0000-0000000000a2": {
+                "id": "00000000-0000-0000-0000-0000000000a2", "name": "done",
+                "colors": [{ "Leaf": { "uuid": "00000000-0000-0000-0000-0000000000d1", "name": "robot1" } }],
+                "capacity": null, "port": "out"
+            }
+        },
+        "transitions": {
+            "00000000-0000-0000-0000-0000000000b1": {
+                "id": "00000000-0000-0000-0000-0000000000b1", "name": "work",
+                "input": { "00000000-0000-0000-0000-0000000000a1": { "symbols": ["x"] } },
+                "output": { "00000000-0000-0000-0000-0000000000a2": { "symbols": ["x"] } },
+                "guard": { "Any": [
+                    { "Is": ["x", { "Leaf": { "uuid": "00000000-0000-0000-0000-0000000000d1", "name": "robot1" } }] },
+                    { "Not": ["x", { "Leaf": { "uuid": "00000000-0000-0000-0000-0000000000d3", "name": "part" } }] }
+                ] },
+                "function": { "id": "00000000-0000-0000-0000-0000000000b2" },
+                "reads": {},
+                "inhibitors": { "00000000-0000-0000-0000-0000000000a2": { "Leaf": { "uuid": "00000000-0000-0000-0000-0000000000d2", "name": "drone" } } },
+                "resets": [], "priority": 2, "substitution": null, "label": "step"
+            }
+        },
+        "initial_marking": {
+            "00000000-0000-0000-0000-0000000000a1": {
+                "00000000-0000-0000-0000-0000000000e1": {
+                    "id": "00000000-0000-0000-0000-0000000000e1", "name": "r1",
+                    "clade": { "Leaf": { "uuid": "00000000-0000-0000-0000-0000000000d1", "name": "robot1" } }
+                }
+            }
+        },
+        "current_marking": {
+            "00000000-0000-0000-0000-0000000000a2": [{
+                "id": "00000000-0000-0000-0000-0000000000e1", "name": "r1",
+                "clade": { "Leaf": { "uuid": "00000000-0000-0000-0000-0000000000d1", "name": "robot1" } }
+            }]
+        },
+        "name_lookup": {},
+        "clades": [{ "Branch": { "uuid": "00000000-0000-0000-0000-0000000000d0", "name": "robot", "children": [
+            { "Leaf": { "uuid": "00000000-0000-0000-0000-0000000000d1", "name": "robot1" } }
+        ] } }],
+        "transition_order": ["00000000-0000-0000-0000-0000000000b1"]
+    }"#;
+    let id = |n: u128| Uuid::from_u128(n);
+    let read = from_json(legacy).unwrap();
+    let migrated = migrate(serde_json::from_str(legacy).unwrap()).unwrap();
+    assert!(conforms(&migrated, &schema(), &schema()));
+    assert_eq!(migrated["net"]["otherClades"].as_array().unwrap().len(), 2);
+    assert_eq!((read.id, read.name.as_str()), (id(0xc1), "cell"));
+    assert_eq!(read.clades.len(), 1);
+    let robot1 = read.clade(&id(0xd1)).unwrap();
+    assert_eq!(read.places[&id(0xa1)].capacity, Some(Capacity::PerClade(vec![(robot1.clone(), 2)])));
+    assert_eq!((read.places[&id(0xa2)].colors.clone(), read.places[&id(0xa2)].port), (vec![robot1.clone()], Some(Port::Out)));
+    let work = &read.transitions[&id(0xb1)];
+    assert!(matches!(&work.guard, Guard::Any(guards) if guards.len() == 2 && guards[0] == Guard::Is("x".into(), robot1.clone())));
+    assert_eq!(work.inhibitors[&id(0xa2)].as_ref().map(|c| c.name()), Some("drone".to_string()));
+    assert_eq!((work.priority, work.label.as_deref(), work.function.id), (2, Some("step"), id(0xb2)));
+    assert_eq!(read.id_of("work"), Some(id(0xb1)));
+    assert_eq!(read.initial_marking.tokens(&id(0xa1)).iter().map(|t| t.id).collect_vec(), vec![id(0xe1)]);
+    assert_eq!(read.current_marking.tokens(&id(0xa2)).iter().map(|t| t.id).collect_vec(), vec![id(0xe1)]);
+    assert_eq!(from_json(&to_json(&read)).unwrap(), read);
+
+    // Newer, misspelled and dangling files are refused rather than misread
+    let mut newer = file.clone();
+    newer["formatVersion"] = json!(FORMAT_VERSION + 1);
+    assert_eq!(from_json(&newer.to_string()), Err(FormatError::UnsupportedVersion(FORMAT_VERSION + 1)));
+    let mut misspelled = file.clone();
+    misspelled["net"]["place"] = json!([]);
+    assert!(matches!(from_json(&misspelled.to_string()), Err(FormatError::Json(_))));
+    let mut dangling = file.clone();
+    dangling["net"]["otherClades"] = json!([]);
+    assert!(matches!(from_json(&dangling.to_string()), Err(FormatError::UnknownClade(_))));
+}
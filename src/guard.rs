@@ -1,6 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use itertools::Itertools;
 use serde::{Serialize, Deserialize};
+use uuid::Uuid;
 use crate::clade::Clade;
+use crate::query::{tokenize, Comparison, Lexeme, QueryError};
 use crate::symbol::Symbol;
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -47,10 +51,171 @@ impl Guard {
             Guard::Empty => vec![]
         }
     }
+
+    /// For each symbol of the guard, the ids of the clades among `clades` it is bound to in some
+    /// assignment of those clades to the symbols that satisfies the guard. Returns `None` when
+    /// there are more than `limit` assignments to try.
+    pub fn satisfying_clades(&self, clades: &[Clade], limit: usize) -> Option<HashMap<Symbol, HashSet<Uuid>>> {
+        let symbols = self.symbols().into_iter().unique().sorted().collect_vec();
+        if clades.len().checked_pow(symbols.len() as u32).is_none_or(|n| n > limit) {
+            return None;
+        }
+        let mut possible: HashMap<Symbol, HashSet<Uuid>> = symbols.iter().map(|s| (s.clone(), HashSet::new())).collect();
+        for chosen in symbols.iter().map(|_| clades.iter()).multi_cartesian_product() {
+            let assignment: HashMap<Symbol, Clade> = symbols.iter().cloned().zip(chosen.into_iter().cloned()).collect();
+            if self.eval(&assignment) {
+                for (symbol, clade) in assignment {
+                    possible.entry(symbol).or_default().insert(clade.id());
+                }
+            }
+        }
+        Some(possible)
+    }
+
+    /// Rebuilds the guard with its symbols and clades replaced.
+    pub fn map(&self, symbol: &dyn Fn(&Symbol) -> Symbol, clade: &dyn Fn(&Clade) -> Clade) -> Guard {
+        let list = |guards: &Vec<Guard>| guards.iter().map(|g| g.map(symbol, clade)).collect();
+        match self {
+            Guard::Is(s, c) => Guard::Is(symbol(s), clade(c)),
+            Guard::GreaterThan(s, c) => Guard::GreaterThan(symbol(s), clade(c)),
+            Guard::LessThan(s, c) => Guard::LessThan(symbol(s), clade(c)),
+            Guard::GreaterThanOrEqual(s, c) => Guard::GreaterThanOrEqual(symbol(s), clade(c)),
+            Guard::LessThanOrEqual(s, c) => Guard::LessThanOrEqual(symbol(s), clade(c)),
+            Guard::Not(s, c) => Guard::Not(symbol(s), clade(c)),
+            Guard::All(guards) => Guard::All(list(guards)),
+            Guard::Any(guards) => Guard::Any(list(guards)),
+            Guard::None(guards) => Guard::None(list(guards)),
+            Guard::Empty => Guard::Empty,
+        }
+    }
+
+    /// Parses a guard written as it is displayed, e.g. `x >= robot and not (y = part)`. `&`, `|`
+    /// and `!` work as well as the words, and clade names are looked up with `clade`.
+    pub fn parse(text: &str, clade: &dyn Fn(&str) -> Option<Clade>) -> Result<Guard, QueryError> {
+        let mut parser = GuardParser { lexemes: tokenize(text)?, position: 0, length: text.len(), clade };
+        let guard = parser.parse_or()?;
+        match parser.lexemes.get(parser.position) {
+            Some(_) => parser.error("unexpected input after the guard"),
+            None => Ok(guard),
+        }
+    }
+}
+
+// Recursive descent over the query lexemes; 'and' binds tighter than 'or'
+struct GuardParser<'a> {
+    lexemes: Vec<(usize, Lexeme)>,
+    position: usize,
+    length: usize,
+    clade: &'a dyn Fn(&str) -> Option<Clade>,
+}
+
+impl GuardParser<'_> {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.position).map(|(_, l)| l)
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, QueryError> {
+        let position = self.lexemes.get(self.position).map(|(o, _)| *o).unwrap_or(self.length);
+        Err(QueryError::Syntax { position, message: message.to_string() })
+    }
+
+    // Whether the next lexeme is the operator, spelled as a symbol or as a word
+    fn accept(&mut self, lexeme: Lexeme, word: &str) -> bool {
+        let found = match self.peek() {
+            Some(Lexeme::Name(name)) => name == word,
+            Some(next) => *next == lexeme,
+            None => false,
+        };
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn parse_or(&mut self) -> Result<Guard, QueryError> {
+        let mut guards = vec![self.parse_and()?];
+        while self.accept(Lexeme::Or, "or") {
+            guards.push(self.parse_and()?);
+        }
+        Ok(if guards.len() == 1 { guards.remove(0) } else { Guard::Any(guards) })
+    }
+
+    fn parse_and(&mut self) -> Result<Guard, QueryError> {
+        let mut guards = vec![self.parse_unary()?];
+        while self.accept(Lexeme::And, "and") {
+            guards.push(self.parse_unary()?);
+        }
+        Ok(if guards.len() == 1 { guards.remove(0) } else { Guard::All(guards) })
+    }
+
+    fn parse_unary(&mut self) -> Result<Guard, QueryError> {
+        if self.accept(Lexeme::Not, "not") {
+            return Ok(Guard::None(vec![self.parse_unary()?]));
+        }
+        if self.accept(Lexeme::Open, "(") {
+            let guard = self.parse_or()?;
+            if !self.accept(Lexeme::Close, ")") {
+                return self.error("expected ')'");
+            }
+            return Ok(guard);
+        }
+        if self.accept(Lexeme::Name("true".into()), "true") {
+            return Ok(Guard::Empty);
+        }
+        let symbol = self.name("a symbol")?;
+        let comparison = match self.peek() {
+            Some(Lexeme::Compare(comparison)) => *comparison,
+            _ => return self.error("expected a comparison"),
+        };
+        self.position += 1;
+        let start = self.position;
+        let name = self.name("a clade")?;
+        let Some(clade) = (self.clade)(&name) else {
+            self.position = start;
+            return Err(QueryError::UnknownClade(name));
+        };
+        let symbol = Symbol::new(symbol);
+        Ok(match comparison {
+            Comparison::Equal => Guard::Is(symbol, clade),
+            Comparison::NotEqual => Guard::Not(symbol, clade),
+            Comparison::Less => Guard::LessThan(symbol, clade),
+            Comparison::LessOrEqual => Guard::LessThanOrEqual(symbol, clade),
+            Comparison::Greater => Guard::GreaterThan(symbol, clade),
+            Comparison::GreaterOrEqual => Guard::GreaterThanOrEqual(symbol, clade),
+        })
+    }
+
+    fn name(&mut self, description: &str) -> Result<String, QueryError> {
+        let name = match self.peek() {
+            Some(Lexeme::Name(name)) => name.clone(),
+            Some(Lexeme::Number(number)) => number.to_string(),
+            _ => return self.error(&format!("expected {}", description)),
+        };
+        self.position += 1;
+        Ok(name)
+    }
 }
 
 impl Default for Guard {
     fn default() -> Self {
         Guard::Empty
     }
-}
\ No newline at end of file
+}
+
+impl fmt::Display for Guard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |guards: &Vec<Guard>, op: &str| guards.iter().map(|g| g.to_string()).collect::<Vec<_>>().join(op);
+        match self {
+            Guard::Is(symbol, clade) => write!(f, "{} = {}", symbol, clade.name()),
+            Guard::GreaterThan(symbol, clade) => write!(f, "{} > {}", symbol, clade.name()),
+            Guard::LessThan(symbol, clade) => write!(f, "{} < {}", symbol, clade.name()),
+            Guard::GreaterThanOrEqual(symbol, clade) => write!(f, "{} >= {}", symbol, clade.name()),
+            Guard::LessThanOrEqual(symbol, clade) => write!(f, "{} <= {}", symbol, clade.name()),
+            Guard::Not(symbol, clade) => write!(f, "{} != {}", symbol, clade.name()),
+            Guard::All(guards) => write!(f, "({})", list(guards, " and ")),
+            Guard::Any(guards) => write!(f, "({})", list(guards, " or ")),
+            Guard::None(guards) => write!(f, "not ({})", list(guards, " or ")),
+            Guard::Empty => write!(f, "true")
+        }
+    }
+}
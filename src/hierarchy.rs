@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::fmt;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::marking::Marking;
+use crate::net::ColoredPetriNet;
+
+/// What a substitution transition stands for: an instance of a subnet, with each port place of
+/// the subnet bound to a place (socket) of the surrounding net.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Substitution {
+    pub subnet: Uuid,
+    // Socket places of the surrounding net, hashed by the id of the port place in the subnet
+    pub ports: HashMap<Uuid, Uuid>,
+}
+
+impl Substitution {
+    pub fn new(subnet: Uuid, ports: HashMap<Uuid, Uuid>) -> Self {
+        Self { subnet, ports }
+    }
+}
+
+/// Reasons a hierarchical net cannot be flattened.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HierarchyError {
+    // No enclosing net registers the subnet
+    UnknownSubnet { transition: Uuid, subnet: Uuid },
+    // A port place of the subnet is not bound to a socket
+    UnboundPort { transition: Uuid, port: Uuid },
+    // A place bound as a port is not a port place of the subnet
+    NotAPort { transition: Uuid, place: Uuid },
+    // A socket is not a place of the surrounding net
+    UnknownSocket { transition: Uuid, place: Uuid },
+    // An arc of a transition of the subnet leads to a place the subnet does not have
+    UnknownPlace { subnet: Uuid, transition: Uuid, place: Uuid },
+    // The subnet contains an instance of itself
+    Recursive(Uuid),
+}
+
+impl fmt::Display for HierarchyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HierarchyError::UnknownSubnet { transition, subnet } => {
+                write!(f, "transition {} stands for subnet {}, which is not registered", transition, subnet)
+            }
+            HierarchyError::UnboundPort { transition, port } => {
+                write!(f, "port place {} of the subnet of transition {} is not bound", port, transition)
+            }
+            HierarchyError::NotAPort { transition, place } => {
+                write!(f, "place {} bound by transition {} is not a port of its subnet", place, transition)
+            }
+            HierarchyError::UnknownSocket { transition, place } => {
+                write!(f, "transition {} binds a port to place {}, which is not in the net", transition, place)
+            }
+            HierarchyError::UnknownPlace { subnet, transition, place } => {
+                write!(f, "transition {} of subnet {} has an arc to place {}, which is not in the subnet", transition, subnet, place)
+            }
+            HierarchyError::Recursive(subnet) => write!(f, "subnet {} contains an instance of itself", subnet),
+        }
+    }
+}
+
+impl std::error::Error for HierarchyError {}
+
+/// The id an element of a subnet gets in the instance created by a substitution transition.
+///
+/// Ids are derived from the id of the substitution transition and of the element, so flattening
+/// the same net twice gives the same ids, and instances of a shared subnet never collide.
+pub fn instance_id(transition: &Uuid, element: &Uuid) -> Uuid {
+    Uuid::new_v5(transition, element.as_bytes())
+}
+
+/// Replaces every substitution transition by a copy of its subnet, recursively.
+///
+/// The places and transitions of an instance are named by their path from the surrounding net,
+/// e.g. `cell1/loader/pick`, and get ids from [`instance_id`]. Port places are merged with their
+/// sockets, so tokens of the subnet's marking in port places are dropped. Subnets are looked up
+/// in the net that contains the substitution transition first, then in the nets enclosing it.
+pub fn flatten(net: &ColoredPetriNet) -> Result<ColoredPetriNet, HierarchyError> {
+    flatten_within(net, &[], &mut vec![])
+}
+
+fn flatten_within<'a>(
+    net: &'a ColoredPetriNet,
+    enclosing: &[&'a HashMap<Uuid, ColoredPetriNet>],
+    stack: &mut Vec<Uuid>,
+) -> Result<ColoredPetriNet, HierarchyError> {
+    let mut libraries = enclosing.to_vec();
+    libraries.push(&net.subnets);
+    let mut flat = net.clone();
+    flat.subnets.clear();
+    for transition in net.declared_transitions() {
+        let substitution = match &transition.substitution {
+            Some(substitution) => substitution,
+            None => continue,
+        };
+        let subnet = libraries
+            .iter()
+            .rev()
+            .find_map(|l| l.get(&substitution.subnet))
+            .ok_or(HierarchyError::UnknownSubnet { transition: transition.id, subnet: substitution.subnet })?;
+        if stack.contains(&subnet.id) {
+            return Err(HierarchyError::Recursive(subnet.id));
+        }
+        stack.push(subnet.id);
+        let inner = flatten_within(subnet, &libraries, stack)?;
+        stack.pop();
+
+        for (port, socket) in substitution.ports.iter().sorted() {
+            if inner.places.get(port).is_none_or(|p| p.port.is_none()) {
+                return Err(HierarchyError::NotAPort { transition: transition.id, place: *port });
+            }
+            if !flat.places.contains_key(socket) {
+                return Err(HierarchyError::UnknownSocket { transition: transition.id, place: *socket });
+            }
+        }
+        let places: HashMap<Uuid, Uuid> = inner
+            .places
+            .values()
+            .sorted_by_key(|p| (p.name.clone(), p.id))
+            .map(|place| match (place.port, substitution.ports.get(&place.id)) {
+                (Some(_), Some(socket)) => Ok((place.id, *socket)),
+                (Some(_), None) => Err(HierarchyError::UnboundPort { transition: transition.id, port: place.id }),
+                (None, _) => Ok((place.id, instance_id(&transition.id, &place.id))),
+            })
+            .collect::<Result<_, _>>()?;
+
+        let path = |name: &str| format!("{}/{}", transition.name, name);
+        for place in inner.places.values().filter(|p| p.port.is_none()) {
+            let mut copy = place.clone();
+            copy.id = places[&place.id];
+            copy.name = path(&place.name);
+            flat.add_place(copy);
+        }
+        let mut instances = vec![];
+        for inner_transition in inner.declared_transitions() {
+            let place = |p: Uuid| {
+                places.get(&p).copied().ok_or(HierarchyError::UnknownPlace { subnet: subnet.id, transition: inner_transition.id, place: p })
+            };
+            let mut copy = inner_transition.clone();
+            copy.id = instance_id(&transition.id, &inner_transition.id);
+            copy.name = path(&inner_transition.name);
+            copy.input = copy.input.into_iter().map(|(p, s)| Ok((place(p)?, s))).collect::<Result<_, _>>()?;
+            copy.output = copy.output.into_iter().map(|(p, s)| Ok((place(p)?, s))).collect::<Result<_, _>>()?;
+            copy.reads = copy.reads.into_iter().map(|(p, s)| Ok((place(p)?, s))).collect::<Result<_, _>>()?;
+            copy.inhibitors = copy.inhibitors.into_iter().map(|(p, c)| Ok((place(p)?, c))).collect::<Result<_, _>>()?;
+            copy.resets = copy.resets.into_iter().map(place).collect::<Result<_, _>>()?;
+            instances.push(copy.id);
+            flat.name_lookup.insert(copy.id, copy.name.clone());
+            flat.transitions.insert(copy.id, copy);
+        }
+        // The instance takes the place of the substitution transition in declaration order
+        flat.transitions.remove(&transition.id);
+        flat.name_lookup.remove(&transition.id);
+        let position = flat.transition_order.iter().position(|t| *t == transition.id).unwrap_or(flat.transition_order.len());
+        flat.transition_order.splice(position..(position + 1).min(flat.transition_order.len()), instances);
+
+        let copy_tokens = |marking: &Marking, into: &mut Marking| {
+            for (place, tokens) in marking {
+                if inner.places.get(place).is_some_and(|p| p.port.is_some()) || !places.contains_key(place) {
+                    continue;
+                }
+                for token in tokens.values() {
+                    let mut copy = token.clone();
+                    copy.id = instance_id(&transition.id, &token.id);
+                    into.add_token(places[place], copy);
+                }
+            }
+        };
+        copy_tokens(&inner.initial_marking, &mut flat.initial_marking);
+        copy_tokens(&inner.current_marking, &mut flat.current_marking);
+        for clade in &inner.clades {
+            flat.add_clade(clade.clone());
+        }
+    }
+    Ok(flat)
+}
+
+#[test]
+pub fn flatten_nested_instances() {
+    use std::collections::HashSet;
+    use crate::clade::Clade;
+    use crate::place::{Place, Port};
+    use crate::signature::Signature;
+    use crate::statespace::StateSpace;
+    use crate::token::Token;
+    use crate::transition::Transition;
+
+    let part = Clade::new("part".into(), None);
+    let sig = || Signature::new(HashSet::from(["x".into()]));
+    let arcs = |from, to| (Some(HashMap::from([(from, sig())])), Some(HashMap::from([(to, sig())])));
+
+    // A loader picks a part from its input port and places it on its output port
+    let mut loader = ColoredPetriNet::new("loader".into(), None, None, None);
+    let source = loader.add_place(Place::new("source".into()).with_port(Port::In));
+    let gripper = loader.add_place(Place::new("gripper".into()));
+    let target = loader.add_place(Place::new("target".into()).with_port(Port::Out));
+    let (i, o) = arcs(source, gripper);
+    loader.add_transition(Transition::new("pick".into(), i, o, None, None));
+    let (i, o) = arcs(gripper, target);
+    loader.add_transition(Transition::new("place".into(), i, o, None, None));
+
+    // A cell loads parts from its inbox into a machine, which finishes them into its outbox
+    let mut cell = ColoredPetriNet::new("cell".into(), None, None, None);
+    let loader_id = cell.add_subnet(loader);
+    let inbox = cell.add_place(Place::new("inbox".into()).with_port(Port::In));
+    let machine = cell.add_place(Place::new("machine".into()));
+    let outbox = cell.add_place(Place::new("outbox".into()).with_port(Port::Out));
+    cell.add_transition(
+        Transition::new("loader".into(), None, None, None, None)
+            .with_substitution(Substitution::new(loader_id, HashMap::from([(source, inbox), (target, machine)]))),
+    );
+    let (i, o) = arcs(machine, outbox);
+    let finish = cell.add_transition(Transition::new("finish".into(), i, o, None, None));
+
+    // Two instances of the same cell in a line
+    let mut line = ColoredPetriNet::new("line".into(), None, None, None);
+    line.add_clade(part.clone());
+    let cell_id = line.add_subnet(cell);
+    let raw = line.add_place(Place::new("raw".into()));
+    let middle = line.add_place(Place::new("middle".into()));
+    let done = line.add_place(Place::new("done".into()));
+    line.add_token(raw, Token::new("p1".into(), part.clone()));
+    let cell1 = line.add_transition(
+        Transition::new("cell1".into(), None, None, None, None)
+            .with_substitution(Substitution::new(cell_id, HashMap::from([(inbox, raw), (outbox, middle)]))),
+    );
+    line.add_transition(
+        Transition::new("cell2".into(), None, None, None, None)
+            .with_substitution(Substitution::new(cell_id, HashMap::from([(inbox, middle), (outbox, done)]))),
+    );
+    assert!(line.enabled().is_empty());
+
+    let flat = flatten(&line).unwrap();
+    assert_eq!(flat.places.len(), 3 + 2 * 2);
+    assert_eq!(flat.transitions.len(), 2 * 3);
+    assert!(flat.subnets.is_empty());
+    assert!(flat.transitions.values().all(|t| t.substitution.is_none()));
+    let pick = flat.id_of("cell1/loader/pick").unwrap();
+    assert_eq!(line.flat_id_of("cell1/loader/pick"), Some(pick));
+    assert_eq!(line.id_of("cell1/loader/pick"), None);
+    assert_eq!(line.flat_id_of("cell1"), None);
+    assert_ne!(flat.id_of("cell2/loader/pick"), Some(pick));
+    assert_eq!(flat.transitions[&pick].input.keys().collect::<Vec<_>>(), vec![&raw]);
+    assert!(flat.id_of("cell1/loader/gripper").is_some());
+    assert!(flat.id_of("cell1/machine").is_some());
+    assert!(flat.id_of("cell1").is_none());
+    assert_eq!(flatten(&line).unwrap().places.keys().sorted().collect_vec(), flat.places.keys().sorted().collect_vec());
+    let order = flat.declared_transitions().iter().map(|t| t.name.clone()).collect_vec();
+    assert_eq!(order, ["cell1/loader/pick", "cell1/loader/place", "cell1/finish", "cell2/loader/pick", "cell2/loader/place", "cell2/finish"]);
+
+    // The part goes through both cells, one step at a time
+    let space = StateSpace::explore(&flat, None);
+    assert_eq!(space.markings.len(), 7);
+    assert_eq!(space.dead().len(), 1);
+
+    let mut broken = line.clone();
+    broken.transitions.get_mut(&cell1).unwrap().substitution.as_mut().unwrap().ports.remove(&outbox);
+    assert_eq!(flatten(&broken), Err(HierarchyError::UnboundPort { transition: cell1, port: outbox }));
+    let mut broken = line.clone();
+    broken.subnets.clear();
+    assert!(matches!(flatten(&broken), Err(HierarchyError::UnknownSubnet { .. })));
+    let mut broken = line.clone();
+    let nowhere = Uuid::new_v4();
+    broken.subnets.get_mut(&cell_id).unwrap().transitions.get_mut(&finish).unwrap().resets.insert(nowhere);
+    assert_eq!(flatten(&broken), Err(HierarchyError::UnknownPlace { subnet: cell_id, transition: finish, place: nowhere }));
+}
@@ -0,0 +1,227 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::binding::Binding;
+use crate::marking::Marking;
+use crate::net::ColoredPetriNet;
+use crate::transition::FiringError;
+
+/// A recorded firing, with the tokens it took out of and put into each place.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Step {
+    // The step recorded before this one, or None for a step taken from where recording started
+    pub parent: Option<usize>,
+    pub transition: Uuid,
+    pub binding: Binding,
+    pub consumed: Marking,
+    pub produced: Marking,
+}
+
+/// Reasons the history cannot move the net to another state.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HistoryError {
+    Firing(FiringError),
+    UnknownCheckpoint(String),
+    // The current marking no longer holds what the step produced, so the net was changed
+    // outside the history
+    Diverged { step: usize },
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistoryError::Firing(error) => write!(f, "{}", error),
+            HistoryError::UnknownCheckpoint(name) => write!(f, "no checkpoint named '{}'", name),
+            HistoryError::Diverged { step } => {
+                write!(f, "the net was changed outside the history, so step {} cannot be undone or redone", step)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HistoryError {}
+
+impl From<FiringError> for HistoryError {
+    fn from(error: FiringError) -> Self {
+        HistoryError::Firing(error)
+    }
+}
+
+/// The firings of a net, for undoing and redoing them and for jumping between named states.
+///
+/// Steps form a tree rooted at the marking the net had when recording started: firing after an
+/// undo starts a new branch, and the steps of the old branch stay available to checkpoints.
+/// Only the tokens each step moved are stored, so the history is small enough to serialize and
+/// share along with the net.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct History {
+    pub steps: Vec<Step>,
+    // The last step applied to the net, or None at the root
+    pub position: Option<usize>,
+    // Steps that were undone, most recently undone last
+    pub redo: Vec<usize>,
+    pub checkpoints: BTreeMap<String, Option<usize>>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fires a transition of the net and records the firing.
+    pub fn fire(&mut self, net: &mut ColoredPetriNet, transition: &Uuid, binding: &Binding) -> Result<(), HistoryError> {
+        let before = net.current_marking.clone();
+        net.fire(transition, binding)?;
+        self.record(*transition, binding.clone(), &before, &net.current_marking);
+        Ok(())
+    }
+
+    /// Records a firing that took the net from one marking to another, on top of the current
+    /// position. Anything left to redo is forgotten.
+    pub fn record(&mut self, transition: Uuid, binding: Binding, before: &Marking, after: &Marking) {
+        self.steps.push(Step { parent: self.position, transition, binding, consumed: before - after, produced: after - before });
+        self.position = Some(self.steps.len() - 1);
+        self.redo.clear();
+    }
+
+    /// Reverts the last step, returning the transition it fired, or `None` at the root.
+    pub fn undo(&mut self, net: &mut ColoredPetriNet) -> Result<Option<Uuid>, HistoryError> {
+        let Some(step) = self.position else { return Ok(None) };
+        self.revert(net, step)?;
+        self.redo.push(step);
+        Ok(Some(self.steps[step].transition))
+    }
+
+    /// Applies the step undone last again, returning the transition it fired, or `None` if there
+    /// is nothing to redo.
+    pub fn redo(&mut self, net: &mut ColoredPetriNet) -> Result<Option<Uuid>, HistoryError> {
+        let Some(step) = self.redo.last().copied() else { return Ok(None) };
+        self.apply(net, step)?;
+        self.redo.pop();
+        Ok(Some(self.steps[step].transition))
+    }
+
+    /// Names the current state, replacing any checkpoint of the same name.
+    pub fn checkpoint(&mut self, name: &str) {
+        self.checkpoints.insert(name.to_string(), self.position);
+    }
+
+    /// Moves the net to a checkpoint, undoing steps back to the branch it is on and then applying
+    /// the steps of that branch.
+    pub fn restore(&mut self, net: &mut ColoredPetriNet, name: &str) -> Result<(), HistoryError> {
+        let target = *self.checkpoints.get(name).ok_or_else(|| HistoryError::UnknownCheckpoint(name.to_string()))?;
+        let path = self.path_to(target);
+        while let Some(step) = self.position.filter(|s| !path.contains(s)) {
+            self.revert(net, step)?;
+        }
+        let applied = self.position.map_or(0, |s| path.iter().position(|p| *p == s).unwrap_or(0) + 1);
+        for step in path[applied..].iter().copied() {
+            self.apply(net, step)?;
+        }
+        self.redo.clear();
+        Ok(())
+    }
+
+    /// The steps from the root to the current position.
+    pub fn path(&self) -> Vec<usize> {
+        self.path_to(self.position)
+    }
+
+    fn path_to(&self, step: Option<usize>) -> Vec<usize> {
+        let mut path = vec![];
+        let mut current = step;
+        while let Some(step) = current {
+            path.push(step);
+            current = self.steps[step].parent;
+        }
+        path.reverse();
+        path
+    }
+
+    fn revert(&mut self, net: &mut ColoredPetriNet, step: usize) -> Result<(), HistoryError> {
+        let Step { consumed, produced, parent, .. } = &self.steps[step];
+        if !net.current_marking.includes(produced) {
+            return Err(HistoryError::Diverged { step });
+        }
+        net.current_marking = &(&net.current_marking - produced) + consumed;
+        self.position = *parent;
+        Ok(())
+    }
+
+    fn apply(&mut self, net: &mut ColoredPetriNet, step: usize) -> Result<(), HistoryError> {
+        let Step { consumed, produced, .. } = &self.steps[step];
+        if !net.current_marking.includes(consumed) {
+            return Err(HistoryError::Diverged { step });
+        }
+        net.current_marking = &(&net.current_marking - consumed) + produced;
+        self.position = Some(step);
+        Ok(())
+    }
+}
+
+#[test]
+pub fn undo_redo_and_checkpoints() {
+    use std::collections::{HashMap, HashSet};
+    use crate::clade::Clade;
+    use crate::place::Place;
+    use crate::signature::Signature;
+    use crate::token::Token;
+    use crate::transition::Transition;
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    let idle = net.add_place(Place::new("idle".into()));
+    let busy = net.add_place(Place::new("busy".into()));
+    let done = net.add_place(Place::new("done".into()));
+    net.add_token(idle, Token::new("r1".into(), robot1.clone()));
+    net.add_token(idle, Token::new("r2".into(), robot1.clone()));
+    let sig = || Signature::new(HashSet::from(["x".into()]));
+    let arcs = |from, to| (Some(HashMap::from([(from, sig())])), Some(HashMap::from([(to, sig())])));
+    let (i, o) = arcs(idle, busy);
+    let start = net.add_transition(Transition::new("start".into(), i, o, None, None));
+    let (i, o) = arcs(busy, done);
+    let finish = net.add_transition(Transition::new("finish".into(), i, o, None, None));
+    let fire_first = |history: &mut History, net: &mut ColoredPetriNet, transition: Uuid| {
+        let binding = net.bindings(&net.transitions[&transition], &net.current_marking)[0].clone();
+        history.fire(net, &transition, &binding).unwrap();
+    };
+
+    let mut history = History::new();
+    history.checkpoint("start");
+    fire_first(&mut history, &mut net, start);
+    fire_first(&mut history, &mut net, finish);
+    let finished = net.current_marking.clone();
+    history.checkpoint("one done");
+    assert_eq!(history.undo(&mut net).unwrap(), Some(finish));
+    assert_eq!(history.undo(&mut net).unwrap(), Some(start));
+    assert_eq!(history.undo(&mut net).unwrap(), None);
+    assert_eq!(net.current_marking, net.initial_marking);
+    assert_eq!(history.redo(&mut net).unwrap(), Some(start));
+    assert_eq!(history.redo(&mut net).unwrap(), Some(finish));
+    assert_eq!(net.current_marking, finished);
+
+    // Branch from the start: both robots get busy
+    history.restore(&mut net, "start").unwrap();
+    fire_first(&mut history, &mut net, start);
+    fire_first(&mut history, &mut net, start);
+    assert_eq!(net.current_marking.count(&busy), 2);
+    assert_eq!(history.redo(&mut net).unwrap(), None);
+    assert_eq!(history.path().len(), 2);
+    history.restore(&mut net, "one done").unwrap();
+    assert_eq!(net.current_marking, finished);
+    assert_eq!(history.steps.len(), 4);
+
+    // A shared session picks up where it was left
+    let mut shared: History = serde_json::from_str(&serde_json::to_string(&history).unwrap()).unwrap();
+    let mut copy = net.clone();
+    assert_eq!(shared, history);
+    assert_eq!(shared.undo(&mut copy).unwrap(), Some(finish));
+    assert_eq!(copy.current_marking.count(&busy), 1);
+
+    net.reset();
+    assert_eq!(history.undo(&mut net), Err(HistoryError::Diverged { step: 1 }));
+    assert_eq!(history.restore(&mut net, "lunch"), Err(HistoryError::UnknownCheckpoint("lunch".into())));
+}
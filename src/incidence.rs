@@ -0,0 +1,201 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use itertools::Itertools;
+use uuid::Uuid;
+use crate::clade::Clade;
+use crate::marking::Marking;
+use crate::net::ColoredPetriNet;
+use crate::signature::Signature;
+use crate::symbol::Symbol;
+
+/// Elements of a net that an analysis cannot represent, such as transitions with reset arcs,
+/// which an incidence matrix cannot stand for. Leaving them out would make results claim more
+/// than holds, so analyses refuse nets that have them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unsupported {
+    pub transitions: Vec<Uuid>,
+    pub places: Vec<Uuid>,
+    // Why the elements cannot be represented
+    pub reason: &'static str,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut elements = vec![];
+        if !self.transitions.is_empty() {
+            elements.push(format!("{} transition(s)", self.transitions.len()));
+        }
+        if !self.places.is_empty() {
+            elements.push(format!("{} place(s)", self.places.len()));
+        }
+        write!(f, "{} cannot be analysed: {}", elements.join(" and "), self.reason)
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+/// The pre and post matrices of a net unfolded over the leaf clades of its taxonomies.
+///
+/// Rows are (place, clade) pairs and columns are (transition, assignment) pairs, where an
+/// assignment gives each input symbol a clade such that the guard holds and every output place
+/// accepts the tokens it receives. Entry `[i][j]` of `pre` is the number of tokens of row `i`
+/// consumed by column `j`, and of `post` the number produced.
+///
+/// Inhibitor arcs are left out, so a column may fire where the transition could not; the matrix
+/// allows more behaviour than the net, and conclusions drawn from it still hold. Transitions
+/// that can never fire, substitution transitions and those with output symbols no input binds,
+/// have no columns.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Incidence {
+    pub places: Vec<(Uuid, Clade)>,
+    pub transitions: Vec<(Uuid, BTreeMap<Symbol, Clade>)>,
+    pub pre: Vec<Vec<i64>>,
+    pub post: Vec<Vec<i64>>,
+    // Transitions with reset arcs, which have no columns, see `Unsupported`
+    pub unsupported: Vec<Uuid>,
+}
+
+impl Incidence {
+    /// The clades tokens are unfolded over: the leaves of every registered taxonomy, followed by
+    /// any other clade carried by a token of the initial marking.
+    pub fn colors(net: &ColoredPetriNet) -> Vec<Clade> {
+        let mut colors: Vec<Clade> = net.clades.iter().flat_map(|c| c.leaves()).collect();
+        let mut seen: HashSet<Uuid> = colors.iter().map(|c| c.id()).collect();
+        let extra = net
+            .initial_marking
+            .values()
+            .flat_map(|tokens| tokens.values())
+            .map(|t| t.clade.clone())
+            .sorted_by_key(|c| (c.name(), c.id()))
+            .collect_vec();
+        for clade in extra {
+            if seen.insert(clade.id()) {
+                colors.push(clade);
+            }
+        }
+        colors
+    }
+
+    pub fn new(net: &ColoredPetriNet) -> Self {
+        let colors = Self::colors(net);
+        let places = net
+            .places
+            .values()
+            .sorted_by_key(|p| (p.name.clone(), p.id))
+            .flat_map(|p| colors.iter().map(move |c| (p.id, c.clone())))
+            .collect_vec();
+        let row: HashMap<(Uuid, Uuid), usize> = places.iter().enumerate().map(|(i, (p, c))| ((*p, c.id()), i)).collect();
+
+        let mut transitions = vec![];
+        let mut unsupported = vec![];
+        let mut columns: Vec<(Vec<usize>, Vec<usize>)> = vec![];
+        for transition in net.transitions.values().sorted_by_key(|t| (t.name.clone(), t.id)) {
+            let consumed = transition.input.values().flat_map(|s| s.symbols.iter()).collect::<HashSet<_>>();
+            let outputs_bound = transition.output.values().flat_map(|s| s.symbols.iter()).all(|s| consumed.contains(s));
+            if !transition.resets.is_empty() {
+                unsupported.push(transition.id);
+                continue;
+            }
+            if !outputs_bound || transition.substitution.is_some() {
+                continue;
+            }
+            let symbols = transition
+                .input
+                .values()
+                .chain(transition.reads.values())
+                .flat_map(|s| s.symbols.iter().cloned())
+                .sorted()
+                .collect_vec();
+            let assignments: Vec<Vec<&Clade>> = if symbols.is_empty() {
+                vec![vec![]]
+            } else {
+                symbols.iter().map(|_| colors.iter()).multi_cartesian_product().collect()
+            };
+            for assignment in assignments {
+                let assignment: BTreeMap<Symbol, Clade> =
+                    symbols.iter().cloned().zip(assignment.into_iter().cloned()).collect();
+                if !transition.guard.eval(&assignment.clone().into_iter().collect()) {
+                    continue;
+                }
+                let accepted = transition.output.iter().all(|(place, signature)| {
+                    net.places.get(place).is_none_or(|p| signature.symbols.iter().all(|s| p.accepts(&assignment[s])))
+                });
+                if !accepted {
+                    continue;
+                }
+                let arcs = |arcs: &HashMap<Uuid, Signature>| {
+                    arcs.iter()
+                        .flat_map(|(place, signature)| signature.symbols.iter().map(move |s| (*place, s)))
+                        .map(|(place, s)| row[&(place, assignment[s].id())])
+                        .collect_vec()
+                };
+                // A read token is consumed and put back, which leaves the marking unchanged
+                let read = arcs(&transition.reads);
+                columns.push((
+                    arcs(&transition.input).into_iter().chain(read.iter().copied()).collect(),
+                    arcs(&transition.output).into_iter().chain(read).collect(),
+                ));
+                transitions.push((transition.id, assignment));
+            }
+        }
+
+        let mut pre = vec![vec![0; columns.len()]; places.len()];
+        let mut post = vec![vec![0; columns.len()]; places.len()];
+        for (j, (consumed, produced)) in columns.iter().enumerate() {
+            for i in consumed {
+                pre[*i][j] += 1;
+            }
+            for i in produced {
+                post[*i][j] += 1;
+            }
+        }
+        Self { places, transitions, pre, post, unsupported }
+    }
+
+    /// Fails if the net has transitions the matrix leaves out although they can fire.
+    pub fn check(&self) -> Result<(), Unsupported> {
+        if self.unsupported.is_empty() {
+            Ok(())
+        } else {
+            Err(Unsupported { transitions: self.unsupported.clone(), places: vec![], reason: "reset arcs have no incidence matrix" })
+        }
+    }
+
+    /// The incidence matrix `post - pre`.
+    pub fn matrix(&self) -> Vec<Vec<i64>> {
+        self.pre
+            .iter()
+            .zip(self.post.iter())
+            .map(|(pre, post)| pre.iter().zip(post.iter()).map(|(a, b)| b - a).collect())
+            .collect()
+    }
+
+    /// The number of tokens of each row's clade in each row's place.
+    pub fn marking(&self, marking: &Marking) -> Vec<i64> {
+        self.places
+            .iter()
+            .map(|(place, clade)| {
+                marking
+                    .get(place)
+                    .map(|tokens| tokens.values().filter(|t| t.clade.id() == clade.id()).count() as i64)
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+
+    /// Readable name of a row, e.g. `idle.robot1`.
+    pub fn place_name(&self, net: &ColoredPetriNet, row: usize) -> String {
+        let (place, clade) = &self.places[row];
+        format!("{}.{}", net.places.get(place).map(|p| p.name.clone()).unwrap_or_default(), clade.name())
+    }
+
+    /// Readable name of a column, e.g. `start[x=robot1]`.
+    pub fn transition_name(&self, net: &ColoredPetriNet, column: usize) -> String {
+        let (transition, assignment) = &self.transitions[column];
+        format!(
+            "{}[{}]",
+            net.transitions.get(transition).map(|t| t.name.clone()).unwrap_or_default(),
+            assignment.iter().map(|(s, c)| format!("{}={}", s, c.name())).join(",")
+        )
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use itertools::Itertools;
+use uuid::Uuid;
+use crate::incidence::{Incidence, Unsupported};
+use crate::net::ColoredPetriNet;
+
+/// A semi-positive invariant, given by its non-zero weights.
+///
+/// For a P-invariant the keys are rows of the unfolded incidence matrix (place and clade), and
+/// the weighted token count over them never changes. For a T-invariant the keys are columns
+/// (transition and assignment), and firing each column that many times leaves a marking unchanged.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Invariant {
+    pub weights: BTreeMap<usize, u64>,
+}
+
+impl Invariant {
+    pub fn support(&self) -> HashSet<usize> {
+        self.weights.keys().copied().collect()
+    }
+
+    /// The weighted sum of a vector, e.g. the constant token count of a P-invariant.
+    pub fn apply(&self, vector: &[i64]) -> i64 {
+        self.weights.iter().map(|(i, w)| *w as i64 * vector[*i]).sum()
+    }
+}
+
+/// Computes a generating set of the minimal semi-positive solutions `y >= 0` of `y * matrix = 0`
+/// with the Farkas algorithm. Each solution has one entry per row of the matrix.
+pub fn farkas(matrix: &[Vec<i64>]) -> Vec<Vec<i64>> {
+    let rows = matrix.len();
+    let columns = matrix.first().map(|r| r.len()).unwrap_or(0);
+    // Each row is the remaining part of the matrix followed by the identity
+    let mut tableau: Vec<Vec<i64>> = matrix
+        .iter()
+        .enumerate()
+        .map(|(i, row)| row.iter().copied().chain((0..rows).map(|k| (k == i) as i64)).collect())
+        .collect();
+    for column in 0..columns {
+        let mut next: Vec<Vec<i64>> = tableau.iter().filter(|r| r[column] == 0).cloned().collect();
+        let positive = tableau.iter().filter(|r| r[column] > 0).collect_vec();
+        let negative = tableau.iter().filter(|r| r[column] < 0).collect_vec();
+        for p in &positive {
+            for n in &negative {
+                let (a, b) = (-n[column], p[column]);
+                let combined = p.iter().zip(n.iter()).map(|(x, y)| a * x + b * y).collect_vec();
+                next.push(normalize(combined));
+            }
+        }
+        // Keep only rows whose support is minimal, which also removes duplicates
+        let supports = next.iter().map(|r| support(&r[columns..])).collect_vec();
+        tableau = next
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| {
+                !supports.iter().enumerate().any(|(k, s)| {
+                    k != *i && s.is_subset(&supports[*i]) && (s.len() < supports[*i].len() || k < *i)
+                })
+            })
+            .map(|(_, r)| r.clone())
+            .collect();
+    }
+    tableau.into_iter().map(|r| r[columns..].to_vec()).collect()
+}
+
+fn support(row: &[i64]) -> HashSet<usize> {
+    row.iter().enumerate().filter(|(_, v)| **v != 0).map(|(i, _)| i).collect()
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn normalize(row: Vec<i64>) -> Vec<i64> {
+    let divisor = row.iter().fold(0, |g, v| gcd(g, *v));
+    if divisor > 1 {
+        row.into_iter().map(|v| v / divisor).collect()
+    } else {
+        row
+    }
+}
+
+fn transpose(matrix: &[Vec<i64>], columns: usize) -> Vec<Vec<i64>> {
+    (0..columns).map(|j| matrix.iter().map(|row| row[j]).collect()).collect()
+}
+
+fn to_invariant(solution: Vec<i64>) -> Invariant {
+    Invariant {
+        weights: solution.into_iter().enumerate().filter(|(_, w)| *w > 0).map(|(i, w)| (i, w as u64)).collect(),
+    }
+}
+
+/// Structural analysis of a net: its P- and T-invariants and the places they prove bounded.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InvariantAnalysis {
+    pub incidence: Incidence,
+    pub p_invariants: Vec<Invariant>,
+    pub t_invariants: Vec<Invariant>,
+}
+
+impl InvariantAnalysis {
+    pub fn new(net: &ColoredPetriNet) -> Self {
+        let incidence = Incidence::new(net);
+        let matrix = incidence.matrix();
+        let p_invariants = farkas(&matrix).into_iter().map(to_invariant).collect();
+        let t_invariants = farkas(&transpose(&matrix, incidence.transitions.len())).into_iter().map(to_invariant).collect();
+        Self { incidence, p_invariants, t_invariants }
+    }
+
+    /// Rows of the incidence matrix in the support of some P-invariant. Since every P-invariant
+    /// is semi-positive, the token count of these rows is structurally bounded.
+    pub fn covered(&self) -> HashSet<usize> {
+        self.p_invariants.iter().flat_map(|i| i.support()).collect()
+    }
+
+    /// True if every row is covered, i.e. the net is conservative.
+    pub fn covered_by_p_invariants(&self) -> bool {
+        self.covered().len() == self.incidence.places.len()
+    }
+
+    /// True if every column is in the support of some T-invariant.
+    pub fn covered_by_t_invariants(&self) -> bool {
+        let covered: HashSet<usize> = self.t_invariants.iter().flat_map(|i| i.support()).collect();
+        covered.len() == self.incidence.transitions.len()
+    }
+
+    /// Places of the net whose rows are all covered, i.e. places that are structurally bounded.
+    /// Fails for nets with reset arcs, for which invariants of the matrix prove nothing.
+    pub fn bounded_places(&self) -> Result<Vec<Uuid>, Unsupported> {
+        self.incidence.check()?;
+        let covered = self.covered();
+        Ok(self.incidence
+            .places
+            .iter()
+            .enumerate()
+            .map(|(i, (place, _))| (*place, covered.contains(&i)))
+            .into_group_map()
+            .into_iter()
+            .filter(|(_, covered)| covered.iter().all(|c| *c))
+            .map(|(place, _)| place)
+            .sorted()
+            .collect())
+    }
+
+    /// The smallest bound a P-invariant gives on the token count of a row, starting from the
+    /// initial marking of the net. Fails for nets with reset arcs.
+    pub fn bound(&self, net: &ColoredPetriNet, row: usize) -> Result<Option<u64>, Unsupported> {
+        self.incidence.check()?;
+        let marking = self.incidence.marking(&net.initial_marking);
+        Ok(self
+            .p_invariants
+            .iter()
+            .filter_map(|i| i.weights.get(&row).map(|w| i.apply(&marking) as u64 / w))
+            .min())
+    }
+
+    /// Describes the invariants with place and transition names.
+    pub fn describe<'a>(&'a self, net: &'a ColoredPetriNet) -> InvariantDescription<'a> {
+        InvariantDescription { analysis: self, net }
+    }
+}
+
+/// Human-readable listing of an [`InvariantAnalysis`].
+pub struct InvariantDescription<'a> {
+    analysis: &'a InvariantAnalysis,
+    net: &'a ColoredPetriNet,
+}
+
+impl fmt::Display for InvariantDescription<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let incidence = &self.analysis.incidence;
+        let marking = incidence.marking(&self.net.initial_marking);
+        let term = |w: &u64, name: String| if *w == 1 { name } else { format!("{}*{}", w, name) };
+        writeln!(f, "P-invariants:")?;
+        for invariant in &self.analysis.p_invariants {
+            let sum = invariant.weights.iter().map(|(i, w)| term(w, incidence.place_name(self.net, *i))).join(" + ");
+            writeln!(f, "  {} = {}", sum, invariant.apply(&marking))?;
+        }
+        writeln!(f, "T-invariants:")?;
+        for invariant in &self.analysis.t_invariants {
+            let sum = invariant.weights.iter().map(|(i, w)| term(w, incidence.transition_name(self.net, *i))).join(" + ");
+            writeln!(f, "  {}", sum)?;
+        }
+        if !incidence.unsupported.is_empty() {
+            let mut names = incidence.unsupported.iter().filter_map(|t| self.net.transitions.get(t)).map(|t| t.name.clone()).sorted();
+            writeln!(f, "Not sound, reset arcs are not analysed: {}", names.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+pub fn robots_are_conserved() {
+    use std::collections::{HashMap, HashSet};
+    use crate::clade::Clade;
+    use crate::place::Place;
+    use crate::signature::Signature;
+    use crate::token::Token;
+    use crate::transition::Transition;
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let robot2 = Clade::new("robot2".into(), None);
+    let robot = Clade::new("robot".into(), Some(vec![robot1.clone(), robot2.clone()]));
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    net.add_clade(robot.clone());
+    let idle = net.add_place(Place::new("idle".into()));
+    let busy = net.add_place(Place::new("busy".into()));
+    let log = net.add_place(Place::new("log".into()));
+    net.add_token(idle, Token::new("r1".into(), robot1.clone()));
+    net.add_token(idle, Token::new("r2".into(), robot2.clone()));
+    let sig = |s: &str| Signature::new(HashSet::from([s.into()]));
+    net.add_transition(Transition::new("start".into(), Some(HashMap::from([(idle, sig("x"))])), Some(HashMap::from([(busy, sig("x"))])), None, None));
+    net.add_transition(Transition::new("stop".into(), Some(HashMap::from([(busy, sig("x"))])), Some(HashMap::from([(idle, sig("x"))])), None, None));
+    // Takes the robot from idle and puts it back there and in the log; the matrix counts a log
+    // token on every firing, so no invariant covers the log
+    net.add_transition(Transition::new(
+        "record".into(),
+        Some(HashMap::from([(idle, sig("x"))])),
+        Some(HashMap::from([(idle, sig("x")), (log, sig("x"))])),
+        None,
+        None,
+    ));
+
+    let analysis = InvariantAnalysis::new(&net);
+    assert_eq!(analysis.incidence.places.len(), 6);
+    assert_eq!(analysis.incidence.transitions.len(), 6);
+    // One invariant per robot: idle.r + busy.r = 1
+    assert_eq!(analysis.p_invariants.len(), 2);
+    for invariant in &analysis.p_invariants {
+        assert_eq!(invariant.weights.len(), 2);
+        assert_eq!(invariant.apply(&analysis.incidence.marking(&net.initial_marking)), 1);
+    }
+    assert_eq!(analysis.bounded_places().unwrap(), [idle, busy].into_iter().sorted().collect_vec());
+    assert!(!analysis.covered_by_p_invariants());
+    assert_eq!(analysis.bound(&net, 0), Ok(Some(1)));
+    // start followed by stop, for each robot
+    assert_eq!(analysis.t_invariants.len(), 2);
+    assert!(!analysis.covered_by_t_invariants());
+    let description = analysis.describe(&net).to_string();
+    assert!(description.contains("busy.robot1 + idle.robot1 = 1"));
+}
+
+#[test]
+pub fn inhibitors_and_resets() {
+    use std::collections::{HashMap, HashSet};
+    use crate::clade::Clade;
+    use crate::coverability::CoverabilityTree;
+    use crate::place::Place;
+    use crate::signature::Signature;
+    use crate::statespace::StateSpace;
+    use crate::token::Token;
+    use crate::transition::Transition;
+    use crate::unfold::unfold;
+
+    let part = Clade::new("part".into(), None);
+    let mut net = ColoredPetriNet::new("line".into(), None, None, None);
+    net.add_clade(part.clone());
+    let a = net.add_place(Place::new("a".into()));
+    let b = net.add_place(Place::new("b".into()));
+    let c = net.add_place(Place::new("c".into()));
+    let d = net.add_place(Place::new("d".into()));
+    net.add_token(a, Token::new("p".into(), part.clone()));
+    let arc = |place| Some(HashMap::from([(place, Signature::new(HashSet::from(["x".into()])))]));
+    net.add_transition(Transition::new("t1".into(), arc(a), arc(b), None, None));
+    let t2 = net.add_transition(Transition::new("t2".into(), arc(b), arc(c), None, None).with_inhibitor(d, None));
+
+    // The inhibitor on the empty place d never blocks t2, so c gets the part
+    let space = StateSpace::explore(&net, None);
+    assert!(space.markings.iter().any(|m| m.count(&c) == 1));
+    let analysis = InvariantAnalysis::new(&net);
+    assert!(analysis.incidence.unsupported.is_empty());
+    assert_eq!(analysis.bounded_places().unwrap(), [a, b, c, d].into_iter().sorted().collect_vec());
+    let row = analysis.incidence.places.iter().position(|(p, _)| *p == c).unwrap();
+    assert_eq!(analysis.bound(&net, row), Ok(Some(1)));
+    // The coverability tree counts tokens exactly, which the inhibitor would break
+    assert_eq!(CoverabilityTree::build(&net, None).map_err(|e| e.transitions), Err(vec![t2]));
+
+    // A reset arc cannot be put in the matrix, so the analyses refuse the net
+    net.transitions.get_mut(&t2).unwrap().resets.insert(a);
+    let analysis = InvariantAnalysis::new(&net);
+    let unsupported = analysis.incidence.check().unwrap_err();
+    assert_eq!(unsupported.transitions, vec![t2]);
+    assert_eq!(analysis.bounded_places(), Err(unsupported.clone()));
+    assert_eq!(analysis.bound(&net, row), Err(unsupported.clone()));
+    assert_eq!(unfold(&net), Err(unsupported.clone()));
+    assert_eq!(CoverabilityTree::build(&net, None), Err(unsupported));
+    assert!(analysis.describe(&net).to_string().contains("Not sound, reset arcs are not analysed: t2"));
+}
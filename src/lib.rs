@@ -1,9 +1,34 @@
+pub mod action;
 pub mod aliases;
+pub mod binding;
 pub mod clade;
+pub mod compose;
+pub mod coverability;
+pub mod enabling;
+pub mod format;
 pub mod function;
 pub mod guard;
+pub mod hierarchy;
+pub mod history;
+pub mod incidence;
+pub mod invariants;
+pub mod marking;
+pub mod modelcheck;
+pub mod net;
+pub mod observer;
+pub mod parallel;
+pub mod partialorder;
 pub mod place;
+pub mod pnml;
+pub mod query;
+pub mod render;
+pub mod repl;
 pub mod signature;
+pub mod simulation;
+pub mod snapshot;
+pub mod statespace;
 pub mod symbol;
+pub mod symmetry;
 pub mod token;
-pub mod transition;
\ No newline at end of file
+pub mod transition;
+pub mod unfold;
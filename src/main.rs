@@ -1,3 +1,459 @@
-fn main() {
-    println!("Hello, world!");
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::process::ExitCode;
+use colorpnet::format;
+use colorpnet::hierarchy::flatten;
+use colorpnet::modelcheck::ModelChecker;
+use colorpnet::net::{ColoredPetriNet, Severity};
+use colorpnet::parallel::{self, Parallelism};
+use colorpnet::partialorder::{self, PartialOrder};
+use colorpnet::pnml::{from_pnml, to_pnml};
+use colorpnet::query::{Ctl, QueryError};
+use colorpnet::render::{to_dot, to_mermaid, RenderOptions, TokenLabel};
+use colorpnet::repl::Repl;
+use colorpnet::simulation::{ConflictPolicy, Simulation};
+use colorpnet::snapshot::{self, Snapshot};
+use colorpnet::statespace::{marking_summary, StateSpace};
+use colorpnet::symmetry;
+
+// Exit codes, so that scripts can tell a failed check from a broken invocation
+const CHECK_FAILED: u8 = 1;
+const USAGE: u8 = 2;
+const INPUT: u8 = 3;
+
+const HELP: &str = "\
+Usage: colorpnet <command> [options]
+
+Commands:
+  validate <net> [--strict]                 Check the net for errors, and with --strict for warnings
+  simulate <net> [--seed N] [--steps N]     Fire randomly chosen transitions
+  explore <net> [--limit N] [--threads N | --symmetry | --partial-order] [--forbid-deadlocks]
+          [--snapshot FILE]                 Explore the reachable markings, saving them if asked;
+                                            --symmetry merges markings that differ only in which
+                                            tokens of a clade are where, and --partial-order skips
+                                            reorderings of independent firings
+  plan <net> <goal> [--limit N] [--threads N | --symmetry | --partial-order] [--snapshot FILE]
+                                            Find a shortest firing sequence reaching the goal,
+                                            e.g. 'has(done, part) & count(idle) >= 2', in the
+                                            markings saved by explore if given; a reduced
+                                            search may find a longer one
+  render <net> [--format dot|mermaid] [--output FILE]
+                                            Draw the net
+  convert <input> <output> [--from FORMAT] [--to FORMAT]
+                                            Convert between json and pnml, or to dot and mermaid
+  repl <net>                                Step through the net interactively
+  schema [--output FILE]                    Print the JSON Schema of the net file format
+
+Nets are read as PNML if the file ends in .pnml or .xml, and as JSON otherwise. JSON files
+written by older versions are migrated when read; convert writes the current version.
+
+Exit codes:
+  0  success
+  1  the check failed: errors found, deadlocks forbidden but found, no plan, or too many markings
+  2  invalid command line
+  3  unreadable or invalid net file
+";
+
+// Options that take a value; any other option is a flag
+const VALUED: &[&str] = &["seed", "steps", "limit", "format", "output", "from", "to", "snapshot", "threads"];
+
+#[derive(Debug)]
+enum CliError {
+    Usage(String),
+    Input(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Usage(message) => write!(f, "{}\n\n{}", message, HELP),
+            CliError::Input(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Args, CliError> {
+        let mut parsed = Args::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if VALUED.contains(&name) => {
+                    let value = args.next().ok_or_else(|| CliError::Usage(format!("--{} needs a value", name)))?;
+                    parsed.options.insert(name.to_string(), value.clone());
+                }
+                Some(name) => parsed.flags.push(name.to_string()),
+                None => parsed.positional.push(arg.clone()),
+            }
+        }
+        Ok(parsed)
+    }
+
+    // Fails on options the command does not know
+    fn allow(&self, options: &[&str]) -> Result<(), CliError> {
+        match self.options.keys().chain(self.flags.iter()).find(|o| !options.contains(&o.as_str())) {
+            Some(option) => Err(CliError::Usage(format!("unknown option --{}", option))),
+            None => Ok(()),
+        }
+    }
+
+    fn positional(&self, count: usize) -> Result<&[String], CliError> {
+        match self.positional.len() {
+            n if n < count => Err(CliError::Usage("missing argument".to_string())),
+            n if n > count => Err(CliError::Usage(format!("unexpected argument '{}'", self.positional[count]))),
+            _ => Ok(&self.positional),
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, CliError> {
+        self.options
+            .get(name)
+            .map(|value| value.parse().map_err(|_| CliError::Usage(format!("--{} expects a number, not '{}'", name, value))))
+            .transpose()
+    }
+}
+
+// The format of a net file, from an explicit option or else from its extension
+fn format_of(path: &str, explicit: Option<&String>) -> String {
+    explicit.cloned().unwrap_or_else(|| {
+        match Path::new(path).extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref() {
+            Some("pnml") | Some("xml") => "pnml".to_string(),
+            Some("dot") | Some("gv") => "dot".to_string(),
+            Some("mmd") | Some("mermaid") => "mermaid".to_string(),
+            _ => "json".to_string(),
+        }
+    })
+}
+
+fn load(path: &str, format: &str) -> Result<ColoredPetriNet, CliError> {
+    let text = fs::read_to_string(path).map_err(|e| CliError::Input(format!("cannot read {}: {}", path, e)))?;
+    match format {
+        "json" => format::from_json(&text).map_err(|e| CliError::Input(format!("{} is not a valid net: {}", path, e))),
+        "pnml" => {
+            let (net, report) = from_pnml(&text).map_err(|e| CliError::Input(format!("{} is not valid PNML: {}", path, e)))?;
+            for issue in &report.issues {
+                eprintln!("warning: {}: {}", issue.element, issue.message);
+            }
+            Ok(net)
+        }
+        other => Err(CliError::Usage(format!("cannot read nets in {} format", other))),
+    }
+}
+
+// Nets with substitution transitions are run as their flattened equivalent
+fn runnable(net: ColoredPetriNet) -> Result<ColoredPetriNet, CliError> {
+    if net.transitions.values().any(|t| t.substitution.is_some()) {
+        flatten(&net).map_err(|e| CliError::Input(e.to_string()))
+    } else {
+        Ok(net)
+    }
+}
+
+fn validate(args: &Args) -> Result<u8, CliError> {
+    args.allow(&["strict"])?;
+    let path = &args.positional(1)?[0];
+    let net = load(path, &format_of(path, None))?;
+    let mut issues = net.validate().into_iter().map(|i| (i.severity, i.to_string())).collect::<Vec<_>>();
+    if let Err(error) = runnable(net) {
+        issues.push((Severity::Error, format!("error: {}", error)));
+    }
+    for (_, issue) in &issues {
+        println!("{}", issue);
+    }
+    let strict = args.flags.iter().any(|f| f == "strict");
+    if issues.is_empty() {
+        println!("{}: no issues", path);
+    }
+    Ok(if issues.iter().any(|(severity, _)| strict || *severity == Severity::Error) { CHECK_FAILED } else { 0 })
+}
+
+fn simulate(args: &Args) -> Result<u8, CliError> {
+    args.allow(&["seed", "steps"])?;
+    let path = &args.positional(1)?[0];
+    let mut net = runnable(load(path, &format_of(path, None))?)?;
+    let mut simulation = Simulation::new(ConflictPolicy::random(args.number("seed")?.unwrap_or(0)));
+    let steps = args.number("steps")?.unwrap_or(100);
+    for step in 1..=steps {
+        match simulation.step(&mut net).map_err(|e| CliError::Input(e.to_string()))? {
+            Some((transition, binding)) => println!("{}. {}({})", step, net.transitions[&transition].name, binding),
+            None => {
+                println!("deadlock after {} steps", step - 1);
+                break;
+            }
+        }
+    }
+    println!("{}", marking_summary(&net, &net.current_marking));
+    Ok(0)
+}
+
+// Explores on the given number of threads, numbering markings as a single thread would, up to
+// symmetry, or with partial-order reduction keeping the dead markings and the formula if given
+fn reachable(net: &ColoredPetriNet, args: &Args, formula: Option<&Ctl>) -> Result<StateSpace, CliError> {
+    let limit = args.number("limit")?;
+    let threads = args.number("threads")?;
+    let flag = |name: &str| args.flags.iter().any(|f| f == name);
+    let (symmetric, reduced) = (flag("symmetry"), flag("partial-order"));
+    if [threads.is_some(), symmetric, reduced].iter().filter(|on| **on).count() > 1 {
+        return Err(CliError::Usage("only one of --threads, --symmetry and --partial-order can be given".to_string()));
+    }
+    Ok(match threads {
+        Some(threads) => parallel::explore(net, limit, Parallelism::new(threads).deterministic()),
+        None if symmetric => symmetry::explore(net, limit),
+        None if reduced => {
+            let order = match formula {
+                Some(formula) => PartialOrder::preserving(net, formula).map_err(|e| CliError::Usage(format!("invalid goal: {}", e)))?,
+                None => PartialOrder::new(net),
+            };
+            partialorder::explore(net, limit, &order)
+        }
+        None => StateSpace::explore(net, limit),
+    })
+}
+
+fn explore(args: &Args) -> Result<u8, CliError> {
+    args.allow(&["limit", "threads", "symmetry", "partial-order", "forbid-deadlocks", "snapshot"])?;
+    let path = &args.positional(1)?[0];
+    let net = runnable(load(path, &format_of(path, None))?)?;
+    let space = reachable(&net, args, None)?;
+    if let Some(file) = args.options.get("snapshot") {
+        let out = BufWriter::new(fs::File::create(file).map_err(|e| CliError::Input(format!("cannot write {}: {}", file, e)))?);
+        snapshot::write(&space, &net, out).map_err(|e| CliError::Input(format!("cannot write {}: {}", file, e)))?;
+    }
+    let dead = space.dead();
+    println!("markings: {}", space.markings.len());
+    println!("edges: {}", space.edges.len());
+    println!("deadlocks: {}", dead.len());
+    for marking in &dead {
+        println!("  {}", marking_summary(&net, &space.markings[*marking]));
+    }
+    if space.truncated() {
+        println!("exploration stopped at the limit; {} markings were not expanded", space.markings.len() - space.expanded);
+    }
+    Ok(if !dead.is_empty() && args.flags.iter().any(|f| f == "forbid-deadlocks") { CHECK_FAILED } else { 0 })
+}
+
+fn plan(args: &Args) -> Result<u8, CliError> {
+    args.allow(&["limit", "threads", "symmetry", "partial-order", "snapshot"])?;
+    let positional = args.positional(2)?;
+    let net = runnable(load(&positional[0], &format_of(&positional[0], None))?)?;
+    let goal = Ctl::parse(&positional[1]).map_err(|e| CliError::Usage(format!("invalid goal: {}", e)))?;
+    let reach = Ctl::EF(Box::new(goal));
+    let space = match args.options.get("snapshot") {
+        Some(file) => {
+            let bytes = fs::read(file).map_err(|e| CliError::Input(format!("cannot read {}: {}", file, e)))?;
+            let snapshot = Snapshot::read(&bytes).map_err(|e| CliError::Input(format!("{}: {}", file, e)))?;
+            snapshot.to_state_space().map_err(|e| CliError::Input(format!("{}: {}", file, e)))?
+        }
+        None => reachable(&net, args, Some(&reach))?,
+    };
+    let checker = match ModelChecker::from_space(&net, space) {
+        Ok(checker) => checker,
+        Err(QueryError::Truncated) => {
+            println!("the state space exceeds the exploration limit");
+            return Ok(CHECK_FAILED);
+        }
+        Err(error) => return Err(CliError::Input(error.to_string())),
+    };
+    let result = checker.check_ctl(&reach).map_err(|e| CliError::Usage(format!("invalid goal: {}", e)))?;
+    match result.trace.filter(|_| result.holds) {
+        Some(trace) => {
+            // Edges of a reduced space may bind other tokens of the same clades than the net has
+            let steps = symmetry::concretize(&net, &checker.space, &trace.prefix)
+                .ok_or_else(|| CliError::Input("the saved markings do not belong to this net".to_string()))?;
+            for (step, (transition, binding, _)) in steps.iter().enumerate() {
+                println!("{}. {}({})", step + 1, net.transitions[transition].name, binding);
+            }
+            if trace.prefix.is_empty() {
+                println!("the goal already holds");
+            }
+            Ok(0)
+        }
+        None => {
+            println!("no reachable marking satisfies the goal");
+            Ok(CHECK_FAILED)
+        }
+    }
+}
+
+fn draw(net: &ColoredPetriNet, format: &str) -> Result<String, CliError> {
+    let options = RenderOptions { tokens: TokenLabel::Names, highlight_marking: true, highlight_enabled: true, ..Default::default() };
+    let render = match format {
+        "dot" => to_dot,
+        "mermaid" => to_mermaid,
+        other => return Err(CliError::Usage(format!("cannot render to {} format", other))),
+    };
+    if let Some(issue) = net.validate().into_iter().find(|i| i.severity == Severity::Error) {
+        return Err(CliError::Input(format!("cannot render a net with errors, e.g. {}", issue.message)));
+    }
+    Ok(render(net, &options))
+}
+
+fn write(path: Option<&String>, text: &str) -> Result<(), CliError> {
+    match path {
+        Some(path) => fs::write(path, text).map_err(|e| CliError::Input(format!("cannot write {}: {}", path, e))),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+fn render(args: &Args) -> Result<u8, CliError> {
+    args.allow(&["format", "output"])?;
+    let path = &args.positional(1)?[0];
+    let net = load(path, &format_of(path, None))?;
+    let format = args.options.get("format").cloned().unwrap_or_else(|| "dot".to_string());
+    write(args.options.get("output"), &draw(&net, &format)?)?;
+    Ok(0)
+}
+
+fn convert(args: &Args) -> Result<u8, CliError> {
+    args.allow(&["from", "to"])?;
+    let positional = args.positional(2)?;
+    let (input, output) = (&positional[0], &positional[1]);
+    let net = load(input, &format_of(input, args.options.get("from")))?;
+    let text = match format_of(output, args.options.get("to")).as_str() {
+        "json" => format::to_json(&net),
+        "pnml" => {
+            let (xml, report) = to_pnml(&net);
+            for issue in &report.issues {
+                eprintln!("warning: {}: {}", issue.element, issue.message);
+            }
+            xml
+        }
+        other => draw(&net, other)?,
+    };
+    write(Some(output), &text)?;
+    Ok(0)
+}
+
+fn repl(args: &Args) -> Result<u8, CliError> {
+    args.allow(&[])?;
+    let path = &args.positional(1)?[0];
+    let net = runnable(load(path, &format_of(path, None))?)?;
+    Repl::new(net).run(io::stdin().lock(), &mut io::stdout()).map_err(|e| CliError::Input(e.to_string()))?;
+    Ok(0)
+}
+
+fn schema(args: &Args) -> Result<u8, CliError> {
+    args.allow(&["output"])?;
+    args.positional(0)?;
+    let text = serde_json::to_string_pretty(&format::schema()).map_err(|e| CliError::Input(e.to_string()))?;
+    write(args.options.get("output"), &format!("{}\n", text))?;
+    Ok(0)
+}
+
+fn run(args: &[String]) -> Result<u8, CliError> {
+    let (command, rest) = args.split_first().ok_or_else(|| CliError::Usage("missing command".to_string()))?;
+    let args = Args::parse(rest)?;
+    match command.as_str() {
+        "validate" => validate(&args),
+        "simulate" => simulate(&args),
+        "explore" => explore(&args),
+        "plan" => plan(&args),
+        "render" => render(&args),
+        "convert" => convert(&args),
+        "repl" => repl(&args),
+        "schema" => schema(&args),
+        "help" | "--help" | "-h" => {
+            print!("{}", HELP);
+            Ok(0)
+        }
+        other => Err(CliError::Usage(format!("unknown command '{}'", other))),
+    }
+}
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match run(&args) {
+        Ok(code) => ExitCode::from(code),
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::from(match error {
+                CliError::Usage(_) => USAGE,
+                CliError::Input(_) => INPUT,
+            })
+        }
+    }
+}
+
+#[test]
+fn exit_codes() {
+    use std::collections::HashSet;
+    use colorpnet::clade::Clade;
+    use colorpnet::place::Place;
+    use colorpnet::signature::Signature;
+    use colorpnet::token::Token;
+    use colorpnet::transition::Transition;
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    net.add_clade(Clade::new("robot".into(), Some(vec![robot1.clone()])));
+    let idle = net.add_place(Place::new("idle".into()));
+    let done = net.add_place(Place::new("done".into()));
+    net.add_token(idle, Token::new("r1".into(), robot1.clone()));
+    let sig = || Signature::new(HashSet::from(["x".into()]));
+    net.add_transition(Transition::new("work".into(), Some(HashMap::from([(idle, sig())])), Some(HashMap::from([(done, sig())])), None, None));
+
+    let dir = std::env::temp_dir().join(format!("colorpnet-cli-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+    fs::write(path("cell.json"), format::to_json(&net)).unwrap();
+    fs::write(path("legacy.json"), serde_json::to_string(&net).unwrap()).unwrap();
+    fs::write(path("broken.json"), "{").unwrap();
+    let mut dangling = net.clone();
+    let work = dangling.id_of("work").unwrap();
+    dangling.transitions.get_mut(&work).unwrap().reads.insert(uuid::Uuid::new_v4(), sig());
+    fs::write(path("dangling.json"), format::to_json(&dangling)).unwrap();
+    let run = |line: &str| {
+        let args = line.split('|').map(|a| a.replace("DIR", &dir.to_string_lossy())).collect::<Vec<_>>();
+        match run(&args) {
+            Ok(code) => code,
+            Err(CliError::Usage(_)) => USAGE,
+            Err(CliError::Input(_)) => INPUT,
+        }
+    };
+
+    assert_eq!(run("validate|DIR/cell.json"), 0);
+    assert_eq!(run("validate|DIR/cell.json|--strict"), 0);
+    assert_eq!(run("simulate|DIR/cell.json|--seed|3|--steps|5"), 0);
+    assert_eq!(run("explore|DIR/cell.json"), 0);
+    assert_eq!(run("explore|DIR/cell.json|--forbid-deadlocks"), CHECK_FAILED);
+    assert_eq!(run("explore|DIR/cell.json|--threads|4|--forbid-deadlocks"), CHECK_FAILED);
+    assert_eq!(run("plan|DIR/cell.json|has(done, robot)"), 0);
+    assert_eq!(run("plan|DIR/cell.json|has(done, robot)|--symmetry"), 0);
+    assert_eq!(run("explore|DIR/cell.json|--symmetry|--threads|2"), USAGE);
+    assert_eq!(run("explore|DIR/cell.json|--partial-order|--forbid-deadlocks"), CHECK_FAILED);
+    assert_eq!(run("plan|DIR/cell.json|has(done, robot)|--partial-order"), 0);
+    assert_eq!(run("plan|DIR/cell.json|AF has(done, robot)|--partial-order"), USAGE);
+    assert_eq!(run("explore|DIR/cell.json|--snapshot|DIR/cell.cpns"), 0);
+    assert_eq!(run("plan|DIR/cell.json|has(done, robot)|--snapshot|DIR/cell.cpns"), 0);
+    assert_eq!(run("plan|DIR/cell.json|has(done, robot)|--snapshot|DIR/cell.json"), INPUT);
+    assert_eq!(run("plan|DIR/cell.json|count(done) >= 2"), CHECK_FAILED);
+    assert_eq!(run("plan|DIR/cell.json|has(nowhere, robot)"), USAGE);
+    assert_eq!(run("convert|DIR/cell.json|DIR/cell.pnml"), 0);
+    assert_eq!(run("convert|DIR/cell.pnml|DIR/copy.json"), 0);
+    assert_eq!(run("render|DIR/copy.json|--format|mermaid|--output|DIR/cell.mmd"), 0);
+    assert!(fs::read_to_string(path("cell.mmd")).unwrap().contains("idle"));
+    assert_eq!(run("render|DIR/dangling.json"), INPUT);
+    assert_eq!(run("convert|DIR/dangling.json|DIR/dangling.dot"), INPUT);
+    assert_eq!(run("simulate|DIR/cell.json|--steps|many"), USAGE);
+    assert_eq!(run("explore|DIR/cell.json|--depth|3"), USAGE);
+    assert_eq!(run("convert|DIR/legacy.json|DIR/migrated.json"), 0);
+    assert_eq!(format::from_json(&fs::read_to_string(path("migrated.json")).unwrap()).unwrap(), net);
+    assert_eq!(run("schema|--output|DIR/schema.json"), 0);
+    assert_eq!(run("validate|DIR/broken.json"), INPUT);
+    assert_eq!(run("validate|DIR/missing.json"), INPUT);
+    assert_eq!(run("frobnicate"), USAGE);
+    fs::remove_dir_all(&dir).unwrap();
 }
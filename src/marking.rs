@@ -0,0 +1,282 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Add, Deref, DerefMut, Sub};
+use itertools::Itertools;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use uuid::Uuid;
+use crate::clade::Clade;
+use crate::token::Token;
+
+/// The tokens of each place of a net, hashed by place id and then by token id.
+///
+/// Tokens keep their identity, so the tokens of a place form a set; seen by clade, they form a
+/// multiset, which is what [`Marking::covers`] compares. Places without tokens are the same as
+/// places that are left out, both for equality and for hashing. The marking dereferences to the
+/// underlying map for everything else.
+#[derive(Clone, Debug, Default)]
+pub struct Marking(HashMap<Uuid, HashMap<Uuid, Token>>);
+
+impl Marking {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Puts a token into a place.
+    pub fn add_token(&mut self, place: Uuid, token: Token) {
+        self.0.entry(place).or_default().insert(token.id, token);
+    }
+
+    /// Takes a token out of a place, dropping the place once it is empty.
+    pub fn remove_token(&mut self, place: &Uuid, token: &Uuid) -> Option<Token> {
+        let tokens = self.0.get_mut(place)?;
+        let removed = tokens.remove(token);
+        if tokens.is_empty() {
+            self.0.remove(place);
+        }
+        removed
+    }
+
+    /// The tokens of a place, ordered by name and id.
+    pub fn tokens(&self, place: &Uuid) -> Vec<&Token> {
+        self.0.get(place).into_iter().flat_map(|t| t.values()).sorted_by_key(|t| (t.name.clone(), t.id)).collect()
+    }
+
+    /// The places holding at least one token, ordered by id.
+    pub fn places(&self) -> Vec<Uuid> {
+        self.0.iter().filter(|(_, tokens)| !tokens.is_empty()).map(|(place, _)| *place).sorted().collect()
+    }
+
+    /// The number of tokens in a place.
+    pub fn count(&self, place: &Uuid) -> usize {
+        self.0.get(place).map_or(0, |tokens| tokens.len())
+    }
+
+    /// The number of tokens in all places.
+    pub fn total(&self) -> usize {
+        self.0.values().map(|tokens| tokens.len()).sum()
+    }
+
+    /// The number of tokens of a clade or any of its descendants in a place.
+    pub fn count_of(&self, place: &Uuid, clade: &Clade) -> usize {
+        self.0.get(place).map_or(0, |tokens| tokens.values().filter(|t| clade.descendent(&t.clade.id())).count())
+    }
+
+    /// The number of tokens of a clade or any of its descendants in all places.
+    pub fn count_clade(&self, clade: &Clade) -> usize {
+        self.0.keys().map(|place| self.count_of(place, clade)).sum()
+    }
+
+    /// The tokens of a place as a multiset of clades: the number of tokens of each clade, by id.
+    pub fn clades(&self, place: &Uuid) -> BTreeMap<Uuid, usize> {
+        let mut counts = BTreeMap::new();
+        for token in self.0.get(place).into_iter().flat_map(|t| t.values()) {
+            *counts.entry(token.clade.id()).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Whether every token of the other marking is in this marking, in the same place.
+    pub fn includes(&self, other: &Marking) -> bool {
+        other.0.iter().all(|(place, tokens)| {
+            tokens.keys().all(|token| self.0.get(place).is_some_and(|mine| mine.contains_key(token)))
+        })
+    }
+
+    /// Whether every place holds at least as many tokens of each clade as in the other marking,
+    /// regardless of which tokens they are.
+    pub fn covers(&self, other: &Marking) -> bool {
+        other.0.keys().all(|place| {
+            let mine = self.clades(place);
+            other.clades(place).iter().all(|(clade, count)| mine.get(clade).copied().unwrap_or(0) >= *count)
+        })
+    }
+
+    /// Order-independent identity of the marking: token ids per marked place, both sorted.
+    pub fn key(&self) -> Vec<(Uuid, Vec<Uuid>)> {
+        self.0
+            .iter()
+            .filter(|(_, tokens)| !tokens.is_empty())
+            .map(|(place, tokens)| (*place, tokens.keys().copied().sorted().collect_vec()))
+            .sorted()
+            .collect()
+    }
+}
+
+impl Deref for Marking {
+    type Target = HashMap<Uuid, HashMap<Uuid, Token>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Marking {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<'a> IntoIterator for &'a Marking {
+    type Item = (&'a Uuid, &'a HashMap<Uuid, Token>);
+    type IntoIter = std::collections::hash_map::Iter<'a, Uuid, HashMap<Uuid, Token>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl From<HashMap<Uuid, HashMap<Uuid, Token>>> for Marking {
+    fn from(tokens: HashMap<Uuid, HashMap<Uuid, Token>>) -> Self {
+        Self(tokens)
+    }
+}
+
+impl FromIterator<(Uuid, Token)> for Marking {
+    fn from_iter<I: IntoIterator<Item = (Uuid, Token)>>(iter: I) -> Self {
+        let mut marking = Marking::new();
+        for (place, token) in iter {
+            marking.add_token(place, token);
+        }
+        marking
+    }
+}
+
+impl PartialEq for Marking {
+    fn eq(&self, other: &Self) -> bool {
+        let tokens = |m: &Marking| m.0.iter().filter(|(_, t)| !t.is_empty()).map(|(p, t)| (*p, t.clone())).collect::<HashMap<_, _>>();
+        tokens(self) == tokens(other)
+    }
+}
+
+impl Eq for Marking {}
+
+impl Hash for Marking {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// Every token of both markings. A token in both keeps the place it has in the right marking.
+impl Add for &Marking {
+    type Output = Marking;
+
+    fn add(self, other: &Marking) -> Marking {
+        let mut sum = self.clone();
+        for (place, tokens) in &other.0 {
+            for token in tokens.values() {
+                sum.0.values_mut().for_each(|t| {
+                    t.remove(&token.id);
+                });
+                sum.add_token(*place, token.clone());
+            }
+        }
+        sum.0.retain(|_, tokens| !tokens.is_empty());
+        sum
+    }
+}
+
+/// The tokens of the left marking that are not in the same place of the right marking.
+impl Sub for &Marking {
+    type Output = Marking;
+
+    fn sub(self, other: &Marking) -> Marking {
+        let mut difference = self.clone();
+        for (place, tokens) in &other.0 {
+            for token in tokens.keys() {
+                difference.remove_token(place, token);
+            }
+        }
+        difference
+    }
+}
+
+/// Lists the token names of each marked place, e.g. `3f2a9c01{r1,r2} 77b0e5d2{p1}`, with places
+/// shortened to the first eight digits of their id. Use `marking_summary` for place names.
+impl fmt::Display for Marking {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let places = self
+            .places()
+            .into_iter()
+            .map(|place| format!("{}{{{}}}", &place.simple().to_string()[..8], self.tokens(&place).iter().map(|t| &t.name).join(",")))
+            .collect_vec();
+        if places.is_empty() {
+            write!(f, "empty")
+        } else {
+            write!(f, "{}", places.join(" "))
+        }
+    }
+}
+
+// Markings are written as a list of tokens per marked place. The nested maps written by earlier
+// versions are still read.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PlaceTokens {
+    List(Vec<Token>),
+    Map(HashMap<Uuid, Token>),
+}
+
+impl Serialize for Marking {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let places: BTreeMap<Uuid, Vec<&Token>> =
+            self.places().into_iter().map(|place| (place, self.0[&place].values().sorted_by_key(|t| t.id).collect())).collect();
+        places.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Marking {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let places = HashMap::<Uuid, PlaceTokens>::deserialize(deserializer)?;
+        Ok(places
+            .into_iter()
+            .flat_map(|(place, tokens)| {
+                let tokens = match tokens {
+                    PlaceTokens::List(tokens) => tokens,
+                    PlaceTokens::Map(tokens) => tokens.into_values().collect(),
+                };
+                tokens.into_iter().map(move |t| (place, t))
+            })
+            .collect())
+    }
+}
+
+#[test]
+pub fn marking_operations() {
+    use std::collections::HashSet;
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let robot2 = Clade::new("robot2".into(), None);
+    let robot = Clade::new("robot".into(), Some(vec![robot1.clone(), robot2.clone()]));
+    let (idle, busy) = (Uuid::new_v4(), Uuid::new_v4());
+    let (r1, r2, r3) = (Token::new("r1".into(), robot1.clone()), Token::new("r2".into(), robot2.clone()), Token::new("r3".into(), robot1.clone()));
+    let marking: Marking = [(idle, r1.clone()), (idle, r2.clone()), (busy, r3.clone())].into_iter().collect();
+
+    assert_eq!(marking.count(&idle), 2);
+    assert_eq!(marking.total(), 3);
+    assert_eq!(marking.count_of(&idle, &robot1), 1);
+    assert_eq!(marking.count_clade(&robot), 3);
+    assert_eq!(marking.clades(&idle), BTreeMap::from([(robot1.id(), 1), (robot2.id(), 1)]));
+
+    // Subtracting and adding back gives the same marking, and the same hash
+    let part: Marking = [(idle, r2.clone())].into_iter().collect();
+    let rest = &marking - &part;
+    assert_eq!(rest.count(&idle), 1);
+    assert!(marking.includes(&rest) && !rest.includes(&marking));
+    let mut whole = &rest + &part;
+    whole.entry(Uuid::new_v4()).or_default();
+    assert_eq!(whole, marking);
+    assert_eq!(HashSet::from([whole.clone(), marking.clone()]).len(), 1);
+
+    // Covering compares clades, not tokens: another robot1 in place of r1 is as good
+    let other: Marking = [(idle, Token::new("r4".into(), robot1.clone())), (idle, r2.clone())].into_iter().collect();
+    assert!(marking.covers(&other) && !marking.includes(&other));
+    assert!(!rest.covers(&other));
+
+    let json = serde_json::to_string(&marking).unwrap();
+    assert_eq!(serde_json::from_str::<Marking>(&json).unwrap(), marking);
+    let nested = serde_json::to_string(&HashMap::from([(busy, HashMap::from([(r3.id, r3.clone())]))])).unwrap();
+    assert_eq!(serde_json::from_str::<Marking>(&nested).unwrap(), [(busy, r3.clone())].into_iter().collect());
+    assert_eq!(Marking::new().to_string(), "empty");
+    assert!(marking.to_string().contains("{r1,r2}"));
+}
@@ -0,0 +1,611 @@
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use crate::net::ColoredPetriNet;
+use crate::partialorder::{self, PartialOrder};
+use crate::query::{Atom, Ctl, Ltl, QueryError};
+use crate::statespace::{strongly_connected, StateSpace};
+
+/// A firing sequence through the state space, given as indices of its edges.
+///
+/// Finite traces reach a marking of interest. Infinite traces (lassos) repeat `cycle` forever
+/// after `prefix`; an infinite trace with an empty cycle stays in the dead marking it ends in.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trace {
+    pub prefix: Vec<usize>,
+    pub cycle: Vec<usize>,
+    pub infinite: bool,
+}
+
+impl Trace {
+    /// Lists the fired transitions with their bindings, e.g. `start(x=r1) (stop(x=r1) start(x=r1))*`.
+    pub fn describe(&self, net: &ColoredPetriNet, space: &StateSpace) -> String {
+        let step = |e: &usize| {
+            let edge = &space.edges[*e];
+            let name = net.transitions.get(&edge.transition).map(|t| t.name.clone()).unwrap_or_default();
+            format!("{}({})", name, edge.binding)
+        };
+        let mut parts = self.prefix.iter().map(step).collect_vec();
+        if !self.cycle.is_empty() {
+            parts.push(format!("({})*", self.cycle.iter().map(step).join(" ")));
+        } else if self.infinite {
+            parts.push("deadlock".to_string());
+        }
+        parts.join(" ")
+    }
+}
+
+/// The outcome of checking a formula on the initial marking, with a witness or counterexample
+/// trace when one can be given.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckResult {
+    pub holds: bool,
+    pub trace: Option<Trace>,
+}
+
+/// Checks CTL and LTL formulas against the complete reachability graph of a net.
+///
+/// Dead markings are treated as repeating forever, so every path is infinite.
+pub struct ModelChecker<'a> {
+    pub net: &'a ColoredPetriNet,
+    pub space: StateSpace,
+    // Successor markings with the edge leading to them; dead markings loop without an edge
+    successors: Vec<Vec<(usize, Option<usize>)>>,
+    predecessors: Vec<Vec<usize>>,
+    labels: RefCell<HashMap<Atom, Vec<bool>>>,
+}
+
+impl<'a> ModelChecker<'a> {
+    /// Explores the state space of the net from its current marking.
+    pub fn new(net: &'a ColoredPetriNet, limit: Option<usize>) -> Result<Self, QueryError> {
+        Self::from_space(net, StateSpace::explore(net, limit))
+    }
+
+    /// Explores the state space with partial-order reduction, which keeps its answer to the
+    /// formula (and to any formula over the same atoms, of the kinds
+    /// [`PartialOrder::preserving`] accepts) but not necessarily to others.
+    pub fn reduced(net: &'a ColoredPetriNet, limit: Option<usize>, formula: &Ctl) -> Result<Self, QueryError> {
+        let order = PartialOrder::preserving(net, formula)?;
+        Self::from_space(net, partialorder::explore(net, limit, &order))
+    }
+
+    pub fn from_space(net: &'a ColoredPetriNet, space: StateSpace) -> Result<Self, QueryError> {
+        if space.truncated() {
+            return Err(QueryError::Truncated);
+        }
+        let n = space.markings.len();
+        let mut successors: Vec<Vec<(usize, Option<usize>)>> = vec![vec![]; n];
+        let mut predecessors = vec![vec![]; n];
+        for (i, edge) in space.edges.iter().enumerate() {
+            successors[edge.source].push((edge.target, Some(i)));
+            predecessors[edge.target].push(edge.source);
+        }
+        for marking in 0..n {
+            if successors[marking].is_empty() {
+                successors[marking].push((marking, None));
+                predecessors[marking].push(marking);
+            }
+        }
+        Ok(Self { net, space, successors, predecessors, labels: RefCell::new(HashMap::new()) })
+    }
+
+    fn label(&self, atom: &Atom) -> Result<Vec<bool>, QueryError> {
+        if let Some(label) = self.labels.borrow().get(atom) {
+            return Ok(label.clone());
+        }
+        let proposition = atom.resolve(self.net)?;
+        let label = self.space.markings.iter().map(|m| proposition.eval(self.net, m)).collect_vec();
+        self.labels.borrow_mut().insert(atom.clone(), label.clone());
+        Ok(label)
+    }
+
+    fn pre_exists(&self, target: &[bool]) -> Vec<bool> {
+        (0..target.len()).map(|s| self.successors[s].iter().any(|(t, _)| target[*t])).collect()
+    }
+
+    // Least fixpoint of E[left U right], computed backwards from the right states
+    fn exists_until(&self, left: &[bool], right: &[bool]) -> Vec<bool> {
+        let mut result = right.to_vec();
+        let mut queue: VecDeque<usize> = (0..right.len()).filter(|s| right[*s]).collect();
+        while let Some(state) = queue.pop_front() {
+            for pred in &self.predecessors[state] {
+                if !result[*pred] && left[*pred] {
+                    result[*pred] = true;
+                    queue.push_back(*pred);
+                }
+            }
+        }
+        result
+    }
+
+    // Greatest fixpoint of EG inner
+    fn exists_globally(&self, inner: &[bool]) -> Vec<bool> {
+        let mut result = inner.to_vec();
+        loop {
+            let next = (0..result.len()).map(|s| result[s] && self.successors[s].iter().any(|(t, _)| result[*t])).collect_vec();
+            if next == result {
+                return result;
+            }
+            result = next;
+        }
+    }
+
+    /// The markings satisfying a CTL formula, by index in the state space.
+    pub fn states(&self, formula: &Ctl) -> Result<Vec<bool>, QueryError> {
+        let not = |v: Vec<bool>| v.into_iter().map(|b| !b).collect_vec();
+        let n = self.space.markings.len();
+        Ok(match formula {
+            Ctl::True => vec![true; n],
+            Ctl::False => vec![false; n],
+            Ctl::Atom(atom) => self.label(atom)?,
+            Ctl::Not(f) => not(self.states(f)?),
+            Ctl::And(a, b) => self.states(a)?.into_iter().zip(self.states(b)?).map(|(x, y)| x && y).collect(),
+            Ctl::Or(a, b) => self.states(a)?.into_iter().zip(self.states(b)?).map(|(x, y)| x || y).collect(),
+            Ctl::EX(f) => self.pre_exists(&self.states(f)?),
+            Ctl::AX(f) => not(self.pre_exists(&not(self.states(f)?))),
+            Ctl::EF(f) => self.exists_until(&vec![true; n], &self.states(f)?),
+            Ctl::AG(f) => not(self.exists_until(&vec![true; n], &not(self.states(f)?))),
+            Ctl::EG(f) => self.exists_globally(&self.states(f)?),
+            Ctl::AF(f) => not(self.exists_globally(&not(self.states(f)?))),
+            Ctl::EU(a, b) => self.exists_until(&self.states(a)?, &self.states(b)?),
+            Ctl::AU(a, b) => {
+                // A[a U b] = !(E[!b U (!a & !b)] | EG !b)
+                let (a, b) = (self.states(a)?, self.states(b)?);
+                let not_b = not(b.clone());
+                let neither = a.iter().zip(&b).map(|(x, y)| !x && !y).collect_vec();
+                let bad = self.exists_until(&not_b, &neither);
+                let stuck = self.exists_globally(&not_b);
+                bad.into_iter().zip(stuck).map(|(x, y)| !(x || y)).collect()
+            }
+        })
+    }
+
+    /// Checks whether the initial marking satisfies a CTL formula, with a witness for
+    /// existential formulas that hold and a counterexample for universal formulas that fail.
+    pub fn check_ctl(&self, formula: &Ctl) -> Result<CheckResult, QueryError> {
+        let holds = self.states(formula)?[0];
+        let trace = self.explain(formula, holds)?;
+        Ok(CheckResult { holds, trace })
+    }
+
+    // A trace showing why `formula` has the value `holds` in the initial marking
+    fn explain(&self, formula: &Ctl, holds: bool) -> Result<Option<Trace>, QueryError> {
+        let n = self.space.markings.len();
+        let not = |f: &Ctl| Ctl::Not(Box::new(f.clone()));
+        Ok(match (formula, holds) {
+            (Ctl::Not(f), _) => self.explain(f, !holds)?,
+            (Ctl::EF(f), true) => self.path(&vec![true; n], &self.states(f)?),
+            (Ctl::AG(f), false) => self.path(&vec![true; n], &self.states(&not(f))?),
+            (Ctl::EU(a, b), true) => self.path(&self.states(a)?, &self.states(b)?),
+            (Ctl::EX(f), true) => self.step(&self.states(f)?),
+            (Ctl::AX(f), false) => self.step(&self.states(&not(f))?),
+            (Ctl::EG(f), true) => self.lasso(&self.states(&Ctl::EG(f.clone()))?),
+            (Ctl::AF(f), false) => self.lasso(&self.states(&Ctl::EG(Box::new(not(f))))?),
+            (Ctl::AU(a, b), false) => {
+                let not_b = self.states(&not(b))?;
+                let neither = self.states(&Ctl::And(Box::new(not(a)), Box::new(not(b))))?;
+                match self.path(&not_b, &neither) {
+                    Some(trace) => Some(trace),
+                    None => self.lasso(&self.states(&Ctl::EG(Box::new(not(b))))?),
+                }
+            }
+            _ => None,
+        })
+    }
+
+    // Shortest path from the initial marking through `through` states to a `target` state
+    fn path(&self, through: &[bool], target: &[bool]) -> Option<Trace> {
+        let mut parent: Vec<Option<(usize, Option<usize>)>> = vec![None; target.len()];
+        let mut visited = vec![false; target.len()];
+        let mut queue = VecDeque::from([0]);
+        visited[0] = true;
+        while let Some(state) = queue.pop_front() {
+            if target[state] {
+                let mut prefix = vec![];
+                let mut current = state;
+                while let Some((previous, edge)) = parent[current] {
+                    prefix.extend(edge);
+                    current = previous;
+                }
+                prefix.reverse();
+                return Some(Trace { prefix, cycle: vec![], infinite: false });
+            }
+            if !through[state] {
+                continue;
+            }
+            for (next, edge) in &self.successors[state] {
+                if !visited[*next] {
+                    visited[*next] = true;
+                    parent[*next] = Some((state, *edge));
+                    queue.push_back(*next);
+                }
+            }
+        }
+        None
+    }
+
+    fn step(&self, target: &[bool]) -> Option<Trace> {
+        self.successors[0].iter().find(|(t, _)| target[*t]).map(|(_, edge)| Trace {
+            prefix: edge.iter().copied().collect(),
+            cycle: vec![],
+            infinite: false,
+        })
+    }
+
+    // An infinite path from the initial marking staying inside `states`, where every state has a
+    // successor inside `states`
+    fn lasso(&self, states: &[bool]) -> Option<Trace> {
+        if !states[0] {
+            return None;
+        }
+        let mut order = vec![0];
+        let mut edges: Vec<Option<usize>> = vec![];
+        let mut position = HashMap::from([(0, 0)]);
+        let mut state = 0;
+        loop {
+            let (next, edge) = *self.successors[state].iter().find(|(t, _)| states[*t])?;
+            edges.push(edge);
+            if let Some(start) = position.get(&next) {
+                let prefix = edges[..*start].iter().flatten().copied().collect();
+                let cycle = edges[*start..].iter().flatten().copied().collect();
+                return Some(Trace { prefix, cycle, infinite: true });
+            }
+            position.insert(next, order.len());
+            order.push(next);
+            state = next;
+        }
+    }
+
+    /// Checks whether every path from the initial marking satisfies an LTL formula, returning a
+    /// lasso-shaped counterexample if not.
+    ///
+    /// The negated formula is translated into a generalized Büchi automaton, and the product of
+    /// the automaton with the state space is searched for an accepting cycle.
+    pub fn check_ltl(&self, formula: &Ltl) -> Result<CheckResult, QueryError> {
+        let negated = nnf(&Ltl::Not(Box::new(formula.clone())));
+        let automaton = Automaton::new(&negated);
+        let mut labels: HashMap<Atom, Vec<bool>> = HashMap::new();
+        for atom in negated.atoms() {
+            labels.insert(atom.clone(), self.label(atom)?);
+        }
+        let satisfies = |state: usize, node: &AutomatonNode| {
+            node.old.iter().all(|f| match f {
+                Ltl::Atom(a) => labels[a][state],
+                Ltl::Not(inner) => match &**inner {
+                    Ltl::Atom(a) => !labels[a][state],
+                    _ => true,
+                },
+                _ => true,
+            })
+        };
+
+        // Product states are (marking, automaton node); each remembers how it was first reached
+        let mut index: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut states: Vec<(usize, usize)> = vec![];
+        let mut parent: Vec<Option<(usize, Option<usize>)>> = vec![];
+        let mut successors: Vec<Vec<(usize, Option<usize>)>> = vec![];
+        let mut queue = VecDeque::new();
+        for (n, node) in automaton.nodes.iter().enumerate() {
+            if node.incoming.contains(&INITIAL) && satisfies(0, node) {
+                index.insert((0, n), states.len());
+                queue.push_back(states.len());
+                states.push((0, n));
+                parent.push(None);
+                successors.push(vec![]);
+            }
+        }
+        while let Some(current) = queue.pop_front() {
+            let (marking, node) = states[current];
+            for (next_marking, edge) in &self.successors[marking] {
+                for (n, next_node) in automaton.nodes.iter().enumerate() {
+                    if !next_node.incoming.contains(&automaton.nodes[node].id) || !satisfies(*next_marking, next_node) {
+                        continue;
+                    }
+                    let next = *index.entry((*next_marking, n)).or_insert_with(|| {
+                        states.push((*next_marking, n));
+                        parent.push(Some((current, *edge)));
+                        successors.push(vec![]);
+                        queue.push_back(states.len() - 1);
+                        states.len() - 1
+                    });
+                    successors[current].push((next, *edge));
+                }
+            }
+        }
+
+        // An accepting cycle lives in a non-trivial component meeting every acceptance set
+        let plain = successors.iter().map(|s| s.iter().map(|(t, _)| *t).collect_vec()).collect_vec();
+        let components = strongly_connected(&plain);
+        let accepting = |s: usize, set: &BTreeSet<usize>| set.contains(&states[s].1);
+        let component = states.iter().enumerate().map(|(s, _)| components[s]).unique().find(|c| {
+            let members = (0..states.len()).filter(|s| components[*s] == *c).collect_vec();
+            let nontrivial = members.iter().any(|s| plain[*s].iter().any(|t| components[*t] == *c));
+            nontrivial && automaton.acceptance.iter().all(|set| members.iter().any(|s| accepting(*s, set)))
+        });
+        let component = match component {
+            Some(c) => c,
+            None => return Ok(CheckResult { holds: true, trace: None }),
+        };
+
+        let entry = (0..states.len()).find(|s| components[*s] == component).unwrap();
+        let mut prefix = vec![];
+        let mut current = entry;
+        while let Some((previous, edge)) = parent[current] {
+            prefix.extend(edge);
+            current = previous;
+        }
+        prefix.reverse();
+        // Walk the component through one state of each acceptance set and back to the entry
+        let inside = |s: usize| components[s] == component;
+        let mut cycle = vec![];
+        let mut position = entry;
+        for set in &automaton.acceptance {
+            let (end, steps) = bfs(&successors, position, |s| inside(s) && accepting(s, set), &inside, false);
+            cycle.extend(steps);
+            position = end;
+        }
+        let (_, steps) = bfs(&successors, position, |s| s == entry, &inside, true);
+        cycle.extend(steps);
+        let trace = Trace { prefix, cycle: cycle.into_iter().flatten().collect(), infinite: true };
+        Ok(CheckResult { holds: false, trace: Some(trace) })
+    }
+}
+
+// Breadth-first search inside a component for a state matching `goal`, returning the state and
+// the edges of the path. With `nonempty`, the start itself only counts after at least one step.
+fn bfs(
+    successors: &[Vec<(usize, Option<usize>)>],
+    start: usize,
+    goal: impl Fn(usize) -> bool,
+    inside: &dyn Fn(usize) -> bool,
+    nonempty: bool,
+) -> (usize, Vec<Option<usize>>) {
+    if !nonempty && goal(start) {
+        return (start, vec![]);
+    }
+    let mut parent: HashMap<usize, (usize, Option<usize>)> = HashMap::new();
+    let mut queue = VecDeque::from([start]);
+    while let Some(state) = queue.pop_front() {
+        for (next, edge) in &successors[state] {
+            if !inside(*next) || parent.contains_key(next) {
+                continue;
+            }
+            parent.insert(*next, (state, *edge));
+            if goal(*next) {
+                let mut steps = vec![];
+                let mut current = *next;
+                loop {
+                    let (previous, edge) = parent[&current];
+                    steps.push(edge);
+                    current = previous;
+                    if current == start {
+                        break;
+                    }
+                }
+                steps.reverse();
+                return (*next, steps);
+            }
+            queue.push_back(*next);
+        }
+    }
+    (start, vec![])
+}
+
+// Negation normal form: negations only on atoms, and F and G expanded into U and R
+fn nnf(formula: &Ltl) -> Ltl {
+    let b = |f: Ltl| Box::new(f);
+    match formula {
+        Ltl::True | Ltl::False | Ltl::Atom(_) => formula.clone(),
+        Ltl::And(x, y) => Ltl::And(b(nnf(x)), b(nnf(y))),
+        Ltl::Or(x, y) => Ltl::Or(b(nnf(x)), b(nnf(y))),
+        Ltl::Next(x) => Ltl::Next(b(nnf(x))),
+        Ltl::Until(x, y) => Ltl::Until(b(nnf(x)), b(nnf(y))),
+        Ltl::Release(x, y) => Ltl::Release(b(nnf(x)), b(nnf(y))),
+        Ltl::Finally(x) => Ltl::Until(b(Ltl::True), b(nnf(x))),
+        Ltl::Globally(x) => Ltl::Release(b(Ltl::False), b(nnf(x))),
+        Ltl::Not(inner) => match &**inner {
+            Ltl::True => Ltl::False,
+            Ltl::False => Ltl::True,
+            Ltl::Atom(_) => formula.clone(),
+            Ltl::Not(x) => nnf(x),
+            Ltl::And(x, y) => Ltl::Or(b(nnf(&Ltl::Not(x.clone()))), b(nnf(&Ltl::Not(y.clone())))),
+            Ltl::Or(x, y) => Ltl::And(b(nnf(&Ltl::Not(x.clone()))), b(nnf(&Ltl::Not(y.clone())))),
+            Ltl::Next(x) => Ltl::Next(b(nnf(&Ltl::Not(x.clone())))),
+            Ltl::Until(x, y) => Ltl::Release(b(nnf(&Ltl::Not(x.clone()))), b(nnf(&Ltl::Not(y.clone())))),
+            Ltl::Release(x, y) => Ltl::Until(b(nnf(&Ltl::Not(x.clone()))), b(nnf(&Ltl::Not(y.clone())))),
+            Ltl::Finally(x) => Ltl::Release(b(Ltl::False), b(nnf(&Ltl::Not(x.clone())))),
+            Ltl::Globally(x) => Ltl::Until(b(Ltl::True), b(nnf(&Ltl::Not(x.clone())))),
+        },
+    }
+}
+
+// Id of the virtual initial node in the tableau construction
+const INITIAL: usize = 0;
+
+#[derive(Clone, Debug)]
+struct AutomatonNode {
+    id: usize,
+    incoming: BTreeSet<usize>,
+    new: BTreeSet<Ltl>,
+    old: BTreeSet<Ltl>,
+    next: BTreeSet<Ltl>,
+}
+
+// Generalized Büchi automaton of an LTL formula in negation normal form, built with the tableau
+// construction of Gerth, Peled, Vardi and Wolper
+struct Automaton {
+    nodes: Vec<AutomatonNode>,
+    // For each until subformula, the indices of the nodes that fulfil it
+    acceptance: Vec<BTreeSet<usize>>,
+}
+
+impl Automaton {
+    fn new(formula: &Ltl) -> Self {
+        let mut nodes = vec![];
+        let start = AutomatonNode {
+            id: 0,
+            incoming: BTreeSet::from([INITIAL]),
+            new: BTreeSet::from([formula.clone()]),
+            old: BTreeSet::new(),
+            next: BTreeSet::new(),
+        };
+        let mut pending = vec![start];
+        while let Some(node) = pending.pop() {
+            expand(node, &mut nodes, &mut pending);
+        }
+        let mut untils = vec![];
+        collect_untils(formula, &mut untils);
+        let acceptance = untils
+            .iter()
+            .map(|u| match u {
+                Ltl::Until(_, right) => (0..nodes.len())
+                    .filter(|n| !nodes[*n].old.contains(u) || nodes[*n].old.contains(&**right))
+                    .collect(),
+                _ => BTreeSet::new(),
+            })
+            .collect();
+        Self { nodes, acceptance }
+    }
+}
+
+fn collect_untils(formula: &Ltl, untils: &mut Vec<Ltl>) {
+    match formula {
+        Ltl::Until(a, b) => {
+            if !untils.contains(formula) {
+                untils.push(formula.clone());
+            }
+            collect_untils(a, untils);
+            collect_untils(b, untils);
+        }
+        Ltl::And(a, b) | Ltl::Or(a, b) | Ltl::Release(a, b) => {
+            collect_untils(a, untils);
+            collect_untils(b, untils);
+        }
+        Ltl::Not(a) | Ltl::Next(a) | Ltl::Finally(a) | Ltl::Globally(a) => collect_untils(a, untils),
+        Ltl::True | Ltl::False | Ltl::Atom(_) => {}
+    }
+}
+
+fn expand(mut node: AutomatonNode, nodes: &mut Vec<AutomatonNode>, pending: &mut Vec<AutomatonNode>) {
+    let formula = match node.new.pop_first() {
+        Some(f) => f,
+        None => {
+            if let Some(existing) = nodes.iter_mut().find(|n| n.old == node.old && n.next == node.next) {
+                existing.incoming.extend(node.incoming);
+                return;
+            }
+            node.id = nodes.len() + 1;
+            pending.push(AutomatonNode {
+                id: 0,
+                incoming: BTreeSet::from([node.id]),
+                new: node.next.clone(),
+                old: BTreeSet::new(),
+                next: BTreeSet::new(),
+            });
+            nodes.push(node);
+            return;
+        }
+    };
+    if node.old.contains(&formula) {
+        pending.push(node);
+        return;
+    }
+    let with = |node: &AutomatonNode, new: Vec<&Ltl>, next: Vec<&Ltl>| {
+        let mut split = node.clone();
+        split.old.insert(formula.clone());
+        split.new.extend(new.into_iter().filter(|f| !node.old.contains(*f)).cloned());
+        split.next.extend(next.into_iter().cloned());
+        split
+    };
+    match &formula {
+        Ltl::False => {}
+        Ltl::True => pending.push(with(&node, vec![], vec![])),
+        Ltl::Atom(_) | Ltl::Not(_) => {
+            let negation = match &formula {
+                Ltl::Not(inner) => (**inner).clone(),
+                _ => Ltl::Not(Box::new(formula.clone())),
+            };
+            if !node.old.contains(&negation) {
+                pending.push(with(&node, vec![], vec![]));
+            }
+        }
+        Ltl::And(a, b) => pending.push(with(&node, vec![a, b], vec![])),
+        Ltl::Or(a, b) => {
+            pending.push(with(&node, vec![a], vec![]));
+            pending.push(with(&node, vec![b], vec![]));
+        }
+        Ltl::Until(a, b) => {
+            pending.push(with(&node, vec![a], vec![&formula]));
+            pending.push(with(&node, vec![b], vec![]));
+        }
+        Ltl::Release(a, b) => {
+            pending.push(with(&node, vec![b], vec![&formula]));
+            pending.push(with(&node, vec![a, b], vec![]));
+        }
+        Ltl::Next(a) => pending.push(with(&node, vec![], vec![a])),
+        Ltl::Finally(_) | Ltl::Globally(_) => pending.push(with(&node, vec![&nnf(&formula)], vec![])),
+    }
+}
+
+#[test]
+pub fn check_safety_and_liveness() {
+    use std::collections::HashSet;
+    use crate::clade::Clade;
+    use crate::guard::Guard;
+    use crate::place::Place;
+    use crate::signature::Signature;
+    use crate::token::Token;
+    use crate::transition::Transition;
+
+    // Two robots share a cell; only robot1 may finish
+    let robot1 = Clade::new("robot1".into(), None);
+    let robot2 = Clade::new("robot2".into(), None);
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    net.add_clade(Clade::new("robot".into(), Some(vec![robot1.clone(), robot2.clone()])));
+    let idle = net.add_place(Place::new("idle".into()));
+    let cell = net.add_place(Place::new("cell".into()));
+    let done = net.add_place(Place::new("done".into()));
+    net.add_token(idle, Token::new("r1".into(), robot1.clone()));
+    net.add_token(idle, Token::new("r2".into(), robot2.clone()));
+    let sig = || Signature::new(HashSet::from(["x".into()]));
+    let arcs = |from, to| (Some(HashMap::from([(from, sig())])), Some(HashMap::from([(to, sig())])));
+    let (i, o) = arcs(idle, cell);
+    net.add_transition(Transition::new("enter".into(), i, o, None, None));
+    let (i, o) = arcs(cell, idle);
+    net.add_transition(Transition::new("leave".into(), i, o, None, None));
+    let (i, o) = arcs(cell, done);
+    net.add_transition(Transition::new("finish".into(), i, o, Some(Guard::Is("x".into(), robot1.clone())), None));
+
+    let checker = ModelChecker::new(&net, None).unwrap();
+
+    let two_in_cell = Ctl::parse("AG count(cell) < 2").unwrap();
+    let result = checker.check_ctl(&two_in_cell).unwrap();
+    assert!(!result.holds);
+    let trace = result.trace.unwrap();
+    assert_eq!(trace.prefix.len(), 2);
+    let reached = &checker.space.markings[checker.space.edges[trace.prefix[1]].target];
+    assert_eq!(reached[&cell].len(), 2);
+    assert!(trace.describe(&net, &checker.space).starts_with("enter("));
+
+    assert!(checker.check_ctl(&Ctl::parse("EF has(done, robot)").unwrap()).unwrap().holds);
+    assert!(!checker.check_ctl(&Ctl::parse("EF has(done, robot2)").unwrap()).unwrap().holds);
+    let never_done = checker.check_ctl(&Ctl::parse("AF has(done, robot1)").unwrap()).unwrap();
+    assert!(!never_done.holds);
+    assert!(never_done.trace.unwrap().infinite);
+    assert!(checker.check_ctl(&Ctl::parse("AG EF has(idle, robot2) | has(cell, robot2)").unwrap()).unwrap().holds);
+    assert!(matches!(checker.check_ctl(&Ctl::parse("AG has(nowhere, robot)").unwrap()), Err(QueryError::UnknownPlace(_))));
+
+    // robot2 can never leave the idle/cell loop
+    assert!(checker.check_ltl(&Ltl::parse("G !has(done, robot2)").unwrap()).unwrap().holds);
+    let result = checker.check_ltl(&Ltl::parse("F has(done, robot1)").unwrap()).unwrap();
+    assert!(!result.holds);
+    let trace = result.trace.unwrap();
+    assert!(trace.infinite && !trace.cycle.is_empty());
+    // Once robot1 is done it stays done, and the lasso ends when robot2 stops moving
+    assert!(checker.check_ltl(&Ltl::parse("G (has(done, robot1) -> G has(done, robot1))").unwrap()).unwrap().holds);
+    let result = checker.check_ltl(&Ltl::parse("G F enabled(enter)").unwrap()).unwrap();
+    assert!(result.holds);
+    let result = checker.check_ltl(&Ltl::parse("G F deadlock").unwrap()).unwrap();
+    assert!(!result.holds);
+}
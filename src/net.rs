@@ -1,8 +1,69 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use itertools::Itertools;
 use uuid::Uuid;
-use crate::signature::Signature;
-use crate::aliases::{Time,Cost};
+use crate::binding::Binding;
+use crate::clade::Clade;
+use crate::hierarchy::flatten;
+use crate::incidence::Incidence;
+use crate::marking::Marking;
+use crate::place::Place;
+use crate::symbol::Symbol;
+use crate::token::Token;
+use crate::transition::{FiringError, Transition};
+
+/// A transition fired with a binding, and the marking it leads to.
+pub type Firing = (Uuid, Binding, Marking);
+
+/// A place constraint that the net breaks, or that an output arc can never meet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlaceIssue {
+    pub place: Uuid,
+    pub transition: Option<Uuid>,
+    pub message: String,
+}
+
+/// How serious a validation issue is. Errors make the net misbehave or fail when it runs;
+/// warnings point at something that is likely, but not certainly, a mistake.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// The part of a net a validation issue is about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Element {
+    Place(Uuid),
+    Transition(Uuid),
+    Token { place: Uuid, token: Uuid },
+    // An id in the name lookup that is neither a place nor a transition
+    Name(Uuid),
+}
+
+/// A problem found by [`ColoredPetriNet::validate`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidationIssue {
+    pub severity: Severity,
+    pub element: Element,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ColoredPetriNet {
@@ -10,21 +71,457 @@ pub struct ColoredPetriNet {
     pub name: String,
     pub places: HashMap<Uuid, Place>,
     pub transitions: HashMap<Uuid, Transition>,
-    pub initial_marking: HashMap<Uuid, HashMap<Uuid,Token>>,
-    pub current_marking: HashMap<Uuid, HashMap<Uuid,Token>>,
+    pub initial_marking: Marking,
+    pub current_marking: Marking,
     pub name_lookup: HashMap<Uuid, String>,
+    // The root clades (taxonomies) that tokens and guards in this net are drawn from
+    #[serde(default)]
+    pub clades: Vec<Clade>,
+    // Ids of the transitions in the order they were declared
+    #[serde(default)]
+    pub transition_order: Vec<Uuid>,
+    // Nets that substitution transitions of this net (or of its subnets) can stand for
+    #[serde(default)]
+    pub subnets: HashMap<Uuid, ColoredPetriNet>,
 }
 
 impl ColoredPetriNet {
-    pub fn new(name: String, places: Option<HashMap<Uuid, Place>>, transitions: Option<HashMap<Uuid, Transition>>, initial_marking: Option<HashMap<Uuid, HashMap<Uuid,Token>>>) -> Self {
+    pub fn new(name: String, places: Option<HashMap<Uuid, Place>>, transitions: Option<HashMap<Uuid, Transition>>, initial_marking: Option<Marking>) -> Self {
+        let places = places.unwrap_or_default();
+        let transitions = transitions.unwrap_or_default();
+        let initial_marking = initial_marking.unwrap_or_default();
+        let mut name_lookup = HashMap::new();
+        for place in places.values() {
+            name_lookup.insert(place.id, place.name.clone());
+        }
+        for transition in transitions.values() {
+            name_lookup.insert(transition.id, transition.name.clone());
+        }
+        let transition_order = transitions.values().sorted_by_key(|t| (t.name.clone(), t.id)).map(|t| t.id).collect();
         Self {
             id: Uuid::new_v4(),
             name,
-            places: places.unwrap_or(HashMap::new()),
-            transitions: transitions.unwrap_or(HashMap::new()),
-            initial_marking: initial_marking.unwrap_or(HashMap::new()),
-            current_marking: initial_marking.unwrap_or(HashMap::new()),
-            name_lookup: HashMap::new(),
+            places,
+            transitions,
+            current_marking: initial_marking.clone(),
+            initial_marking,
+            name_lookup,
+            clades: vec![],
+            transition_order,
+            subnets: HashMap::new(),
+        }
+    }
+
+    /// Adds a place to the net, registering its name, and returns its id.
+    pub fn add_place(&mut self, place: Place) -> Uuid {
+        let id = place.id;
+        self.name_lookup.insert(id, place.name.clone());
+        self.places.insert(id, place);
+        id
+    }
+
+    /// Adds a transition to the net, registering its name, and returns its id.
+    pub fn add_transition(&mut self, transition: Transition) -> Uuid {
+        let id = transition.id;
+        self.name_lookup.insert(id, transition.name.clone());
+        self.transitions.insert(id, transition);
+        if !self.transition_order.contains(&id) {
+            self.transition_order.push(id);
         }
+        id
+    }
+
+    /// The transitions in declaration order. Transitions missing from `transition_order` come
+    /// last, ordered by name.
+    pub fn declared_transitions(&self) -> Vec<&Transition> {
+        let declared = self.transition_order.iter().filter_map(|id| self.transitions.get(id));
+        let rest = self
+            .transitions
+            .values()
+            .filter(|t| !self.transition_order.contains(&t.id))
+            .sorted_by_key(|t| (t.name.clone(), t.id));
+        declared.unique_by(|t| t.id).chain(rest).collect()
+    }
+
+    /// Adds a token to both the initial and the current marking of a place.
+    pub fn add_token(&mut self, place: Uuid, token: Token) {
+        self.initial_marking.add_token(place, token.clone());
+        self.current_marking.add_token(place, token);
+    }
+
+    /// Registers a root clade, unless a clade with the same id is already known.
+    pub fn add_clade(&mut self, clade: Clade) {
+        if !self.clades.iter().any(|c| c.descendent(&clade.id())) {
+            self.clades.push(clade);
+        }
+    }
+
+    /// Registers a net that substitution transitions can stand for, and returns its id.
+    pub fn add_subnet(&mut self, subnet: ColoredPetriNet) -> Uuid {
+        let id = subnet.id;
+        self.subnets.insert(id, subnet);
+        id
+    }
+
+    /// Looks up the id of a place or transition of this net by name.
+    pub fn id_of(&self, name: &str) -> Option<Uuid> {
+        self.name_lookup.iter().find(|(_, n)| n.as_str() == name).map(|(id, _)| *id)
+    }
+
+    /// Looks up the id an element gets in the flattened net, where paths such as
+    /// `cell1/loader/pick` name elements of subnet instances.
+    pub fn flat_id_of(&self, name: &str) -> Option<Uuid> {
+        flatten(self).ok()?.id_of(name)
+    }
+
+    /// Finds a clade by id among the registered root clades.
+    pub fn clade(&self, id: &Uuid) -> Option<Clade> {
+        self.clades.iter().find_map(|c| c.get(id))
+    }
+
+    /// Finds a clade by name, first among the registered root clades and then among the
+    /// clades of tokens in the initial marking.
+    pub fn find_clade(&self, name: &str) -> Option<Clade> {
+        let name = name.to_string();
+        self.clades
+            .iter()
+            .find_map(|c| c.query(&name).and_then(|id| c.get(&id)))
+            .or_else(|| {
+                self.initial_marking
+                    .values()
+                    .flat_map(|tokens| tokens.values())
+                    .find_map(|t| t.clade.query(&name).and_then(|id| t.clade.get(&id)))
+            })
+    }
+
+    /// Every enabled transition of the current marking with each of its bindings, ordered by
+    /// transition name.
+    pub fn enabled(&self) -> Vec<(Uuid, Binding)> {
+        self.enabled_in(&self.current_marking)
     }
-}
\ No newline at end of file
+
+    /// The enabled transitions of a marking with each of their bindings, ordered by transition
+    /// name. Only transitions of the highest priority that has a binding are enabled.
+    pub fn enabled_in(&self, marking: &Marking) -> Vec<(Uuid, Binding)> {
+        self.firings(marking).into_iter().map(|(t, b, _)| (t, b)).collect()
+    }
+
+    /// Like [`ColoredPetriNet::enabled_in`], together with the marking each firing leads to.
+    pub fn firings(&self, marking: &Marking) -> Vec<Firing> {
+        for priority in self.transitions.values().map(|t| t.priority).unique().sorted().rev() {
+            let firings = self
+                .transitions
+                .values()
+                .filter(|t| t.priority == priority)
+                .sorted_by_key(|t| (t.name.clone(), t.id))
+                .flat_map(|t| {
+                    t.bindings(marking)
+                        .into_iter()
+                        .filter_map(|b| self.successor(t, marking, &b).ok().map(|next| (t.id, b, next)))
+                        .collect_vec()
+                })
+                .collect_vec();
+            if !firings.is_empty() {
+                return firings;
+            }
+        }
+        vec![]
+    }
+
+    /// The bindings with which a transition can fire in a marking, leaving out those whose
+    /// outputs the target places would not accept. Priorities are not taken into account.
+    pub fn bindings(&self, transition: &Transition, marking: &Marking) -> Vec<Binding> {
+        transition.bindings(marking).into_iter().filter(|b| self.successor(transition, marking, b).is_ok()).collect()
+    }
+
+    /// Whether a transition has a binding in a marking and no transition of higher priority has.
+    pub fn is_enabled(&self, transition: &Transition, marking: &Marking) -> bool {
+        !self.bindings(transition, marking).is_empty() && self.preempting(transition, marking).is_none()
+    }
+
+    // A transition of higher priority that has a binding in the marking
+    fn preempting(&self, transition: &Transition, marking: &Marking) -> Option<Uuid> {
+        self.transitions
+            .values()
+            .filter(|t| t.priority > transition.priority)
+            .sorted_by_key(|t| (std::cmp::Reverse(t.priority), t.name.clone(), t.id))
+            .find(|t| !self.bindings(t, marking).is_empty())
+            .map(|t| t.id)
+    }
+
+    /// The marking after firing a transition with a binding, checking the colors and capacity
+    /// of every output place.
+    pub fn successor(
+        &self,
+        transition: &Transition,
+        marking: &Marking,
+        binding: &Binding,
+    ) -> Result<Marking, FiringError> {
+        let next = transition.fire(marking, binding)?;
+        for place in transition.output.keys().sorted() {
+            if let (Some(p), Some(tokens)) = (self.places.get(place), next.get(place)) {
+                p.check(tokens).map_err(|violation| FiringError::PlaceViolation { place: *place, violation })?;
+            }
+        }
+        Ok(next)
+    }
+
+    /// Fires a transition with a binding, updating the current marking. The transition must not
+    /// be preempted by an enabled transition of higher priority.
+    pub fn fire(&mut self, transition: &Uuid, binding: &Binding) -> Result<(), FiringError> {
+        let transition = self.transitions.get(transition).ok_or(FiringError::UnknownTransition(*transition))?;
+        let next = self.successor(transition, &self.current_marking, binding)?;
+        if let Some(higher) = self.preempting(transition, &self.current_marking) {
+            return Err(FiringError::Preempted(higher));
+        }
+        self.current_marking = next;
+        Ok(())
+    }
+
+    /// Finds place constraints the net breaks or can never meet: tokens of the initial marking
+    /// that their place rejects, and output arcs that can never put tokens into their place,
+    /// whatever the binding.
+    ///
+    /// Output tokens are considered for every assignment of leaf clades to input symbols that
+    /// the guard and the colors of the input places allow.
+    pub fn check_places(&self) -> Vec<PlaceIssue> {
+        let mut issues = vec![];
+        for place in self.places.values().sorted_by_key(|p| (p.name.clone(), p.id)) {
+            if let Err(violation) = place.check(self.initial_marking.get(&place.id).unwrap_or(&HashMap::new())) {
+                issues.push(PlaceIssue { place: place.id, transition: None, message: format!("initial marking: {}", violation) });
+            }
+        }
+        let colors = Incidence::colors(self);
+        for transition in self.transitions.values().sorted_by_key(|t| (t.name.clone(), t.id)) {
+            let source: BTreeMap<Symbol, Uuid> = transition
+                .input
+                .iter()
+                .chain(transition.reads.iter())
+                .flat_map(|(place, signature)| signature.symbols.iter().map(move |s| (s.clone(), *place)))
+                .collect();
+            let symbols = source.keys().cloned().collect_vec();
+            let candidates = symbols
+                .iter()
+                .map(|s| colors.iter().filter(|c| self.places.get(&source[s]).is_none_or(|p| p.accepts(c))).cloned().collect_vec())
+                .collect_vec();
+            let assignments: Vec<HashMap<Symbol, Clade>> = if symbols.is_empty() {
+                vec![HashMap::new()]
+            } else {
+                candidates.into_iter().multi_cartesian_product().map(|clades| symbols.iter().cloned().zip(clades).collect()).collect()
+            };
+            let assignments = assignments.into_iter().filter(|a| transition.guard.eval(a)).collect_vec();
+            // A transition that can never fire at all has no output to blame
+            if assignments.is_empty() {
+                continue;
+            }
+            for (place, signature) in transition.output.iter().sorted_by_key(|(p, _)| **p) {
+                let target = match self.places.get(place) {
+                    Some(target) => target,
+                    None => continue,
+                };
+                if signature.symbols.iter().any(|s| !transition.input.values().any(|i| i.symbols.contains(s))) {
+                    continue;
+                }
+                let fits = assignments.iter().any(|assignment| {
+                    let tokens: HashMap<Uuid, Token> = signature
+                        .symbols
+                        .iter()
+                        .map(|s| Token::new(s.to_string(), assignment[s].clone()))
+                        .map(|t| (t.id, t))
+                        .collect();
+                    target.check(&tokens).is_ok()
+                });
+                if !fits {
+                    let symbols = signature.symbols.iter().sorted().join(", ");
+                    issues.push(PlaceIssue {
+                        place: *place,
+                        transition: Some(transition.id),
+                        message: format!(
+                            "transition '{}' can never put tokens bound to {{{}}} into place '{}'",
+                            transition.name, symbols, target.name
+                        ),
+                    });
+                }
+            }
+        }
+        issues
+    }
+
+    /// Checks the whole net, returning every issue found, errors first.
+    ///
+    /// Besides the place constraints of [`ColoredPetriNet::check_places`], this finds arcs to
+    /// places that do not exist, symbols bound by more than one input, guard and output symbols
+    /// that no input binds, guards no colors of the net can satisfy, tokens whose clade is in no
+    /// taxonomy of the net, names shared by several elements, and places and transitions with no
+    /// arcs at all.
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        let mut issues = vec![];
+        let mut issue = |severity, element, message| issues.push(ValidationIssue { severity, element, message });
+        let colors = Incidence::colors(self);
+        let mut connected = HashSet::new();
+        for transition in self.transitions.values().sorted_by_key(|t| (t.name.clone(), t.id)) {
+            let element = Element::Transition(transition.id);
+            let name = &transition.name;
+            let arcs = [
+                ("an input arc from", transition.input.keys().collect_vec()),
+                ("an output arc to", transition.output.keys().collect_vec()),
+                ("a read arc from", transition.reads.keys().collect_vec()),
+                ("an inhibitor arc from", transition.inhibitors.keys().collect_vec()),
+                ("a reset arc to", transition.resets.iter().collect_vec()),
+            ];
+            for (kind, places) in &arcs {
+                for place in places.iter().sorted() {
+                    if !self.places.contains_key(place) {
+                        issue(Severity::Error, element, format!("transition '{}' has {} place {} that does not exist", name, kind, place));
+                    }
+                    connected.insert(**place);
+                }
+            }
+            if arcs.iter().all(|(_, places)| places.is_empty()) && transition.substitution.is_none() {
+                issue(Severity::Warning, element, format!("transition '{}' has no arcs", name));
+            }
+
+            let mut bound: BTreeMap<&Symbol, usize> = BTreeMap::new();
+            for signature in transition.input.values().chain(transition.reads.values()) {
+                for symbol in &signature.symbols {
+                    *bound.entry(symbol).or_default() += 1;
+                }
+            }
+            for (symbol, _) in bound.iter().filter(|(_, count)| **count > 1) {
+                issue(Severity::Error, element, format!("transition '{}' binds symbol '{}' on more than one input arc", name, symbol));
+            }
+            for symbol in transition.guard.symbols().into_iter().unique().sorted() {
+                if !bound.contains_key(&symbol) {
+                    issue(Severity::Error, element, format!("guard of transition '{}' uses symbol '{}', which no input binds", name, symbol));
+                }
+            }
+            let consumed: HashSet<&Symbol> = transition.input.values().flat_map(|s| s.symbols.iter()).collect();
+            for symbol in transition.output.values().flat_map(|s| s.symbols.iter()).unique().sorted() {
+                if !consumed.contains(symbol) {
+                    issue(Severity::Error, element, format!("transition '{}' outputs symbol '{}', which no input consumes", name, symbol));
+                }
+            }
+
+            // Every assignment of the net's leaf colors to the guard's symbols
+            let symbols = transition.guard.symbols().into_iter().unique().collect_vec();
+            if !symbols.is_empty() && !colors.is_empty() {
+                let satisfiable = symbols
+                    .iter()
+                    .map(|_| colors.iter())
+                    .multi_cartesian_product()
+                    .any(|clades| transition.guard.eval(&symbols.iter().cloned().zip(clades.into_iter().cloned()).collect()));
+                if !satisfiable {
+                    issue(Severity::Error, element, format!("guard of transition '{}' cannot be satisfied by any clade of the net", name));
+                }
+            }
+        }
+
+        for place in self.places.values().sorted_by_key(|p| (p.name.clone(), p.id)) {
+            if !connected.contains(&place.id) {
+                issue(Severity::Warning, Element::Place(place.id), format!("place '{}' is not connected to any transition", place.name));
+            }
+        }
+        let known = |clade: &Clade| self.clades.iter().any(|root| root.descendent(&clade.id()));
+        let mut seen = HashSet::new();
+        for (kind, marking) in [("initial", &self.initial_marking), ("current", &self.current_marking)] {
+            for place in marking.places() {
+                for token in marking.tokens(&place).into_iter().filter(|t| seen.insert(t.id) && !known(&t.clade)) {
+                    let place_name = self.places.get(&place).map_or_else(|| place.to_string(), |p| p.name.clone());
+                    issue(
+                        Severity::Warning,
+                        Element::Token { place, token: token.id },
+                        format!("token '{}' in place '{}' of the {} marking has clade '{}', which is in no taxonomy of the net", token.name, place_name, kind, token.clade.name()),
+                    );
+                }
+            }
+        }
+        let by_name = self.name_lookup.iter().map(|(id, name)| (name, *id)).into_group_map();
+        for (name, ids) in by_name.into_iter().filter(|(_, ids)| ids.len() > 1).sorted() {
+            for id in ids.into_iter().sorted() {
+                let element = if self.places.contains_key(&id) {
+                    Element::Place(id)
+                } else if self.transitions.contains_key(&id) {
+                    Element::Transition(id)
+                } else {
+                    Element::Name(id)
+                };
+                issue(Severity::Warning, element, format!("name '{}' is used by more than one element", name));
+            }
+        }
+
+        for place_issue in self.check_places() {
+            let element = place_issue.transition.map_or(Element::Place(place_issue.place), Element::Transition);
+            issue(Severity::Error, element, place_issue.message);
+        }
+        issues.sort_by_key(|i| i.severity);
+        issues
+    }
+
+    /// Restores the initial marking.
+    pub fn reset(&mut self) {
+        self.current_marking = self.initial_marking.clone();
+    }
+}
+
+#[test]
+pub fn validate_net() {
+    use crate::guard::Guard;
+    use crate::signature::Signature;
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let robot2 = Clade::new("robot2".into(), None);
+    let drone = Clade::new("drone".into(), None);
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    net.add_clade(Clade::new("robot".into(), Some(vec![robot1.clone(), robot2.clone()])));
+    let idle = net.add_place(Place::new("idle".into()));
+    let done = net.add_place(Place::new("done".into()));
+    let spare = net.add_place(Place::new("spare".into()));
+    let twin = net.add_place(Place::new("done".into()));
+    let gone = Uuid::new_v4();
+    net.add_token(idle, Token::new("r1".into(), robot1.clone()));
+    net.add_token(idle, Token::new("d1".into(), drone.clone()));
+    let sig = |symbols: &[&str]| Signature::new(symbols.iter().map(|s| (*s).into()).collect());
+
+    let work = net.add_transition(Transition::new(
+        "work".into(),
+        Some(HashMap::from([(idle, sig(&["x"]))])),
+        Some(HashMap::from([(done, sig(&["x"]))])),
+        Some(Guard::Is("x".into(), robot1.clone())),
+        None,
+    ));
+    assert!(net.validate().iter().all(|i| i.element != Element::Transition(work)));
+
+    // Constructors drop guards with unbound symbols, but a net read from a file can have them
+    let mut broken = Transition::new(
+        "broken".into(),
+        Some(HashMap::from([(idle, sig(&["x"])), (gone, sig(&["x"]))])),
+        Some(HashMap::from([(twin, sig(&["y"]))])),
+        None,
+        None,
+    );
+    broken.guard = Guard::All(vec![Guard::Is("x".into(), robot1.clone()), Guard::Is("x".into(), robot2.clone()), Guard::Is("z".into(), robot1.clone())]);
+    let broken = net.add_transition(broken);
+    let idler = net.add_transition(Transition::new("idler".into(), None, None, None, None));
+
+    let issues = net.validate();
+    let about = |element: Element| issues.iter().filter(|i| i.element == element).map(|i| i.message.as_str()).collect_vec();
+    assert_eq!(
+        about(Element::Transition(broken)),
+        [
+            format!("transition 'broken' has an input arc from place {} that does not exist", gone).as_str(),
+            "transition 'broken' binds symbol 'x' on more than one input arc",
+            "guard of transition 'broken' uses symbol 'z', which no input binds",
+            "transition 'broken' outputs symbol 'y', which no input consumes",
+            "guard of transition 'broken' cannot be satisfied by any clade of the net",
+        ]
+    );
+    assert_eq!(about(Element::Transition(idler)), ["transition 'idler' has no arcs"]);
+    assert_eq!(about(Element::Place(spare)), ["place 'spare' is not connected to any transition"]);
+    assert_eq!(about(Element::Place(done)), ["name 'done' is used by more than one element"]);
+    assert_eq!(about(Element::Place(twin)), ["name 'done' is used by more than one element"]);
+    let token = net.initial_marking.tokens(&idle).into_iter().find(|t| t.name == "d1").unwrap().id;
+    assert_eq!(issues.iter().filter(|i| i.element == Element::Token { place: idle, token }).count(), 1);
+    // Errors come before warnings
+    assert!(issues.iter().tuple_windows().all(|(a, b)| a.severity <= b.severity));
+    assert_eq!(issues.iter().filter(|i| i.severity == Severity::Error).count(), 5);
+}
@@ -0,0 +1,278 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use log::warn;
+use uuid::Uuid;
+use crate::binding::Binding;
+use crate::net::ColoredPetriNet;
+use crate::simulation::ConflictPolicy;
+use crate::token::Token;
+use crate::transition::FiringError;
+
+/// What an observer answers when a transition is about to fire.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    // Stops the firing, giving a reason
+    Veto(String),
+}
+
+/// Callbacks for what happens while a net runs. Every callback does nothing by default, and
+/// returns an error message when the observer fails, which its [`ErrorPolicy`] deals with.
+///
+/// A token that moves from one place to another is destroyed in the first and created in the
+/// second, keeping its id.
+pub trait Observer {
+    fn enabled(&mut self, _net: &ColoredPetriNet, _transition: &Uuid) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn disabled(&mut self, _net: &ColoredPetriNet, _transition: &Uuid) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn before_fire(&mut self, _net: &ColoredPetriNet, _transition: &Uuid, _binding: &Binding) -> Result<Verdict, String> {
+        Ok(Verdict::Allow)
+    }
+
+    fn fired(&mut self, _net: &ColoredPetriNet, _transition: &Uuid, _binding: &Binding) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn token_created(&mut self, _net: &ColoredPetriNet, _place: &Uuid, _token: &Token) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn token_destroyed(&mut self, _net: &ColoredPetriNet, _place: &Uuid, _token: &Token) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// What to do when an observer fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    Ignore,
+    // Log a warning and carry on
+    Log,
+    // Stop, returning the error. A failure before firing prevents the firing; after firing, the
+    // marking has changed but later observers are not told.
+    Abort,
+}
+
+/// Reasons observed execution stopped.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ObserverError {
+    Firing(FiringError),
+    Vetoed { observer: String, reason: String },
+    Failed { observer: String, message: String },
+}
+
+impl fmt::Display for ObserverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObserverError::Firing(error) => write!(f, "{}", error),
+            ObserverError::Vetoed { observer, reason } => write!(f, "observer '{}' vetoed the firing: {}", observer, reason),
+            ObserverError::Failed { observer, message } => write!(f, "observer '{}' failed: {}", observer, message),
+        }
+    }
+}
+
+impl std::error::Error for ObserverError {}
+
+impl From<FiringError> for ObserverError {
+    fn from(error: FiringError) -> Self {
+        ObserverError::Firing(error)
+    }
+}
+
+struct Entry {
+    name: String,
+    observer: Box<dyn Observer>,
+    policy: ErrorPolicy,
+}
+
+/// Observers of a running net, told about firings in the order they were added.
+///
+/// Enabledness is tracked between calls: [`Observers::refresh`] tells observers about transitions
+/// that became enabled or disabled since the last refresh, and is called after every firing.
+/// Call it directly after changing the marking in another way, e.g. after `reset`.
+#[derive(Default)]
+pub struct Observers {
+    entries: Vec<Entry>,
+    enabled: BTreeSet<Uuid>,
+}
+
+impl Observers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, name: &str, observer: impl Observer + 'static, policy: ErrorPolicy) {
+        self.entries.push(Entry { name: name.to_string(), observer: Box::new(observer), policy });
+    }
+
+    // Calls every observer, applying its error policy
+    fn notify(&mut self, mut call: impl FnMut(&mut dyn Observer) -> Result<(), String>) -> Result<(), ObserverError> {
+        for entry in &mut self.entries {
+            if let Err(message) = call(entry.observer.as_mut()) {
+                match entry.policy {
+                    ErrorPolicy::Ignore => {}
+                    ErrorPolicy::Log => warn!("Observer {} failed: {}", entry.name, message),
+                    ErrorPolicy::Abort => return Err(ObserverError::Failed { observer: entry.name.clone(), message }),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Tells observers which transitions became enabled or disabled since the last refresh.
+    pub fn refresh(&mut self, net: &ColoredPetriNet) -> Result<(), ObserverError> {
+        let enabled: BTreeSet<Uuid> = net.enabled().into_iter().map(|(t, _)| t).collect();
+        let disabled = self.enabled.difference(&enabled).copied().collect::<Vec<_>>();
+        let newly = enabled.difference(&self.enabled).copied().collect::<Vec<_>>();
+        self.enabled = enabled;
+        for transition in disabled {
+            self.notify(|o| o.disabled(net, &transition))?;
+        }
+        for transition in newly {
+            self.notify(|o| o.enabled(net, &transition))?;
+        }
+        Ok(())
+    }
+
+    /// Fires a transition unless an observer vetoes it, and tells the observers about the tokens
+    /// destroyed and created, the firing, and the change in enabled transitions, in that order.
+    pub fn fire(&mut self, net: &mut ColoredPetriNet, transition: &Uuid, binding: &Binding) -> Result<(), ObserverError> {
+        for entry in &mut self.entries {
+            match entry.observer.before_fire(net, transition, binding) {
+                Ok(Verdict::Allow) => {}
+                Ok(Verdict::Veto(reason)) => return Err(ObserverError::Vetoed { observer: entry.name.clone(), reason }),
+                Err(message) => match entry.policy {
+                    ErrorPolicy::Ignore => {}
+                    ErrorPolicy::Log => warn!("Observer {} failed: {}", entry.name, message),
+                    ErrorPolicy::Abort => return Err(ObserverError::Failed { observer: entry.name.clone(), message }),
+                },
+            }
+        }
+        let before = net.current_marking.clone();
+        net.fire(transition, binding)?;
+        let net = &*net;
+        let destroyed = &before - &net.current_marking;
+        let created = &net.current_marking - &before;
+        for place in destroyed.places() {
+            for token in destroyed.tokens(&place) {
+                self.notify(|o| o.token_destroyed(net, &place, token))?;
+            }
+        }
+        for place in created.places() {
+            for token in created.tokens(&place) {
+                self.notify(|o| o.token_created(net, &place, token))?;
+            }
+        }
+        self.notify(|o| o.fired(net, transition, binding))?;
+        self.refresh(net)
+    }
+
+    /// Fires one enabled transition chosen by the policy, or returns `None` in a deadlock.
+    pub fn step(&mut self, net: &mut ColoredPetriNet, policy: &mut ConflictPolicy) -> Result<Option<(Uuid, Binding)>, ObserverError> {
+        let candidates = net.enabled();
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+        let (transition, binding) = candidates[policy.choose(net, &candidates)].clone();
+        self.fire(net, &transition, &binding)?;
+        Ok(Some((transition, binding)))
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = self.entries.iter().map(|e| (&e.name, e.policy)).collect::<Vec<_>>();
+        f.debug_struct("Observers").field("entries", &names).field("enabled", &self.enabled).finish()
+    }
+}
+
+#[test]
+pub fn observe_and_veto() {
+    use std::cell::RefCell;
+    use std::collections::{HashMap, HashSet};
+    use std::rc::Rc;
+    use crate::clade::Clade;
+    use crate::place::Place;
+    use crate::signature::Signature;
+    use crate::transition::Transition;
+
+    // Writes every event it sees into a shared log
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+    impl Observer for Recorder {
+        fn enabled(&mut self, net: &ColoredPetriNet, transition: &Uuid) -> Result<(), String> {
+            self.0.borrow_mut().push(format!("enabled {}", net.transitions[transition].name));
+            Ok(())
+        }
+        fn disabled(&mut self, net: &ColoredPetriNet, transition: &Uuid) -> Result<(), String> {
+            self.0.borrow_mut().push(format!("disabled {}", net.transitions[transition].name));
+            Ok(())
+        }
+        fn fired(&mut self, net: &ColoredPetriNet, transition: &Uuid, _: &Binding) -> Result<(), String> {
+            self.0.borrow_mut().push(format!("fired {}", net.transitions[transition].name));
+            Ok(())
+        }
+        fn token_created(&mut self, net: &ColoredPetriNet, place: &Uuid, token: &Token) -> Result<(), String> {
+            self.0.borrow_mut().push(format!("created {} in {}", token.name, net.places[place].name));
+            Ok(())
+        }
+        fn token_destroyed(&mut self, net: &ColoredPetriNet, place: &Uuid, token: &Token) -> Result<(), String> {
+            self.0.borrow_mut().push(format!("destroyed {} in {}", token.name, net.places[place].name));
+            Ok(())
+        }
+    }
+
+    // Keeps the robot from leaving while the door is open, and fails on every firing
+    struct Door {
+        open: bool,
+    }
+    impl Observer for Door {
+        fn before_fire(&mut self, net: &ColoredPetriNet, transition: &Uuid, _: &Binding) -> Result<Verdict, String> {
+            Ok(if self.open && net.transitions[transition].name == "leave" { Verdict::Veto("door open".into()) } else { Verdict::Allow })
+        }
+        fn fired(&mut self, _: &ColoredPetriNet, _: &Uuid, _: &Binding) -> Result<(), String> {
+            Err("sensor offline".into())
+        }
+    }
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    let outside = net.add_place(Place::new("outside".into()));
+    let inside = net.add_place(Place::new("inside".into()));
+    net.add_token(outside, Token::new("r1".into(), robot1.clone()));
+    let sig = || Signature::new(HashSet::from(["x".into()]));
+    let arcs = |from, to| (Some(HashMap::from([(from, sig())])), Some(HashMap::from([(to, sig())])));
+    let (i, o) = arcs(outside, inside);
+    let enter = net.add_transition(Transition::new("enter".into(), i, o, None, None));
+    let (i, o) = arcs(inside, outside);
+    let leave = net.add_transition(Transition::new("leave".into(), i, o, None, None));
+
+    let log = Rc::new(RefCell::new(vec![]));
+    let mut observers = Observers::new();
+    observers.add("recorder", Recorder(log.clone()), ErrorPolicy::Abort);
+    observers.add("door", Door { open: true }, ErrorPolicy::Log);
+    observers.refresh(&net).unwrap();
+    let mut policy = ConflictPolicy::DeclarationOrder;
+    assert_eq!(observers.step(&mut net, &mut policy).unwrap().unwrap().0, enter);
+    assert_eq!(
+        *log.borrow(),
+        ["enabled enter", "destroyed r1 in outside", "created r1 in inside", "fired enter", "disabled enter", "enabled leave"]
+    );
+
+    let binding = net.enabled()[0].1.clone();
+    assert_eq!(
+        observers.fire(&mut net, &leave, &binding),
+        Err(ObserverError::Vetoed { observer: "door".into(), reason: "door open".into() })
+    );
+    assert_eq!(net.current_marking.count(&inside), 1);
+
+    // The same failure stops execution once the door observer aborts on errors
+    let mut strict = Observers::new();
+    strict.add("door", Door { open: false }, ErrorPolicy::Abort);
+    assert!(matches!(strict.fire(&mut net, &leave, &binding), Err(ObserverError::Failed { .. })));
+    assert_eq!(net.current_marking.count(&outside), 1);
+}
@@ -0,0 +1,247 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use crate::marking::Marking;
+use crate::net::{ColoredPetriNet, Firing};
+use crate::statespace::{explore_with, Edge, StateSpace};
+
+/// How to spread the exploration of a state space over threads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Parallelism {
+    pub threads: usize,
+    // Number markings exactly as sequential exploration would, at the cost of expanding
+    // markings level by level
+    pub deterministic: bool,
+}
+
+impl Parallelism {
+    pub fn new(threads: usize) -> Self {
+        Self { threads: threads.max(1), deterministic: false }
+    }
+
+    /// One thread per core, as far as the platform can tell.
+    pub fn available() -> Self {
+        Self::new(thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    pub fn deterministic(mut self) -> Self {
+        self.deterministic = true;
+        self
+    }
+}
+
+impl Default for Parallelism {
+    fn default() -> Self {
+        Self::available()
+    }
+}
+
+/// Explores the markings reachable from the current marking of the net on several threads,
+/// finding the same markings and edges as [`StateSpace::explore`].
+///
+/// By default, threads take markings from their own end of a shared set of queues and steal
+/// from the others when theirs runs dry, and new markings are recognized in a visited set split
+/// into shards by the hash of the marking. Markings are then numbered in the order they were
+/// found, which depends on scheduling, except that expanded markings come first. Deterministic
+/// exploration instead expands the markings of each breadth-first level in parallel and merges
+/// their successors in order, giving exactly the state space of sequential exploration.
+pub fn explore(net: &ColoredPetriNet, limit: Option<usize>, parallelism: Parallelism) -> StateSpace {
+    if parallelism.deterministic {
+        explore_levels(net, limit, parallelism.threads)
+    } else {
+        explore_stealing(net, limit, parallelism.threads)
+    }
+}
+
+// Expands the markings of a level in parallel the first time one of them is asked for, and
+// hands their firings to sequential exploration in order
+fn explore_levels(net: &ColoredPetriNet, limit: Option<usize>, threads: usize) -> StateSpace {
+    let (mut start, mut firings): (usize, Vec<Vec<Firing>>) = (0, vec![]);
+    explore_with(net, limit, Marking::clone, |source, markings, _| {
+        if source >= start + firings.len() {
+            start = source;
+            firings = expand(net, &markings[source..], threads);
+        }
+        std::mem::take(&mut firings[source - start])
+    })
+}
+
+// The firings of each marking of a level, computed on several threads
+fn expand(net: &ColoredPetriNet, level: &[Marking], threads: usize) -> Vec<Vec<Firing>> {
+    let next = AtomicUsize::new(0);
+    let mut firings: Vec<(usize, Vec<Firing>)> = thread::scope(|scope| {
+        let workers = (0..threads.min(level.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = vec![];
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= level.len() {
+                            return done;
+                        }
+                        done.push((i, net.firings(&level[i])));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        workers.into_iter().flat_map(|w| w.join().expect("exploration thread panicked")).collect()
+    });
+    firings.sort_by_key(|(i, _)| *i);
+    firings.into_iter().map(|(_, f)| f).collect()
+}
+
+// Markings found so far, split into shards that are locked separately
+struct Visited {
+    shards: Vec<Mutex<HashMap<Marking, usize>>>,
+    next: AtomicUsize,
+}
+
+impl Visited {
+    // The number of the marking, and whether it is new
+    fn insert(&self, marking: Marking) -> (usize, bool) {
+        let mut hasher = DefaultHasher::new();
+        marking.hash(&mut hasher);
+        let shard = &self.shards[hasher.finish() as usize % self.shards.len()];
+        let mut shard = shard.lock().expect("visited set poisoned");
+        if let Some(id) = shard.get(&marking) {
+            return (*id, false);
+        }
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        shard.insert(marking, id);
+        (id, true)
+    }
+}
+
+fn explore_stealing(net: &ColoredPetriNet, limit: Option<usize>, threads: usize) -> StateSpace {
+    let visited = Visited { shards: (0..threads * 16).map(|_| Mutex::new(HashMap::new())).collect(), next: AtomicUsize::new(0) };
+    let queues: Vec<Mutex<VecDeque<(usize, Marking)>>> = (0..threads).map(|_| Mutex::new(VecDeque::new())).collect();
+    let (start, _) = visited.insert(net.current_marking.clone());
+    queues[0].lock().expect("queue poisoned").push_back((start, net.current_marking.clone()));
+    // Markings queued or being expanded; exploration is over when none are left
+    let pending = AtomicUsize::new(1);
+
+    let results: Vec<(Vec<usize>, Vec<Edge>)> = thread::scope(|scope| {
+        let workers = (0..threads)
+            .map(|me| {
+                let (visited, queues, pending) = (&visited, &queues, &pending);
+                scope.spawn(move || {
+                    let (mut expanded, mut edges) = (vec![], vec![]);
+                    loop {
+                        // Newest work from our own queue, else the oldest from another
+                        let own = queues[me].lock().expect("queue poisoned").pop_back();
+                        let item = own.or_else(|| {
+                            (1..threads).find_map(|k| queues[(me + k) % threads].lock().expect("queue poisoned").pop_front())
+                        });
+                        let Some((source, marking)) = item else {
+                            if pending.load(Ordering::Acquire) == 0 {
+                                return (expanded, edges);
+                            }
+                            thread::yield_now();
+                            continue;
+                        };
+                        if limit.is_none_or(|l| visited.next.load(Ordering::Relaxed) < l) {
+                            for (transition, binding, next) in net.firings(&marking) {
+                                let (target, new) = visited.insert(next.clone());
+                                if new {
+                                    pending.fetch_add(1, Ordering::AcqRel);
+                                    queues[me].lock().expect("queue poisoned").push_back((target, next));
+                                }
+                                edges.push(Edge { source, target, transition, binding });
+                            }
+                            expanded.push(source);
+                        }
+                        pending.fetch_sub(1, Ordering::AcqRel);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        workers.into_iter().map(|w| w.join().expect("exploration thread panicked")).collect()
+    });
+
+    // Renumber so that the expanded markings come first, in the order they were found
+    let count = visited.next.load(Ordering::Relaxed);
+    let mut is_expanded = vec![false; count];
+    for id in results.iter().flat_map(|(expanded, _)| expanded) {
+        is_expanded[*id] = true;
+    }
+    let order = (0..count).filter(|id| is_expanded[*id]).chain((0..count).filter(|id| !is_expanded[*id])).collect::<Vec<_>>();
+    let mut renumbered = vec![0; count];
+    for (new, old) in order.iter().enumerate() {
+        renumbered[*old] = new;
+    }
+    let mut markings: Vec<Option<Marking>> = vec![None; count];
+    for shard in visited.shards {
+        for (marking, id) in shard.into_inner().expect("visited set poisoned") {
+            markings[renumbered[id]] = Some(marking);
+        }
+    }
+    let mut edges = results
+        .into_iter()
+        .flat_map(|(_, edges)| edges)
+        .map(|e| Edge { source: renumbered[e.source], target: renumbered[e.target], ..e })
+        .collect::<Vec<_>>();
+    // Stable, so the edges of each marking stay in firing order
+    edges.sort_by_key(|e| e.source);
+    StateSpace {
+        markings: markings.into_iter().map(|m| m.expect("every numbered marking is stored")).collect(),
+        edges,
+        expanded: is_expanded.iter().filter(|e| **e).count(),
+    }
+}
+
+#[test]
+pub fn parallel_matches_sequential() {
+    use std::collections::HashSet;
+    use crate::clade::Clade;
+    use crate::place::Place;
+    use crate::signature::Signature;
+    use crate::token::Token;
+    use crate::transition::Transition;
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    let idle = net.add_place(Place::new("idle".into()));
+    let busy = net.add_place(Place::new("busy".into()));
+    let done = net.add_place(Place::new("done".into()));
+    for i in 0..5 {
+        net.add_token(idle, Token::new(format!("r{}", i), robot1.clone()));
+    }
+    let sig = || Signature::new(HashSet::from(["x".into()]));
+    let arcs = |from, to| (Some(HashMap::from([(from, sig())])), Some(HashMap::from([(to, sig())])));
+    for (name, from, to) in [("start", idle, busy), ("pause", busy, idle), ("finish", busy, done)] {
+        let (input, output) = arcs(from, to);
+        net.add_transition(Transition::new(name.into(), input, output, None, None));
+    }
+
+    let sequential = StateSpace::explore(&net, None);
+    assert_eq!(explore(&net, None, Parallelism::new(4).deterministic()), sequential);
+    assert_eq!(explore(&net, Some(50), Parallelism::new(3).deterministic()), StateSpace::explore(&net, Some(50)));
+
+    // The same graph, up to the numbering of markings
+    let graph = |space: &StateSpace| {
+        space
+            .edges
+            .iter()
+            .map(|e| (space.markings[e.source].key(), e.transition, e.binding.clone(), space.markings[e.target].key()))
+            .collect::<HashSet<_>>()
+    };
+    let stolen = explore(&net, None, Parallelism::new(4));
+    assert_eq!(stolen.markings.len(), sequential.markings.len());
+    assert_eq!(stolen.edges.len(), sequential.edges.len());
+    assert_eq!(stolen.markings[0], net.current_marking);
+    assert_eq!(graph(&stolen), graph(&sequential));
+    assert!(!stolen.truncated());
+
+    // A limited exploration expands a prefix of the markings, as sequential exploration does
+    let partial = explore(&net, Some(50), Parallelism::new(4));
+    assert!(partial.truncated());
+    assert!(partial.edges.iter().all(|e| e.source < partial.expanded));
+    let full = graph(&sequential);
+    assert!(graph(&partial).is_subset(&full));
+    for source in 0..partial.expanded {
+        assert_eq!(partial.successors(source).count(), sequential.successors(sequential.markings.iter().position(|m| *m == partial.markings[source]).unwrap()).count());
+    }
+}
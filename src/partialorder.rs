@@ -0,0 +1,397 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use itertools::Itertools;
+use uuid::Uuid;
+use crate::clade::Clade;
+use crate::marking::Marking;
+use crate::net::{ColoredPetriNet, Firing};
+use crate::query::{Ctl, Proposition, QueryError};
+use crate::statespace::{explore_with, StateSpace};
+use crate::symbol::Symbol;
+use crate::transition::Transition;
+
+// Beyond this many assignments of clades to the symbols of a guard, any clade is taken as possible
+const MAX_ASSIGNMENTS: usize = 4096;
+
+// Stands for the free room of a place with a capacity, which producers need and consumers make
+const ROOM: Uuid = Uuid::nil();
+
+// What a transition does to the tokens of one place, by the clades of the tokens involved
+#[derive(Clone, Debug, Default)]
+struct Access {
+    takes: HashSet<Uuid>,
+    puts: HashSet<Uuid>,
+    // Clades of tokens the transition binds, by input or read arcs
+    needs: HashSet<Uuid>,
+    // Clades of tokens that inhibit the transition
+    forbids: HashSet<Uuid>,
+    // How many tokens the transition binds
+    needed: usize,
+}
+
+impl Access {
+    fn writes(&self) -> impl Iterator<Item = &Uuid> {
+        self.takes.iter().chain(self.puts.iter())
+    }
+
+    fn reads(&self) -> impl Iterator<Item = &Uuid> {
+        self.needs.iter().chain(self.forbids.iter())
+    }
+}
+
+/// Partial-order reduction of state-space exploration with stubborn sets.
+///
+/// From each marking only the firings of a stubborn set of transitions are followed: one
+/// enabled transition, every transition that may interfere with an enabled member, and for a
+/// disabled member the transitions that could enable it. Two transitions are independent when
+/// neither can add or remove tokens of a clade the other binds or is inhibited by in some place;
+/// the clades a transition can bind are those its guard accepts among the clades of the tokens
+/// of the net, and producing into a place with a capacity depends on anything that changes how
+/// full it is.
+///
+/// The reduced state space keeps every reachable dead marking. Built with
+/// [`PartialOrder::preserving`], it also keeps the truth of a safety or reachability formula.
+/// Nets whose transitions have different priorities are not reduced.
+#[derive(Clone, Debug)]
+pub struct PartialOrder {
+    accesses: HashMap<Uuid, BTreeMap<Uuid, Access>>,
+    dependents: HashMap<Uuid, Vec<Uuid>>,
+    // Transitions whose firing may change the value of an atom of the formula preserved
+    visible: HashSet<Uuid>,
+    // Fully expand markings whose reduced successors were already found, so that no transition
+    // is postponed forever around a cycle
+    proviso: bool,
+    reduces: bool,
+}
+
+impl PartialOrder {
+    /// A reduction preserving the dead markings of the net.
+    pub fn new(net: &ColoredPetriNet) -> Self {
+        let universe = net
+            .current_marking
+            .values()
+            .flat_map(|tokens| tokens.values())
+            .map(|t| t.clade.clone())
+            .unique_by(|c| c.id())
+            .sorted_by_key(|c| (c.name(), c.id()))
+            .collect_vec();
+        let accesses: HashMap<Uuid, BTreeMap<Uuid, Access>> =
+            net.transitions.values().map(|t| (t.id, Self::access(net, t, &universe))).collect();
+        let dependent = |t: &Uuid, u: &Uuid| {
+            let interferes = |a: &BTreeMap<Uuid, Access>, b: &BTreeMap<Uuid, Access>| {
+                a.iter().any(|(place, a)| b.get(place).is_some_and(|b| a.writes().any(|c| b.reads().contains(c))))
+            };
+            interferes(&accesses[t], &accesses[u]) || interferes(&accesses[u], &accesses[t])
+        };
+        let dependents = accesses
+            .keys()
+            .map(|t| (*t, accesses.keys().filter(|u| *u != t && dependent(t, u)).sorted().copied().collect()))
+            .collect();
+        let reduces = net.transitions.values().map(|t| t.priority).all_equal();
+        Self { accesses, dependents, visible: HashSet::new(), proviso: false, reduces }
+    }
+
+    /// A reduction preserving the dead markings of the net and whether a formula holds, which
+    /// must be made of atoms, `AG` and `EF` of atoms, and boolean operators.
+    pub fn preserving(net: &ColoredPetriNet, formula: &Ctl) -> Result<Self, QueryError> {
+        if !invariant(formula) {
+            return Err(QueryError::NotPreserved);
+        }
+        let mut order = Self::new(net);
+        let mut places = HashSet::new();
+        for atom in formula.atoms() {
+            match atom.resolve(net)? {
+                Proposition::Has { place, .. } | Proposition::Count { place, .. } => {
+                    places.insert(place);
+                }
+                Proposition::Enabled(transition) => {
+                    let read = order.accesses[&transition].iter().filter(|(_, a)| a.reads().next().is_some());
+                    places.extend(read.map(|(place, _)| *place));
+                }
+                // Dead markings are kept anyway
+                Proposition::Deadlock => {}
+            }
+        }
+        order.visible = order
+            .accesses
+            .iter()
+            .filter(|(_, accesses)| accesses.iter().any(|(place, a)| places.contains(place) && a.writes().next().is_some()))
+            .map(|(t, _)| *t)
+            .collect();
+        order.proviso = true;
+        Ok(order)
+    }
+
+    // The clades of tokens a transition may take from, put into, bind in and be inhibited by
+    // each place
+    fn access(net: &ColoredPetriNet, transition: &Transition, universe: &[Clade]) -> BTreeMap<Uuid, Access> {
+        let every: HashSet<Uuid> = universe.iter().map(|c| c.id()).collect();
+        let possible = transition.guard.satisfying_clades(universe, MAX_ASSIGNMENTS).unwrap_or_default();
+        let clades = |symbol: &Symbol| possible.get(symbol).unwrap_or(&every).clone();
+
+        let mut accesses: BTreeMap<Uuid, Access> = BTreeMap::new();
+        for (place, signature) in transition.input.iter().chain(transition.reads.iter()) {
+            let access = accesses.entry(*place).or_default();
+            for symbol in &signature.symbols {
+                access.needs.extend(clades(symbol));
+                access.needed += 1;
+            }
+        }
+        for (place, signature) in &transition.input {
+            let access = accesses.entry(*place).or_default();
+            for symbol in &signature.symbols {
+                access.takes.extend(clades(symbol));
+            }
+        }
+        for (place, signature) in &transition.output {
+            let access = accesses.entry(*place).or_default();
+            for symbol in &signature.symbols {
+                access.puts.extend(clades(symbol));
+            }
+        }
+        for (place, clade) in &transition.inhibitors {
+            let forbidden = universe.iter().filter(|c| clade.as_ref().is_none_or(|clade| clade.descendent(&c.id())));
+            accesses.entry(*place).or_default().forbids.extend(forbidden.map(|c| c.id()));
+        }
+        for place in &transition.resets {
+            accesses.entry(*place).or_default().takes.extend(every.iter().copied());
+        }
+        for (place, access) in accesses.iter_mut() {
+            if net.places.get(place).is_some_and(|p| p.capacity.is_some()) {
+                if !access.puts.is_empty() {
+                    access.needs.insert(ROOM);
+                    access.puts.insert(ROOM);
+                } else if !access.takes.is_empty() {
+                    access.takes.insert(ROOM);
+                }
+            }
+        }
+        accesses
+    }
+
+    /// Whether firing either transition can never enable, disable or change the effect of the
+    /// other.
+    pub fn independent(&self, transition: &Uuid, other: &Uuid) -> bool {
+        transition != other && self.dependents.get(transition).is_some_and(|d| !d.contains(other))
+    }
+
+    /// The firings to follow from a marking: those of the stubborn set with the fewest firings,
+    /// or every firing when the net is not reduced.
+    pub fn ample(&self, net: &ColoredPetriNet, marking: &Marking) -> Vec<Firing> {
+        self.reduce(net, marking, net.firings(marking))
+    }
+
+    fn reduce(&self, net: &ColoredPetriNet, marking: &Marking, firings: Vec<Firing>) -> Vec<Firing> {
+        if !self.reduces || firings.is_empty() {
+            return firings;
+        }
+        let enabled: HashSet<Uuid> = firings.iter().map(|(t, _, _)| *t).collect();
+        let keys = firings.iter().map(|(t, _, _)| *t).unique();
+        let Some(stubborn) = keys
+            .map(|key| self.stubborn(net, marking, key, &enabled))
+            .min_by_key(|set| firings.iter().filter(|(t, _, _)| set.contains(t)).count())
+        else {
+            return firings;
+        };
+        firings.into_iter().filter(|(t, _, _)| stubborn.contains(t)).collect()
+    }
+
+    fn stubborn(&self, net: &ColoredPetriNet, marking: &Marking, key: Uuid, enabled: &HashSet<Uuid>) -> HashSet<Uuid> {
+        let mut set = HashSet::from([key]);
+        let mut work = vec![key];
+        let mut visible = false;
+        while let Some(transition) = work.pop() {
+            let mut added = if enabled.contains(&transition) {
+                self.dependents[&transition].clone()
+            } else {
+                self.enablers(net, marking, &transition)
+            };
+            // Visible firings must not be reordered, so they are taken all or none
+            if enabled.contains(&transition) && self.visible.contains(&transition) && !visible {
+                visible = true;
+                added.extend(self.visible.iter().copied());
+            }
+            for other in added {
+                if set.insert(other) {
+                    work.push(other);
+                }
+            }
+        }
+        set
+    }
+
+    // Transitions one of which must fire before a disabled transition can be enabled
+    fn enablers(&self, net: &ColoredPetriNet, marking: &Marking, transition: &Uuid) -> Vec<Uuid> {
+        let Some(t) = net.transitions.get(transition) else {
+            return vec![];
+        };
+        if t.substitution.is_some() {
+            return vec![];
+        }
+        let writers = |place: &Uuid, clades: &HashSet<Uuid>, puts: bool| {
+            self.accesses
+                .iter()
+                .filter(|(_, accesses)| {
+                    accesses.get(place).is_some_and(|a| if puts { &a.puts } else { &a.takes }.iter().any(|c| clades.contains(c)))
+                })
+                .map(|(u, _)| *u)
+                .sorted()
+                .collect_vec()
+        };
+        let accesses = &self.accesses[transition];
+        if let Some(place) = t.inhibited(marking) {
+            return writers(&place, &accesses[&place].forbids, false);
+        }
+        for (place, access) in accesses.iter().filter(|(_, a)| a.needed > 0) {
+            let available = marking.tokens(place).iter().filter(|token| access.needs.contains(&token.clade.id())).count();
+            if available < access.needed {
+                return writers(place, &access.needs, true);
+            }
+        }
+        // Disabled by its guard or the capacity of an output place
+        self.dependents[transition].clone()
+    }
+}
+
+// Whether the truth of a formula only depends on which markings are reachable
+fn invariant(formula: &Ctl) -> bool {
+    match formula {
+        Ctl::AG(f) | Ctl::EF(f) => f.is_state(),
+        Ctl::Not(f) => invariant(f),
+        Ctl::And(a, b) | Ctl::Or(a, b) => invariant(a) && invariant(b),
+        other => other.is_state(),
+    }
+}
+
+/// Explores the markings reachable from the current marking of the net as
+/// [`StateSpace::explore`] does, following only the firings the reduction picks.
+pub fn explore(net: &ColoredPetriNet, limit: Option<usize>, order: &PartialOrder) -> StateSpace {
+    explore_with(net, limit, Marking::clone, |source, markings, index| {
+        let all = net.firings(&markings[source]);
+        let count = all.len();
+        let firings = order.reduce(net, &markings[source], all.clone());
+        if order.proviso && firings.len() < count && firings.iter().any(|(_, _, next)| index.contains_key(next)) {
+            return all;
+        }
+        firings
+    })
+}
+
+#[test]
+pub fn preserves_deadlocks() {
+    use crate::guard::Guard;
+    use crate::modelcheck::ModelChecker;
+    use crate::place::{Capacity, Place};
+    use crate::signature::Signature;
+    use crate::token::Token;
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let robot2 = Clade::new("robot2".into(), None);
+    let robot = Clade::new("robot".into(), Some(vec![robot1.clone(), robot2.clone()]));
+    let sig = |symbols: &[&str]| Signature::new(symbols.iter().map(|s| Symbol::new(s.to_string())).collect());
+    let arc = |place: Uuid, symbols: &[&str]| Some(HashMap::from([(place, sig(symbols))]));
+    let new_net = |name: &str| {
+        let mut net = ColoredPetriNet::new(name.into(), None, None, None);
+        net.add_clade(robot.clone());
+        net
+    };
+    let mut benchmarks = vec![];
+
+    // Robots working in separate cells, which interleave in every possible order
+    let mut cells = new_net("cells");
+    let mut ids = vec![];
+    for i in 0..4 {
+        let idle = cells.add_place(Place::new(format!("idle{}", i)));
+        let busy = cells.add_place(Place::new(format!("busy{}", i)));
+        let done = cells.add_place(Place::new(format!("done{}", i)));
+        cells.add_token(idle, Token::new(format!("r{}", i), robot1.clone()));
+        ids.push(cells.add_transition(Transition::new(format!("start{}", i), arc(idle, &["x"]), arc(busy, &["x"]), None, None)));
+        cells.add_transition(Transition::new(format!("finish{}", i), arc(busy, &["x"]), arc(done, &["x"]), None, None));
+    }
+    benchmarks.push(cells.clone());
+
+    // Dining philosophers, who deadlock when each holds their left fork
+    let mut dining = new_net("dining");
+    let forks = (0..4).map(|i| dining.add_place(Place::new(format!("fork{}", i)))).collect_vec();
+    for i in 0..4 {
+        let thinking = dining.add_place(Place::new(format!("thinking{}", i)));
+        let holding = dining.add_place(Place::new(format!("holding{}", i)));
+        let eating = dining.add_place(Place::new(format!("eating{}", i)));
+        dining.add_token(thinking, Token::new(format!("p{}", i), robot1.clone()));
+        dining.add_token(forks[i], Token::new(format!("f{}", i), robot2.clone()));
+        let (left, right) = (forks[i], forks[(i + 1) % 4]);
+        let input = HashMap::from([(thinking, sig(&["p"])), (left, sig(&["l"]))]);
+        dining.add_transition(Transition::new(format!("take{}", i), Some(input), arc(holding, &["p", "l"]), None, None));
+        let input = HashMap::from([(holding, sig(&["p", "l"])), (right, sig(&["r"]))]);
+        dining.add_transition(Transition::new(format!("eat{}", i), Some(input), arc(eating, &["p", "l", "r"]), None, None));
+    }
+    benchmarks.push(dining);
+
+    // Robots of two kinds sharing a pool, each kind picked by its own transition's guard
+    let mut pool = new_net("pool");
+    let shared = pool.add_place(Place::new("shared".into()));
+    let first = pool.add_place(Place::new("first".into()));
+    let second = pool.add_place(Place::new("second".into()));
+    for i in 0..3 {
+        pool.add_token(shared, Token::new(format!("a{}", i), robot1.clone()));
+        pool.add_token(shared, Token::new(format!("b{}", i), robot2.clone()));
+    }
+    let pick1 = pool.add_transition(
+        Transition::new("pick1".into(), arc(shared, &["x"]), arc(first, &["x"]), None, None).with_guard(Guard::Is("x".into(), robot1.clone())),
+    );
+    let pick2 = pool.add_transition(
+        Transition::new("pick2".into(), arc(shared, &["y"]), arc(second, &["y"]), None, None).with_guard(Guard::Is("y".into(), robot2.clone())),
+    );
+    let any = pool.add_transition(Transition::new("any".into(), arc(first, &["z"]), arc(second, &["z"]), None, None));
+    benchmarks.push(pool.clone());
+
+    // A bounded buffer between a producer and a consumer, with an alarm inhibited by the buffer
+    let mut buffer = new_net("buffer");
+    let ready = buffer.add_place(Place::new("ready".into()));
+    let slots = buffer.add_place(Place::new("slots".into()).with_capacity(Capacity::Total(2)));
+    let consumed = buffer.add_place(Place::new("consumed".into()));
+    let alarm = buffer.add_place(Place::new("alarm".into()));
+    for i in 0..4 {
+        buffer.add_token(ready, Token::new(format!("item{}", i), robot1.clone()));
+    }
+    buffer.add_token(alarm, Token::new("siren".into(), robot2.clone()));
+    buffer.add_transition(Transition::new("produce".into(), arc(ready, &["x"]), arc(slots, &["x"]), None, None));
+    buffer.add_transition(Transition::new("consume".into(), arc(slots, &["x"]), arc(consumed, &["x"]), None, None));
+    buffer.add_transition(
+        Transition::new("ring".into(), arc(alarm, &["s"]), arc(consumed, &["s"]), None, None).with_inhibitor(ready, None),
+    );
+    benchmarks.push(buffer);
+
+    for net in &benchmarks {
+        let full = StateSpace::explore(net, None);
+        let reduced = explore(net, None, &PartialOrder::new(net));
+        let dead = |space: &StateSpace| space.dead().into_iter().map(|m| space.markings[m].key()).collect::<HashSet<_>>();
+        assert!(!reduced.truncated());
+        assert_eq!(dead(&reduced), dead(&full), "{}", net.name);
+        assert!(reduced.markings.len() <= full.markings.len(), "{}", net.name);
+    }
+    let order = PartialOrder::new(&cells);
+    assert!(order.independent(&ids[0], &ids[1]));
+    assert_eq!(StateSpace::explore(&cells, None).markings.len(), 81);
+    assert_eq!(explore(&cells, None, &order).markings.len(), 9);
+
+    // Guards that pick disjoint clades from a shared place make transitions independent
+    let order = PartialOrder::new(&pool);
+    assert!(order.independent(&pick1, &pick2));
+    assert!(!order.independent(&pick1, &any));
+    assert!(order.independent(&pick2, &any));
+
+    // Safety is preserved for the places the formula looks at
+    let formula = Ctl::parse("AG !(has(done0, robot1) & has(busy1, robot1))").unwrap();
+    let checker = ModelChecker::reduced(&cells, None, &formula).unwrap();
+    let result = checker.check_ctl(&formula).unwrap();
+    assert!(!result.holds);
+    assert!(checker.space.markings.len() < 81);
+    // Not necessarily a shortest counterexample, as the reduction may fire invisible transitions first
+    let last = checker.space.edges[*result.trace.unwrap().prefix.last().unwrap()].target;
+    let marking = &checker.space.markings[last];
+    assert!(marking.count_of(&cells.id_of("done0").unwrap(), &robot1) == 1 && marking.count(&cells.id_of("busy1").unwrap()) == 1);
+    let holds = Ctl::parse("AG count(done2) <= 1 & EF has(done3, robot1)").unwrap();
+    assert!(ModelChecker::reduced(&cells, None, &holds).unwrap().check_ctl(&holds).unwrap().holds);
+    let liveness = Ctl::parse("AF has(done0, robot1)").unwrap();
+    assert!(matches!(PartialOrder::preserving(&cells, &liveness), Err(QueryError::NotPreserved)));
+}
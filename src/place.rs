@@ -1,17 +1,167 @@
+use std::collections::HashMap;
+use std::fmt;
 use uuid::Uuid;
 use serde::{Serialize, Deserialize};
+use crate::clade::Clade;
+use crate::token::Token;
 // use crate::petri::token::TokenSet;
 
+/// The most tokens a place may hold, either in total or separately for tokens of given clades
+/// (counting every descendant of each clade).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Capacity {
+    Total(usize),
+    PerClade(Vec<(Clade, usize)>),
+}
+
+/// Marks a place of a subnet as an interface to the surrounding net. Port places are bound to
+/// places of the surrounding net (their sockets) when the subnet is instantiated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Port {
+    In,
+    Out,
+    InOut,
+}
+
+/// Ways in which the tokens of a place can break its constraints.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlaceViolation {
+    // A token whose clade is not among the accepted colors
+    Rejected { token: Uuid },
+    // More tokens than the capacity allows, in total or of the given clade
+    OverCapacity { clade: Option<Uuid>, limit: usize, count: usize },
+}
+
+impl fmt::Display for PlaceViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlaceViolation::Rejected { token } => write!(f, "token {} is not of an accepted clade", token),
+            PlaceViolation::OverCapacity { clade: None, limit, count } => {
+                write!(f, "{} tokens exceed the capacity of {}", count, limit)
+            }
+            PlaceViolation::OverCapacity { clade: Some(clade), limit, count } => {
+                write!(f, "{} tokens of clade {} exceed the capacity of {}", count, clade, limit)
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct Place {
     pub id: Uuid,
     pub name: String,
+    // Clades whose descendants the place accepts; any token is accepted when empty
+    #[serde(default)]
+    pub colors: Vec<Clade>,
+    #[serde(default)]
+    pub capacity: Option<Capacity>,
+    #[serde(default)]
+    pub port: Option<Port>,
     // pub tokens: TokenSet
 }
 
 impl Place {
     pub fn new(name: String) -> Self {
-        Self { id: Uuid::new_v4(), name }
+        Self { id: Uuid::new_v4(), name, colors: vec![], capacity: None, port: None }
+    }
+
+    /// Restricts the place to tokens descending from one of the given clades.
+    pub fn with_colors(mut self, colors: Vec<Clade>) -> Self {
+        self.colors = colors;
+        self
+    }
+
+    pub fn with_capacity(mut self, capacity: Capacity) -> Self {
+        self.capacity = Some(capacity);
+        self
     }
-}
\ No newline at end of file
+
+    pub fn with_port(mut self, port: Port) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Whether tokens of a clade may be stored in the place.
+    pub fn accepts(&self, clade: &Clade) -> bool {
+        self.colors.is_empty() || self.colors.iter().any(|c| c.descendent(&clade.id()))
+    }
+
+    /// Checks the tokens of the place against its colors and capacity.
+    pub fn check(&self, tokens: &HashMap<Uuid, Token>) -> Result<(), PlaceViolation> {
+        if let Some(token) = tokens.values().find(|t| !self.accepts(&t.clade)) {
+            return Err(PlaceViolation::Rejected { token: token.id });
+        }
+        match &self.capacity {
+            Some(Capacity::Total(limit)) if tokens.len() > *limit => {
+                Err(PlaceViolation::OverCapacity { clade: None, limit: *limit, count: tokens.len() })
+            }
+            Some(Capacity::PerClade(limits)) => {
+                for (clade, limit) in limits {
+                    let count = tokens.values().filter(|t| clade.descendent(&t.clade.id())).count();
+                    if count > *limit {
+                        return Err(PlaceViolation::OverCapacity { clade: Some(clade.id()), limit: *limit, count });
+                    }
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[test]
+pub fn place_constraints() {
+    use std::collections::HashSet;
+    use crate::guard::Guard;
+    use crate::net::ColoredPetriNet;
+    use crate::signature::Signature;
+    use crate::transition::{FiringError, Transition};
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let robot2 = Clade::new("robot2".into(), None);
+    let robot = Clade::new("robot".into(), Some(vec![robot1.clone(), robot2.clone()]));
+    let part = Clade::new("part".into(), None);
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    net.add_clade(Clade::new("thing".into(), Some(vec![robot.clone(), part.clone()])));
+    let idle = net.add_place(Place::new("idle".into()).with_colors(vec![robot.clone()]));
+    let cell = net.add_place(Place::new("cell".into()).with_colors(vec![robot.clone()]).with_capacity(Capacity::Total(1)));
+    let parts = net.add_place(Place::new("parts".into()).with_colors(vec![part.clone()]));
+    net.add_token(idle, Token::new("r1".into(), robot1.clone()));
+    net.add_token(idle, Token::new("r2".into(), robot2.clone()));
+    let sig = || Signature::new(HashSet::from(["x".into()]));
+    let enter = net.add_transition(Transition::new("enter".into(), Some(HashMap::from([(idle, sig())])), Some(HashMap::from([(cell, sig())])), None, None));
+    // Robots can never become parts
+    let store = net.add_transition(Transition::new("store".into(), Some(HashMap::from([(cell, sig())])), Some(HashMap::from([(parts, sig())])), None, None));
+
+    assert!(net.places[&idle].check(&net.initial_marking[&idle]).is_ok());
+    assert!(!net.places[&parts].accepts(&robot1));
+    assert!(net.places[&idle].accepts(&robot2));
+
+    let (_, binding) = net.enabled()[0].clone();
+    net.fire(&enter, &binding).unwrap();
+    // The cell is full, so the other robot cannot enter
+    assert!(net.enabled().iter().all(|(t, _)| *t != enter));
+    let blocked = net.transitions[&enter].bindings(&net.current_marking)[0].clone();
+    assert!(matches!(
+        net.fire(&enter, &blocked),
+        Err(FiringError::PlaceViolation { place, violation: PlaceViolation::OverCapacity { clade: None, limit: 1, count: 2 } }) if place == cell
+    ));
+    // Moving the robot on is not offered as enabled, and firing it anyway fails
+    assert!(net.enabled().is_empty());
+    let binding = net.transitions[&store].bindings(&net.current_marking)[0].clone();
+    assert!(matches!(net.fire(&store, &binding), Err(FiringError::PlaceViolation { violation: PlaceViolation::Rejected { .. }, .. })));
+
+    let issues = net.check_places();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].transition, Some(store));
+    assert_eq!(issues[0].place, parts);
+
+    // A guard restricting the output to parts would fix the arc, but leaves the transition dead
+    net.transitions.get_mut(&store).unwrap().guard = Guard::Is("x".into(), part.clone());
+    assert!(net.check_places().is_empty());
+    net.add_token(parts, Token::new("r3".into(), robot1.clone()));
+    assert_eq!(net.check_places()[0].transition, None);
+}
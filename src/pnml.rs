@@ -0,0 +1,1365 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use itertools::Itertools;
+use roxmltree::{Document, Node};
+use uuid::Uuid;
+use crate::clade::Clade;
+use crate::guard::Guard;
+use crate::net::ColoredPetriNet;
+use crate::place::{Capacity, Place};
+use crate::signature::Signature;
+use crate::symbol::Symbol;
+use crate::token::Token;
+use crate::transition::Transition;
+use crate::unfold::PtNet;
+
+pub const PNML_NAMESPACE: &str = "http://www.pnml.org/version-2009/grammar/pnml";
+pub const HLPN_NET_TYPE: &str = "http://www.pnml.org/version-2009/grammar/highlevelnet";
+pub const SYMMETRIC_NET_TYPE: &str = "http://www.pnml.org/version-2009/grammar/symmetricnet";
+pub const PT_NET_TYPE: &str = "http://www.pnml.org/version-2009/grammar/ptnet";
+// Name used in <toolspecific> blocks carrying information that standard PNML cannot express
+pub const TOOL_NAME: &str = "colorpnet";
+
+/// Errors that prevent a PNML document from being read at all.
+#[derive(Debug)]
+pub enum PnmlError {
+    Xml(roxmltree::Error),
+    MissingNet,
+    MissingAttribute { element: String, attribute: String },
+}
+
+impl fmt::Display for PnmlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PnmlError::Xml(e) => write!(f, "invalid XML: {}", e),
+            PnmlError::MissingNet => write!(f, "document does not contain a <net> element"),
+            PnmlError::MissingAttribute { element, attribute } => {
+                write!(f, "<{}> is missing the required '{}' attribute", element, attribute)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PnmlError {}
+
+impl From<roxmltree::Error> for PnmlError {
+    fn from(e: roxmltree::Error) -> Self {
+        PnmlError::Xml(e)
+    }
+}
+
+/// A single construct that could not be carried across the conversion unchanged.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PnmlIssue {
+    // The PNML id (or net element name) the issue refers to
+    pub element: String,
+    pub message: String,
+}
+
+/// Everything that was dropped or approximated while reading or writing PNML.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PnmlReport {
+    pub issues: Vec<PnmlIssue>,
+}
+
+impl PnmlReport {
+    /// True if the conversion did not lose or approximate anything.
+    pub fn is_lossless(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    fn push(&mut self, element: &str, message: String) {
+        self.issues.push(PnmlIssue { element: element.to_string(), message });
+    }
+}
+
+impl fmt::Display for PnmlReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{}: {}", issue.element, issue.message)?;
+        }
+        Ok(())
+    }
+}
+
+fn place_ref(id: &Uuid) -> String {
+    format!("p-{}", id)
+}
+
+fn transition_ref(id: &Uuid) -> String {
+    format!("t-{}", id)
+}
+
+fn clade_ref(id: &Uuid) -> String {
+    format!("c-{}", id)
+}
+
+fn sort_ref(id: &Uuid) -> String {
+    format!("s-{}", id)
+}
+
+fn variable_ref(transition: &Uuid, symbol: &Symbol) -> String {
+    format!("v-{}-{}", transition, symbol)
+}
+
+// Recovers the uuid from an id written by this module, e.g. "p-<uuid>"
+fn parse_ref(id: &str) -> Option<Uuid> {
+    id.get(2..).and_then(|s| Uuid::parse_str(s).ok())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Minimal indenting XML writer, sufficient for the fixed PNML vocabulary
+struct XmlWriter {
+    out: String,
+    stack: Vec<String>,
+}
+
+impl XmlWriter {
+    fn new() -> Self {
+        Self { out: String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"), stack: vec![] }
+    }
+
+    fn start_tag(&mut self, tag: &str, attributes: &[(&str, &str)], empty: bool) {
+        self.out.push_str(&"  ".repeat(self.stack.len()));
+        self.out.push('<');
+        self.out.push_str(tag);
+        for (key, value) in attributes {
+            self.out.push_str(&format!(" {}=\"{}\"", key, escape(value)));
+        }
+        self.out.push_str(if empty { "/>\n" } else { ">\n" });
+    }
+
+    fn open(&mut self, tag: &str, attributes: &[(&str, &str)]) {
+        self.start_tag(tag, attributes, false);
+        self.stack.push(tag.to_string());
+    }
+
+    fn empty(&mut self, tag: &str, attributes: &[(&str, &str)]) {
+        self.start_tag(tag, attributes, true);
+    }
+
+    fn text(&mut self, tag: &str, text: &str) {
+        self.out.push_str(&"  ".repeat(self.stack.len()));
+        self.out.push_str(&format!("<{}>{}</{}>\n", tag, escape(text), tag));
+    }
+
+    fn close(&mut self) {
+        let tag = self.stack.pop().expect("unbalanced XML writer");
+        self.out.push_str(&"  ".repeat(self.stack.len()));
+        self.out.push_str(&format!("</{}>\n", tag));
+    }
+
+    fn name(&mut self, name: &str) {
+        self.open("name", &[]);
+        self.text("text", name);
+        self.close();
+    }
+}
+
+// A term of the PNML many-sorted algebra, restricted to what nets in this crate can produce
+#[derive(Clone)]
+enum Term {
+    Variable(String),
+    Constant(String),
+    Bool(bool),
+    Equality(Box<Term>, Box<Term>),
+    Inequality(Box<Term>, Box<Term>),
+    And(Vec<Term>),
+    Or(Vec<Term>),
+    Not(Box<Term>),
+    NumberOf(usize, Box<Term>),
+    Add(Vec<Term>),
+}
+
+impl Term {
+    fn write(&self, w: &mut XmlWriter) {
+        match self {
+            Term::Variable(v) => w.empty("variable", &[("refvariable", v)]),
+            Term::Constant(c) => w.empty("useroperator", &[("declaration", c)]),
+            Term::Bool(b) => w.empty("booleanconstant", &[("value", if *b { "true" } else { "false" })]),
+            Term::Equality(a, b) => Self::write_operator(w, "equality", &[a, b]),
+            Term::Inequality(a, b) => Self::write_operator(w, "inequality", &[a, b]),
+            // PNML boolean operators are binary, so longer chains are nested to the right
+            Term::And(terms) | Term::Or(terms) => {
+                let (tag, unit) = match self {
+                    Term::And(_) => ("and", true),
+                    _ => ("or", false),
+                };
+                match terms.len() {
+                    0 => Term::Bool(unit).write(w),
+                    1 => terms[0].write(w),
+                    _ => {
+                        w.open(tag, &[]);
+                        w.open("subterm", &[]);
+                        terms[0].write(w);
+                        w.close();
+                        w.open("subterm", &[]);
+                        let rest = terms[1..].to_vec();
+                        match self {
+                            Term::And(_) => Term::And(rest).write(w),
+                            _ => Term::Or(rest).write(w),
+                        }
+                        w.close();
+                        w.close();
+                    }
+                }
+            }
+            Term::Not(t) => Self::write_operator(w, "not", &[t]),
+            Term::NumberOf(n, t) => {
+                w.open("numberof", &[]);
+                w.open("subterm", &[]);
+                w.open("numberconstant", &[("value", &n.to_string())]);
+                w.empty("positive", &[]);
+                w.close();
+                w.close();
+                w.open("subterm", &[]);
+                t.write(w);
+                w.close();
+                w.close();
+            }
+            Term::Add(terms) => {
+                if terms.len() == 1 {
+                    return terms[0].write(w);
+                }
+                w.open("add", &[]);
+                for term in terms {
+                    w.open("subterm", &[]);
+                    term.write(w);
+                    w.close();
+                }
+                w.close();
+            }
+        }
+    }
+
+    fn write_operator(w: &mut XmlWriter, tag: &str, subterms: &[&Term]) {
+        w.open(tag, &[]);
+        for term in subterms {
+            w.open("subterm", &[]);
+            term.write(w);
+            w.close();
+        }
+        w.close();
+    }
+}
+
+// Clades referenced by a guard, including nested guards
+fn guard_clades(guard: &Guard) -> Vec<Clade> {
+    match guard {
+        Guard::Is(_, c)
+        | Guard::GreaterThan(_, c)
+        | Guard::LessThan(_, c)
+        | Guard::GreaterThanOrEqual(_, c)
+        | Guard::LessThanOrEqual(_, c)
+        | Guard::Not(_, c) => vec![c.clone()],
+        Guard::All(guards) | Guard::Any(guards) | Guard::None(guards) => {
+            guards.iter().flat_map(guard_clades).collect()
+        }
+        Guard::Empty => vec![],
+    }
+}
+
+// The registered root clades, followed by any clades used in the net that are not below one of them
+fn root_clades(net: &ColoredPetriNet) -> Vec<Clade> {
+    let mut roots = net.clades.clone();
+    let mut used: Vec<Clade> = net
+        .initial_marking
+        .values()
+        .flat_map(|tokens| tokens.values().map(|t| t.clade.clone()))
+        .chain(net.transitions.values().flat_map(|t| guard_clades(&t.guard)))
+        .chain(net.places.values().flat_map(place_clades))
+        .collect();
+    // Larger subtrees first, so that clades nested in another used clade are not made roots
+    used.sort_by_key(|c| std::cmp::Reverse(c.flatten().len()));
+    for clade in used {
+        if !roots.iter().any(|r| r.descendent(&clade.id())) {
+            roots.push(clade);
+        }
+    }
+    roots
+}
+
+// Clades a place declares as colors or capacity limits
+fn place_clades(place: &Place) -> Vec<Clade> {
+    let limits = match &place.capacity {
+        Some(Capacity::PerClade(limits)) => limits.iter().map(|(c, _)| c.clone()).collect_vec(),
+        _ => vec![],
+    };
+    place.colors.iter().cloned().chain(limits).collect()
+}
+
+fn root_of<'a>(roots: &'a [Clade], clade: &Uuid) -> Option<&'a Clade> {
+    roots.iter().find(|r| r.descendent(clade))
+}
+
+/// Writes a net as a PNML high-level net document.
+///
+/// Clade hierarchies become finite enumeration sorts, guards become boolean conditions
+/// (comparisons against the hierarchy are expanded into disjunctions of equalities), and
+/// signatures become sums of variables. Token identities, the exact clade hierarchy and the
+/// original guards are additionally kept in `<toolspecific>` blocks, so that reading the
+/// document back with [`from_pnml`] restores the net.
+pub fn to_pnml(net: &ColoredPetriNet) -> (String, PnmlReport) {
+    let mut report = PnmlReport::default();
+    let roots = root_clades(net);
+    let mut w = XmlWriter::new();
+    w.open("pnml", &[("xmlns", PNML_NAMESPACE)]);
+    let net_id = format!("n-{}", net.id);
+    w.open("net", &[("id", &net_id), ("type", HLPN_NET_TYPE)]);
+    w.name(&net.name);
+
+    // The exact hierarchy, which enumerations can only flatten
+    w.open("toolspecific", &[("tool", TOOL_NAME), ("version", env!("CARGO_PKG_VERSION"))]);
+    for root in &roots {
+        write_clade_tree(&mut w, root);
+    }
+    w.close();
+
+    let places = net.places.values().sorted_by_key(|p| (p.name.clone(), p.id)).collect_vec();
+    let transitions = net.declared_transitions();
+
+    // Every symbol of a transition is declared as a variable of the sort its guard (or tokens) imply
+    let mut variables: Vec<(String, Symbol, Option<Uuid>)> = vec![];
+    for transition in &transitions {
+        let symbols = transition
+            .input
+            .values()
+            .chain(transition.reads.values())
+            .chain(transition.output.values())
+            .flat_map(|s| s.symbols.iter().cloned())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .sorted()
+            .collect_vec();
+        for symbol in symbols {
+            let sort = symbol_sort(net, &roots, transition, &symbol);
+            if sort.is_none() && !roots.is_empty() {
+                report.push(
+                    &transition_ref(&transition.id),
+                    format!("the sort of symbol '{}' could not be inferred; it is declared with the sort {}", symbol, roots[0].name()),
+                );
+            }
+            variables.push((variable_ref(&transition.id, &symbol), symbol, sort.or(roots.first().map(|r| r.id()))));
+        }
+    }
+
+    w.open("declaration", &[]);
+    w.open("structure", &[]);
+    w.open("declarations", &[]);
+    for root in &roots {
+        w.open("namedsort", &[("id", &sort_ref(&root.id())), ("name", &root.name())]);
+        w.open("finiteenumeration", &[]);
+        for clade in root.flatten() {
+            w.empty("feconstant", &[("id", &clade_ref(&clade.id())), ("name", &clade.name())]);
+        }
+        w.close();
+        w.close();
+    }
+    for (id, symbol, sort) in &variables {
+        w.open("variabledecl", &[("id", id), ("name", symbol.name())]);
+        write_sort(&mut w, sort.as_ref());
+        w.close();
+    }
+    w.close();
+    w.close();
+    w.close();
+
+    w.open("page", &[("id", &format!("g-{}", net.id))]);
+    for place in &places {
+        w.open("place", &[("id", &place_ref(&place.id))]);
+        w.name(&place.name);
+        let sort = place_sort(net, &roots, &place.id);
+        if sort.len() > 1 {
+            report.push(
+                &place_ref(&place.id),
+                format!("place '{}' holds tokens of several taxonomies, but PNML places have a single sort", place.name),
+            );
+        }
+        w.open("type", &[]);
+        w.open("structure", &[]);
+        write_sort(&mut w, sort.first());
+        w.close();
+        w.close();
+        let tokens = net
+            .initial_marking
+            .get(&place.id)
+            .map(|tokens| tokens.values().sorted_by_key(|t| (t.name.clone(), t.id)).collect_vec())
+            .unwrap_or_default();
+        if !tokens.is_empty() {
+            let counts = tokens.iter().map(|t| t.clade.clone()).counts_by(|c| c.id());
+            let terms = counts
+                .iter()
+                .sorted_by_key(|(id, _)| net.clade(id).or_else(|| root_of(&roots, id).and_then(|r| r.get(id))).map(|c| c.name()))
+                .map(|(id, n)| Term::NumberOf(*n, Box::new(Term::Constant(clade_ref(id)))))
+                .collect_vec();
+            let text = counts
+                .iter()
+                .map(|(id, n)| (*n, roots.iter().find_map(|r| r.get(id)).map(|c| c.name()).unwrap_or_default()))
+                .sorted_by(|a, b| a.1.cmp(&b.1))
+                .map(|(n, name)| format!("{}'{}", n, name))
+                .join(" ++ ");
+            w.open("hlinitialMarking", &[]);
+            w.text("text", &text);
+            w.open("structure", &[]);
+            Term::Add(terms).write(&mut w);
+            w.close();
+            w.close();
+        }
+        // Exact tokens, colors and capacities, which the sort and multiset cannot express
+        if !tokens.is_empty() || !place.colors.is_empty() || place.capacity.is_some() {
+            w.open("toolspecific", &[("tool", TOOL_NAME), ("version", env!("CARGO_PKG_VERSION"))]);
+            for token in tokens {
+                w.empty(
+                    "token",
+                    &[("id", &token.id.to_string()), ("name", &token.name), ("clade", &clade_ref(&token.clade.id()))],
+                );
+            }
+            for color in &place.colors {
+                w.empty("color", &[("clade", &clade_ref(&color.id()))]);
+            }
+            match &place.capacity {
+                Some(Capacity::Total(limit)) => w.empty("capacity", &[("limit", &limit.to_string())]),
+                Some(Capacity::PerClade(limits)) => {
+                    for (clade, limit) in limits {
+                        w.empty("capacity", &[("limit", &limit.to_string()), ("clade", &clade_ref(&clade.id()))]);
+                    }
+                }
+                None => {}
+            }
+            w.close();
+        }
+        w.close();
+    }
+
+    for transition in &transitions {
+        w.open("transition", &[("id", &transition_ref(&transition.id))]);
+        w.name(&transition.name);
+        w.open("toolspecific", &[("tool", TOOL_NAME), ("version", env!("CARGO_PKG_VERSION"))]);
+        w.empty("function", &[("id", &transition.function.id.to_string())]);
+        if transition.guard != Guard::Empty {
+            w.text("guard", &serde_json::to_string(&transition.guard).unwrap_or_default());
+        }
+        if transition.priority != 0 {
+            w.empty("priority", &[("value", &transition.priority.to_string())]);
+        }
+        if let Some(label) = &transition.label {
+            w.empty("label", &[("value", label)]);
+        }
+        // Read arcs are also written as an input and an output arc, which other tools understand
+        for (place, signature) in transition.reads.iter().sorted_by_key(|(p, _)| **p) {
+            for symbol in signature.symbols.iter().sorted() {
+                w.empty("read", &[("place", &place_ref(place)), ("symbol", symbol.name())]);
+            }
+        }
+        for (place, clade) in transition.inhibitors.iter().sorted_by_key(|(p, _)| **p) {
+            let reference = clade.as_ref().map(|c| clade_ref(&c.id()));
+            match &reference {
+                Some(clade) => w.empty("inhibitor", &[("place", &place_ref(place)), ("clade", clade)]),
+                None => w.empty("inhibitor", &[("place", &place_ref(place))]),
+            }
+            report.push(&transition_ref(&transition.id), "inhibitor arcs are only kept in a tool-specific block".to_string());
+        }
+        if transition.substitution.is_some() {
+            report.push(&transition_ref(&transition.id), "substitution transition written as a plain transition; flatten the net first".to_string());
+        }
+        for place in transition.resets.iter().sorted() {
+            w.empty("reset", &[("place", &place_ref(place))]);
+            report.push(&transition_ref(&transition.id), "reset arcs are only kept in a tool-specific block".to_string());
+        }
+        w.close();
+        if transition.guard != Guard::Empty {
+            let variable = |s: &Symbol| variable_ref(&transition.id, s);
+            let term = guard_term(&transition.guard, &roots, &variable);
+            w.open("condition", &[]);
+            w.text("text", &transition.guard.to_string());
+            w.open("structure", &[]);
+            term.write(&mut w);
+            w.close();
+            w.close();
+        }
+        w.close();
+    }
+
+    let bound: HashMap<Uuid, HashSet<Symbol>> = transitions
+        .iter()
+        .map(|t| (t.id, t.input.values().chain(t.reads.values()).flat_map(|s| s.symbols.iter().cloned()).collect()))
+        .collect();
+    for transition in &transitions {
+        let with_reads = |arcs: &HashMap<Uuid, Signature>| {
+            let mut arcs = arcs.clone();
+            for (place, signature) in &transition.reads {
+                arcs.entry(*place).or_default().symbols.extend(signature.symbols.iter().cloned());
+            }
+            arcs
+        };
+        let (input, output) = (with_reads(&transition.input), with_reads(&transition.output));
+        let arcs = input
+            .iter()
+            .map(|(p, s)| (place_ref(p), transition_ref(&transition.id), s, "in"))
+            .chain(output.iter().map(|(p, s)| (transition_ref(&transition.id), place_ref(p), s, "out")))
+            .sorted_by(|a, b| (a.3, &a.0, &a.1).cmp(&(b.3, &b.0, &b.1)));
+        for (source, target, signature, direction) in arcs {
+            if direction == "out" {
+                for symbol in signature.symbols.iter().sorted() {
+                    if !bound[&transition.id].contains(symbol) {
+                        report.push(
+                            &transition_ref(&transition.id),
+                            format!("output symbol '{}' is not bound by any input and will be a free variable in PNML", symbol),
+                        );
+                    }
+                }
+            }
+            let arc_id = format!("a-{}-{}", source, target);
+            w.open("arc", &[("id", &arc_id), ("source", &source), ("target", &target)]);
+            let symbols = signature.symbols.iter().sorted().collect_vec();
+            if !symbols.is_empty() {
+                w.open("hlinscription", &[]);
+                w.text("text", &symbols.iter().map(|s| format!("1'{}", s)).join(" ++ "));
+                w.open("structure", &[]);
+                Term::Add(
+                    symbols
+                        .iter()
+                        .map(|s| Term::NumberOf(1, Box::new(Term::Variable(variable_ref(&transition.id, s)))))
+                        .collect(),
+                )
+                .write(&mut w);
+                w.close();
+                w.close();
+            } else {
+                report.push(&arc_id, "arc has an empty signature; it is written without an inscription".to_string());
+            }
+            w.close();
+        }
+    }
+    w.close();
+    w.close();
+    w.close();
+    (w.out, report)
+}
+
+/// Writes an unfolded net as a PNML place/transition net, for tools without coloured nets.
+pub fn pt_to_pnml(net: &PtNet) -> String {
+    let mut w = XmlWriter::new();
+    w.open("pnml", &[("xmlns", PNML_NAMESPACE)]);
+    w.open("net", &[("id", "net"), ("type", PT_NET_TYPE)]);
+    w.name(&net.name);
+    w.open("page", &[("id", "page")]);
+    for (i, place) in net.places.iter().enumerate() {
+        w.open("place", &[("id", &format!("p{}", i))]);
+        w.name(&place.name);
+        if net.initial_marking[i] > 0 {
+            w.open("initialMarking", &[]);
+            w.text("text", &net.initial_marking[i].to_string());
+            w.close();
+        }
+        w.close();
+    }
+    for (j, transition) in net.transitions.iter().enumerate() {
+        w.open("transition", &[("id", &format!("t{}", j))]);
+        w.name(&transition.name);
+        w.close();
+    }
+    for (j, transition) in net.transitions.iter().enumerate() {
+        let arcs = transition
+            .input
+            .iter()
+            .map(|(p, weight)| (format!("p{}", p), format!("t{}", j), weight))
+            .chain(transition.output.iter().map(|(p, weight)| (format!("t{}", j), format!("p{}", p), weight)));
+        for (source, target, weight) in arcs {
+            w.open("arc", &[("id", &format!("a-{}-{}", source, target)), ("source", &source), ("target", &target)]);
+            if *weight > 1 {
+                w.open("inscription", &[]);
+                w.text("text", &weight.to_string());
+                w.close();
+            }
+            w.close();
+        }
+    }
+    w.close();
+    w.close();
+    w.close();
+    w.out
+}
+
+fn write_clade_tree(w: &mut XmlWriter, clade: &Clade) {
+    let id = clade_ref(&clade.id());
+    match clade.children() {
+        Some(children) => {
+            w.open("clade", &[("id", &id), ("name", &clade.name())]);
+            for child in children {
+                write_clade_tree(w, child);
+            }
+            w.close();
+        }
+        None => w.empty("clade", &[("id", &id), ("name", &clade.name())]),
+    }
+}
+
+fn write_sort(w: &mut XmlWriter, sort: Option<&Uuid>) {
+    match sort {
+        Some(id) => w.empty("usersort", &[("declaration", &sort_ref(id))]),
+        None => w.empty("dot", &[]),
+    }
+}
+
+// Root clade constraining a symbol, taken from the guard and otherwise from the tokens of its input place
+fn symbol_sort(net: &ColoredPetriNet, roots: &[Clade], transition: &Transition, symbol: &Symbol) -> Option<Uuid> {
+    fn find(guard: &Guard, symbol: &Symbol) -> Option<Uuid> {
+        match guard {
+            Guard::Is(s, c)
+            | Guard::GreaterThan(s, c)
+            | Guard::LessThan(s, c)
+            | Guard::GreaterThanOrEqual(s, c)
+            | Guard::LessThanOrEqual(s, c)
+            | Guard::Not(s, c) if s == symbol => Some(c.id()),
+            Guard::All(guards) | Guard::Any(guards) | Guard::None(guards) => guards.iter().find_map(|g| find(g, symbol)),
+            _ => None,
+        }
+    }
+    if let Some(clade) = find(&transition.guard, symbol) {
+        return root_of(roots, &clade).map(|r| r.id());
+    }
+    transition
+        .input
+        .iter()
+        .chain(transition.reads.iter())
+        .find(|(_, s)| s.symbols.contains(symbol))
+        .and_then(|(place, _)| place_sort(net, roots, place).first().cloned())
+}
+
+// Root clades of the colors of a place and of the tokens initially in it
+fn place_sort(net: &ColoredPetriNet, roots: &[Clade], place: &Uuid) -> Vec<Uuid> {
+    let colors = net.places.get(place).map(|p| p.colors.iter().map(|c| c.id()).collect_vec()).unwrap_or_default();
+    let tokens = net.initial_marking.get(place).map(|tokens| tokens.values().map(|t| t.clade.id()).collect_vec()).unwrap_or_default();
+    colors
+        .iter()
+        .chain(&tokens)
+        .filter_map(|c| root_of(roots, c).map(|r| r.id()))
+        .unique()
+        .sorted()
+        .collect()
+}
+
+fn guard_term(guard: &Guard, roots: &[Clade], variable: &dyn Fn(&Symbol) -> String) -> Term {
+    // Comparisons against the hierarchy hold for a fixed set of clades, so they become a disjunction
+    let one_of = |symbol: &Symbol, clades: Vec<Uuid>| {
+        Term::Or(
+            clades
+                .iter()
+                .map(|c| Term::Equality(Box::new(Term::Variable(variable(symbol))), Box::new(Term::Constant(clade_ref(c)))))
+                .collect(),
+        )
+    };
+    let ancestors = |clade: &Clade| {
+        root_of(roots, &clade.id()).and_then(|r| r.parentage(&clade.id())).unwrap_or_default()
+    };
+    let descendents = |clade: &Clade| {
+        root_of(roots, &clade.id())
+            .and_then(|r| r.get(&clade.id()))
+            .unwrap_or_else(|| clade.clone())
+            .flatten()
+            .iter()
+            .map(|c| c.id())
+            .filter(|id| *id != clade.id())
+            .collect_vec()
+    };
+    match guard {
+        Guard::Is(s, c) => Term::Equality(Box::new(Term::Variable(variable(s))), Box::new(Term::Constant(clade_ref(&c.id())))),
+        Guard::Not(s, c) => Term::Inequality(Box::new(Term::Variable(variable(s))), Box::new(Term::Constant(clade_ref(&c.id())))),
+        Guard::GreaterThan(s, c) => one_of(s, ancestors(c)),
+        Guard::GreaterThanOrEqual(s, c) => one_of(s, std::iter::once(c.id()).chain(ancestors(c)).collect()),
+        Guard::LessThan(s, c) => one_of(s, descendents(c)),
+        Guard::LessThanOrEqual(s, c) => one_of(s, std::iter::once(c.id()).chain(descendents(c)).collect()),
+        Guard::All(guards) => Term::And(guards.iter().map(|g| guard_term(g, roots, variable)).collect()),
+        Guard::Any(guards) => Term::Or(guards.iter().map(|g| guard_term(g, roots, variable)).collect()),
+        Guard::None(guards) => Term::Not(Box::new(Term::Or(guards.iter().map(|g| guard_term(g, roots, variable)).collect()))),
+        Guard::Empty => Term::Bool(true),
+    }
+}
+
+fn children<'a, 'input>(node: Node<'a, 'input>, tag: &'a str) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children().filter(move |c| c.is_element() && c.tag_name().name() == tag)
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, tag: &'a str) -> Option<Node<'a, 'input>> {
+    children(node, tag).next()
+}
+
+fn attribute(node: Node, name: &str) -> Result<String, PnmlError> {
+    node.attribute(name).map(|s| s.to_string()).ok_or_else(|| PnmlError::MissingAttribute {
+        element: node.tag_name().name().to_string(),
+        attribute: name.to_string(),
+    })
+}
+
+fn name_text(node: Node) -> Option<String> {
+    child(node, "name").and_then(|n| child(n, "text")).and_then(|t| t.text()).map(|t| t.trim().to_string())
+}
+
+fn toolspecific<'a, 'input>(node: Node<'a, 'input>) -> Option<Node<'a, 'input>> {
+    children(node, "toolspecific").find(|n| n.attribute("tool") == Some(TOOL_NAME))
+}
+
+// The first element child of a <structure> or <subterm> wrapper
+fn first_element<'a, 'input>(node: Node<'a, 'input>) -> Option<Node<'a, 'input>> {
+    node.children().find(|c| c.is_element())
+}
+
+fn subterms<'a, 'input>(node: Node<'a, 'input>) -> Vec<Node<'a, 'input>> {
+    children(node, "subterm").filter_map(first_element).collect()
+}
+
+struct Reader {
+    report: PnmlReport,
+    // Clades by PNML constant id
+    constants: HashMap<String, Clade>,
+    // Symbol names by PNML variable id
+    variables: HashMap<String, Symbol>,
+    roots: Vec<Clade>,
+}
+
+impl Reader {
+    fn read_clade_tree(&mut self, node: Node) -> Result<Clade, PnmlError> {
+        let id = attribute(node, "id")?;
+        let name = attribute(node, "name")?;
+        let uuid = parse_ref(&id).unwrap_or_else(Uuid::new_v4);
+        let nested = children(node, "clade").collect_vec();
+        let clade = if nested.is_empty() {
+            Clade::Leaf { uuid, name }
+        } else {
+            let children = nested.into_iter().map(|n| self.read_clade_tree(n)).collect::<Result<Vec<_>, _>>()?;
+            Clade::Branch { uuid, name, children }
+        };
+        self.constants.insert(id, clade.clone());
+        Ok(clade)
+    }
+
+    fn read_declarations(&mut self, declarations: Node) -> Result<(), PnmlError> {
+        let partitions = children(declarations, "partition").collect_vec();
+        for sort in children(declarations, "namedsort") {
+            let id = attribute(sort, "id")?;
+            let name = attribute(sort, "name")?;
+            let definition = match first_element(sort) {
+                Some(d) => d,
+                None => continue,
+            };
+            match definition.tag_name().name() {
+                "finiteenumeration" | "cyclicenumeration" => {
+                    let constants = children(definition, "feconstant")
+                        .map(|c| Ok((attribute(c, "id")?, attribute(c, "name")?)))
+                        .collect::<Result<Vec<_>, PnmlError>>()?;
+                    if constants.iter().all(|(c, _)| self.constants.contains_key(c)) {
+                        // Already restored from the exact hierarchy
+                        continue;
+                    }
+                    if definition.tag_name().name() == "cyclicenumeration" {
+                        self.report.push(&id, "the cyclic order of the enumeration is not kept".to_string());
+                    }
+                    let leaf = |(c, n): &(String, String)| Clade::Leaf { uuid: parse_ref(c).unwrap_or_else(Uuid::new_v4), name: n.clone() };
+                    // A partition of the sort becomes an intermediate level of the hierarchy
+                    let partition = partitions.iter().find(|p| {
+                        child(**p, "usersort").and_then(|u| u.attribute("declaration")) == Some(id.as_str())
+                    });
+                    let mut grouped: HashSet<String> = HashSet::new();
+                    let mut root_children = vec![];
+                    if let Some(partition) = partition {
+                        for element in children(*partition, "partitionelement") {
+                            let members = children(element, "useroperator")
+                                .filter_map(|u| u.attribute("declaration"))
+                                .filter_map(|d| constants.iter().find(|(c, _)| c == d))
+                                .collect_vec();
+                            let leaves = members.iter().map(|m| leaf(m)).collect_vec();
+                            for (member, clade) in members.iter().zip(leaves.iter()) {
+                                grouped.insert(member.0.clone());
+                                self.constants.insert(member.0.clone(), clade.clone());
+                            }
+                            root_children.push(Clade::Branch {
+                                uuid: Uuid::new_v4(),
+                                name: attribute(element, "name")?,
+                                children: leaves,
+                            });
+                        }
+                    }
+                    for constant in constants.iter().filter(|(c, _)| !grouped.contains(c)) {
+                        let clade = leaf(constant);
+                        self.constants.insert(constant.0.clone(), clade.clone());
+                        root_children.push(clade);
+                    }
+                    let root = Clade::Branch { uuid: parse_ref(&id).unwrap_or_else(Uuid::new_v4), name, children: root_children };
+                    self.constants.insert(id, root.clone());
+                    self.roots.push(root);
+                }
+                "dot" => {
+                    let dot = self.dot();
+                    self.constants.insert(id, dot);
+                }
+                other => self.report.push(&id, format!("sort '{}' ({}) cannot be represented as a clade", name, other)),
+            }
+        }
+        for variable in children(declarations, "variabledecl") {
+            self.variables.insert(attribute(variable, "id")?, Symbol::new(attribute(variable, "name")?));
+        }
+        for other in declarations.children().filter(|c| c.is_element()) {
+            match other.tag_name().name() {
+                "namedsort" | "variabledecl" | "partition" => {}
+                tag => self.report.push(other.attribute("id").unwrap_or(tag), format!("declaration <{}> is not supported", tag)),
+            }
+        }
+        Ok(())
+    }
+
+    // The clade of uncoloured tokens, shared by the whole net
+    fn dot(&mut self) -> Clade {
+        if let Some(dot) = self.constants.get("dot") {
+            return dot.clone();
+        }
+        let dot = Clade::new("dot".into(), None);
+        self.constants.insert("dot".into(), dot.clone());
+        self.roots.push(dot.clone());
+        dot
+    }
+
+    // Multiset of clades denoted by a marking term
+    fn read_multiset(&mut self, element: &str, term: Node) -> Vec<(usize, Clade)> {
+        match term.tag_name().name() {
+            "add" => subterms(term).into_iter().flat_map(|t| self.read_multiset(element, t)).collect(),
+            "numberof" => {
+                let parts = subterms(term);
+                let count = parts
+                    .first()
+                    .filter(|n| n.tag_name().name() == "numberconstant")
+                    .and_then(|n| n.attribute("value"))
+                    .and_then(|v| v.parse::<usize>().ok());
+                match (count, parts.get(1)) {
+                    (Some(n), Some(t)) => self.read_multiset(element, *t).into_iter().map(|(m, c)| (n * m, c)).collect(),
+                    _ => {
+                        self.report.push(element, "numberof without a constant multiplicity is not supported".to_string());
+                        vec![]
+                    }
+                }
+            }
+            "useroperator" => match term.attribute("declaration").and_then(|d| self.constants.get(d)) {
+                Some(clade) => vec![(1, clade.clone())],
+                None => {
+                    self.report.push(element, "reference to an unknown constant".to_string());
+                    vec![]
+                }
+            },
+            "dotconstant" => vec![(1, self.dot())],
+            "all" => match child(term, "usersort").and_then(|u| u.attribute("declaration")).and_then(|d| self.constants.get(d)) {
+                Some(sort) => sort.leaves().into_iter().map(|c| (1, c)).collect(),
+                None => {
+                    self.report.push(element, "<all> over an unknown sort".to_string());
+                    vec![]
+                }
+            },
+            other => {
+                self.report.push(element, format!("marking term <{}> is not supported", other));
+                vec![]
+            }
+        }
+    }
+
+    fn read_constraints(&mut self, id: &str, node: Node, place: &mut Place) {
+        let tool = match toolspecific(node) {
+            Some(tool) => tool,
+            None => return,
+        };
+        let mut clade = |element: Node| {
+            let clade = element.attribute("clade").and_then(|c| self.constants.get(c)).cloned();
+            if clade.is_none() {
+                self.report.push(id, format!("<{}> refers to an unknown clade and is dropped", element.tag_name().name()));
+            }
+            clade
+        };
+        place.colors = children(tool, "color").filter_map(&mut clade).collect();
+        let limits = children(tool, "capacity")
+            .filter_map(|c| Some((c.attribute("clade").is_some(), c, c.attribute("limit")?.parse::<usize>().ok()?)))
+            .collect_vec();
+        place.capacity = match limits.iter().find(|(per_clade, _, _)| !per_clade) {
+            Some((_, _, limit)) => Some(Capacity::Total(*limit)),
+            None if !limits.is_empty() => {
+                Some(Capacity::PerClade(limits.iter().filter_map(|(_, c, limit)| Some((clade(*c)?, *limit))).collect()))
+            }
+            None => None,
+        };
+    }
+
+    fn read_tokens(&mut self, id: &str, place: Node) -> Vec<Token> {
+        if let Some(tool) = toolspecific(place).filter(|t| children(*t, "token").next().is_some()) {
+            let tokens = children(tool, "token")
+                .filter_map(|t| {
+                    let clade = self.constants.get(t.attribute("clade")?)?.clone();
+                    let uuid = t.attribute("id").and_then(|i| Uuid::parse_str(i).ok()).unwrap_or_else(Uuid::new_v4);
+                    Some(Token { id: uuid, name: t.attribute("name").unwrap_or_default().to_string(), clade })
+                })
+                .collect_vec();
+            if tokens.len() == children(tool, "token").count() {
+                return tokens;
+            }
+            self.report.push(id, "tool-specific tokens refer to unknown clades; using the standard marking".to_string());
+        }
+        if let Some(marking) = child(place, "hlinitialMarking").and_then(|m| child(m, "structure")).and_then(first_element) {
+            return self
+                .read_multiset(id, marking)
+                .into_iter()
+                .flat_map(|(n, clade)| (0..n).map(move |_| Token::new(clade.name(), clade.clone())))
+                .collect();
+        }
+        if let Some(text) = child(place, "initialMarking").and_then(|m| child(m, "text")).and_then(|t| t.text()) {
+            match text.trim().parse::<usize>() {
+                Ok(n) => {
+                    let dot = self.dot();
+                    return (0..n).map(|_| Token::new(dot.name(), dot.clone())).collect();
+                }
+                Err(_) => self.report.push(id, format!("initial marking '{}' is not a number", text.trim())),
+            }
+        }
+        vec![]
+    }
+
+    fn read_guard(&mut self, element: &str, term: Node) -> Guard {
+        let parts = subterms(term);
+        match term.tag_name().name() {
+            "and" => Guard::All(parts.into_iter().map(|t| self.read_guard(element, t)).collect()),
+            "or" => Guard::Any(parts.into_iter().map(|t| self.read_guard(element, t)).collect()),
+            "not" => match parts.first().map(|t| (t.tag_name().name(), *t)) {
+                Some(("or", inner)) => {
+                    Guard::None(subterms(inner).into_iter().map(|t| self.read_guard(element, t)).collect())
+                }
+                Some((_, inner)) => Guard::None(vec![self.read_guard(element, inner)]),
+                None => Guard::Empty,
+            },
+            "booleanconstant" => match term.attribute("value") {
+                Some("false") => Guard::Any(vec![]),
+                _ => Guard::Empty,
+            },
+            tag @ ("equality" | "inequality") => {
+                let variable = parts
+                    .iter()
+                    .find(|p| p.tag_name().name() == "variable")
+                    .and_then(|v| v.attribute("refvariable"))
+                    .and_then(|v| self.variables.get(v))
+                    .cloned();
+                let constant = parts
+                    .iter()
+                    .find(|p| p.tag_name().name() == "useroperator")
+                    .and_then(|v| v.attribute("declaration"))
+                    .and_then(|v| self.constants.get(v))
+                    .cloned();
+                match (variable, constant) {
+                    (Some(s), Some(c)) if tag == "equality" => Guard::Is(s, c),
+                    (Some(s), Some(c)) => Guard::Not(s, c),
+                    _ => {
+                        self.report.push(element, format!("only comparisons between a variable and a constant are supported in <{}>; the comparison is dropped", tag));
+                        Guard::Empty
+                    }
+                }
+            }
+            other => {
+                self.report.push(element, format!("condition operator <{}> is not supported and is dropped", other));
+                Guard::Empty
+            }
+        }
+    }
+
+    // Symbols of an arc inscription; plain nets get one fresh symbol per unit of weight, named
+    // after the arc so that the arcs of a transition never share one
+    fn read_signature(&mut self, element: &str, arc: Node) -> Vec<Symbol> {
+        if let Some(term) = child(arc, "hlinscription").and_then(|i| child(i, "structure")).and_then(first_element) {
+            return self.read_symbols(element, term);
+        }
+        let weight = match child(arc, "inscription").and_then(|i| child(i, "text")).and_then(|t| t.text()) {
+            Some(text) => text.trim().parse::<usize>().unwrap_or_else(|_| {
+                self.report.push(element, format!("arc weight '{}' is not a number; using 1", text.trim()));
+                1
+            }),
+            None => 1,
+        };
+        (0..weight).map(|i| Symbol::new(format!("{}.x{}", element, i))).collect()
+    }
+
+    fn read_symbols(&mut self, element: &str, term: Node) -> Vec<Symbol> {
+        match term.tag_name().name() {
+            "add" => subterms(term).into_iter().flat_map(|t| self.read_symbols(element, t)).collect(),
+            "numberof" => {
+                let parts = subterms(term);
+                let count = parts.first().and_then(|n| n.attribute("value")).and_then(|v| v.parse::<usize>().ok());
+                match (count, parts.get(1)) {
+                    (Some(1), Some(t)) => self.read_symbols(element, *t),
+                    _ => {
+                        self.report.push(element, "multiple copies of a variable cannot be bound to distinct tokens; the term is dropped".to_string());
+                        vec![]
+                    }
+                }
+            }
+            "variable" => match term.attribute("refvariable").and_then(|v| self.variables.get(v)) {
+                Some(symbol) => vec![symbol.clone()],
+                None => {
+                    self.report.push(element, "reference to an undeclared variable".to_string());
+                    vec![]
+                }
+            },
+            other => {
+                self.report.push(element, format!("inscription term <{}> cannot be expressed as a signature and is dropped", other));
+                vec![]
+            }
+        }
+    }
+}
+
+// Collects the nodes of all (possibly nested) pages, resolving reference nodes to the nodes they stand for
+fn collect_page<'a, 'input>(page: Node<'a, 'input>, nodes: &mut Vec<Node<'a, 'input>>, references: &mut HashMap<String, String>) {
+    for node in page.children().filter(|c| c.is_element()) {
+        match node.tag_name().name() {
+            "page" => collect_page(node, nodes, references),
+            "referencePlace" | "referenceTransition" => {
+                if let (Some(id), Some(target)) = (node.attribute("id"), node.attribute("ref")) {
+                    references.insert(id.to_string(), target.to_string());
+                }
+            }
+            _ => nodes.push(node),
+        }
+    }
+}
+
+/// Reads the first net of a PNML document.
+///
+/// High-level (and symmetric) nets are read with their enumeration sorts, variables, conditions
+/// and markings; place/transition nets are read with a single `dot` clade. Every construct that
+/// has no counterpart in this crate is listed in the returned report rather than silently dropped.
+pub fn from_pnml(xml: &str) -> Result<(ColoredPetriNet, PnmlReport), PnmlError> {
+    let document = Document::parse(xml)?;
+    let nets = document.descendants().filter(|n| n.is_element() && n.tag_name().name() == "net").collect_vec();
+    let net_node = *nets.first().ok_or(PnmlError::MissingNet)?;
+    let mut reader = Reader { report: PnmlReport::default(), constants: HashMap::new(), variables: HashMap::new(), roots: vec![] };
+    if nets.len() > 1 {
+        reader.report.push(&attribute(nets[1], "id")?, "only the first net of the document is read".to_string());
+    }
+    let net_id = attribute(net_node, "id")?;
+    match net_node.attribute("type") {
+        Some(HLPN_NET_TYPE) | Some(SYMMETRIC_NET_TYPE) | Some(PT_NET_TYPE) => {}
+        Some(other) => reader.report.push(&net_id, format!("net type '{}' is read as a high-level net", other)),
+        None => {}
+    }
+
+    let mut net = ColoredPetriNet::new(name_text(net_node).unwrap_or_else(|| net_id.clone()), None, None, None);
+    if let Some(uuid) = parse_ref(&net_id) {
+        net.id = uuid;
+    }
+
+    let exact_roots = match toolspecific(net_node) {
+        Some(tool) => children(tool, "clade").map(|c| reader.read_clade_tree(c)).collect::<Result<Vec<_>, _>>()?,
+        None => vec![],
+    };
+    reader.roots.extend(exact_roots);
+
+    let mut nodes = vec![];
+    let mut references = HashMap::new();
+    for page in children(net_node, "page") {
+        collect_page(page, &mut nodes, &mut references);
+    }
+    let declarations = children(net_node, "declaration")
+        .chain(nodes.iter().copied().filter(|n| n.tag_name().name() == "declaration"))
+        .filter_map(|d| child(d, "structure"))
+        .filter_map(|s| child(s, "declarations"))
+        .collect_vec();
+    for declaration in declarations {
+        reader.read_declarations(declaration)?;
+    }
+
+    let mut places: HashMap<String, Uuid> = HashMap::new();
+    for node in nodes.iter().filter(|n| n.tag_name().name() == "place") {
+        let id = attribute(*node, "id")?;
+        let mut place = Place::new(name_text(*node).unwrap_or_else(|| id.clone()));
+        if let Some(uuid) = parse_ref(&id) {
+            place.id = uuid;
+        }
+        reader.read_constraints(&id, *node, &mut place);
+        for token in reader.read_tokens(&id, *node) {
+            net.add_token(place.id, token);
+        }
+        places.insert(id, net.add_place(place));
+    }
+
+    let transition_nodes = nodes.iter().filter(|n| n.tag_name().name() == "transition").collect_vec();
+    let mut inputs: HashMap<String, HashMap<Uuid, Signature>> = HashMap::new();
+    let mut outputs: HashMap<String, HashMap<Uuid, Signature>> = HashMap::new();
+    let resolve = |id: &str| references.get(id).cloned().unwrap_or_else(|| id.to_string());
+    let is_transition = |id: &str| transition_nodes.iter().any(|t| t.attribute("id") == Some(id));
+    for arc in nodes.iter().filter(|n| n.tag_name().name() == "arc") {
+        let id = attribute(*arc, "id")?;
+        let source = resolve(&attribute(*arc, "source")?);
+        let target = resolve(&attribute(*arc, "target")?);
+        let symbols = reader.read_signature(&id, *arc);
+        let (transition, place, map) = match (places.get(&source), places.get(&target)) {
+            (Some(p), None) if is_transition(&target) => (target, *p, &mut inputs),
+            (None, Some(p)) if is_transition(&source) => (source, *p, &mut outputs),
+            _ => {
+                reader.report.push(&id, "arc does not connect a place and a transition and is dropped".to_string());
+                continue;
+            }
+        };
+        map.entry(transition)
+            .or_default()
+            .entry(place)
+            .or_default()
+            .symbols
+            .extend(symbols);
+    }
+
+    for node in transition_nodes {
+        let id = attribute(*node, "id")?;
+        let name = name_text(*node).unwrap_or_else(|| id.clone());
+        let mut input = inputs.remove(&id).unwrap_or_default();
+        let mut output = outputs.remove(&id).unwrap_or_default();
+        // Symbols on both an input and an output arc that the tool-specific block marks as read
+        let mut reads: HashMap<Uuid, Signature> = HashMap::new();
+        for read in toolspecific(*node).map(|t| children(t, "read").collect_vec()).unwrap_or_default() {
+            let place = read.attribute("place").and_then(|p| places.get(p));
+            let symbol = read.attribute("symbol").map(Symbol::from);
+            let (place, symbol) = match (place, symbol) {
+                (Some(place), Some(symbol)) => (*place, symbol),
+                _ => {
+                    reader.report.push(&id, "read arc refers to an unknown place and is dropped".to_string());
+                    continue;
+                }
+            };
+            for arcs in [&mut input, &mut output] {
+                if let Some(signature) = arcs.get_mut(&place) {
+                    signature.symbols.remove(&symbol);
+                    if signature.symbols.is_empty() {
+                        arcs.remove(&place);
+                    }
+                }
+            }
+            reads.entry(place).or_default().symbols.insert(symbol);
+        }
+        if net_node.attribute("type") == Some(PT_NET_TYPE) {
+            // Uncoloured tokens are interchangeable, so produced tokens reuse consumed ones
+            let consumed = input.values().flat_map(|s| s.symbols.iter().cloned()).sorted().collect_vec();
+            let mut next = 0;
+            for signature in output.values_mut() {
+                let wanted = signature.symbols.len();
+                signature.symbols = consumed.iter().skip(next).take(wanted).cloned().collect();
+                next += wanted;
+            }
+            if next > consumed.len() {
+                reader.report.push(&id, "transition produces more tokens than it consumes, which cannot be expressed".to_string());
+            }
+        }
+        let stored_guard = toolspecific(*node)
+            .and_then(|t| child(t, "guard"))
+            .and_then(|g| g.text())
+            .and_then(|g| serde_json::from_str::<Guard>(g).ok());
+        let guard = match stored_guard {
+            Some(guard) => guard,
+            None => match child(*node, "condition").and_then(|c| child(c, "structure")).and_then(first_element) {
+                Some(term) => reader.read_guard(&id, term),
+                None => Guard::Empty,
+            },
+        };
+        let input_symbols: HashSet<Symbol> = input.values().chain(reads.values()).flat_map(|s| s.symbols.iter().cloned()).collect();
+        if guard.symbols().iter().any(|s| !input_symbols.contains(s)) {
+            reader.report.push(&id, "the condition refers to variables not consumed by the transition and is dropped".to_string());
+        }
+        let mut transition = Transition::new(name, Some(input), Some(output), None, None);
+        transition.reads = reads;
+        transition = transition.with_guard(guard);
+        if let Some(uuid) = parse_ref(&id) {
+            transition.id = uuid;
+        }
+        if let Some(tool) = toolspecific(*node) {
+            if let Some(priority) = child(tool, "priority").and_then(|p| p.attribute("value")).and_then(|v| v.parse().ok()) {
+                transition.priority = priority;
+            }
+            transition.label = child(tool, "label").and_then(|l| l.attribute("value")).map(|l| l.to_string());
+            for inhibitor in children(tool, "inhibitor") {
+                let clade = inhibitor.attribute("clade").map(|c| reader.constants.get(c).cloned());
+                match (inhibitor.attribute("place").and_then(|p| places.get(p)), clade) {
+                    (Some(place), None) => transition.inhibitors.insert(*place, None),
+                    (Some(place), Some(Some(clade))) => transition.inhibitors.insert(*place, Some(clade)),
+                    _ => {
+                        reader.report.push(&id, "inhibitor arc refers to an unknown place or clade and is dropped".to_string());
+                        continue;
+                    }
+                };
+            }
+            for reset in children(tool, "reset") {
+                match reset.attribute("place").and_then(|p| places.get(p)) {
+                    Some(place) => transition.resets.insert(*place),
+                    None => {
+                        reader.report.push(&id, "reset arc refers to an unknown place and is dropped".to_string());
+                        continue;
+                    }
+                };
+            }
+        }
+        if let Some(function) = toolspecific(*node).and_then(|t| child(t, "function")).and_then(|f| f.attribute("id")) {
+            transition.function.id = Uuid::parse_str(function).unwrap_or(transition.function.id);
+        }
+        net.add_transition(transition);
+    }
+
+    for root in reader.roots.drain(..) {
+        net.add_clade(root);
+    }
+    Ok((net, reader.report))
+}
+
+#[test]
+pub fn pnml_round_trip() {
+    let robot1 = Clade::new("robot1".into(), None);
+    let robot2 = Clade::new("robot2".into(), None);
+    let robot = Clade::new("robot".into(), Some(vec![robot1.clone(), robot2.clone()]));
+    let part = Clade::new("part".into(), None);
+    let tax = Clade::new("thing".into(), Some(vec![robot.clone(), part.clone()]));
+
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    net.add_clade(tax.clone());
+    let idle = net.add_place(Place::new("idle".into()));
+    let busy = net.add_place(
+        Place::new("busy".into()).with_colors(vec![robot.clone()]).with_capacity(Capacity::PerClade(vec![(robot1.clone(), 1)])),
+    );
+    net.add_token(idle, Token::new("r1".into(), robot1.clone()));
+    net.add_token(idle, Token::new("r2".into(), robot2.clone()));
+    let start = net.add_transition(Transition::new(
+        "start".into(),
+        Some(HashMap::from([(idle, Signature::new(HashSet::from(["x".into()])))])),
+        Some(HashMap::from([(busy, Signature::new(HashSet::from(["x".into()])))])),
+        Some(Guard::LessThanOrEqual("x".into(), robot.clone())),
+        None,
+    ));
+
+    let (xml, report) = to_pnml(&net);
+    assert!(report.is_lossless(), "{}", report);
+    let (read, report) = from_pnml(&xml).unwrap();
+    assert!(report.is_lossless(), "{}", report);
+    assert_eq!(read.id, net.id);
+    assert_eq!(read.places, net.places);
+    assert_eq!(read.transitions[&start], net.transitions[&start]);
+    assert_eq!(read.initial_marking, net.initial_marking);
+    assert_eq!(read.clades, vec![tax]);
+}
+
+#[test]
+pub fn pnml_reads_foreign_nets() {
+    let xml = r#"<?xml version="1.0"?>
+<pnml xmlns="http://www.pnml.org/version-2009/grammar/pnml">
+  <net id="n1" type="http://www.pnml.org/version-2009/grammar/symmetricnet">
+    <name><text>foreign</text></name>
+    <declaration><structure><declarations>
+      <namedsort id="colors" name="Colors">
+        <finiteenumeration><feconstant id="red" name="red"/><feconstant id="blue" name="blue"/></finiteenumeration>
+      </namedsort>
+      <namedsort id="ints" name="Ints"><finiteintrange start="0" end="3"/></namedsort>
+      <variabledecl id="vx" name="x"><usersort declaration="colors"/></variabledecl>
+    </declarations></structure></declaration>
+    <page id="g">
+      <place id="p1"><name><text>P1</text></name>
+        <hlinitialMarking><structure><numberof>
+          <subterm><numberconstant value="2"><positive/></numberconstant></subterm>
+          <subterm><useroperator declaration="red"/></subterm>
+        </numberof></structure></hlinitialMarking>
+      </place>
+      <place id="p2"><name><text>P2</text></name></place>
+      <transition id="t1"><name><text>T1</text></name>
+        <condition><structure><equality>
+          <subterm><variable refvariable="vx"/></subterm>
+          <subterm><useroperator declaration="red"/></subterm>
+        </equality></structure></condition>
+      </transition>
+      <arc id="a1" source="p1" target="t1"><hlinscription><structure><variable refvariable="vx"/></structure></hlinscription></arc>
+      <arc id="a2" source="t1" target="p2"><hlinscription><structure><variable refvariable="vx"/></structure></hlinscription></arc>
+      <arc id="a3" source="p1" target="p2"/>
+    </page>
+  </net>
+</pnml>"#;
+    let (net, report) = from_pnml(xml).unwrap();
+    assert_eq!(net.name, "foreign");
+    assert_eq!(net.places.len(), 2);
+    let p1 = net.id_of("P1").unwrap();
+    assert_eq!(net.initial_marking[&p1].len(), 2);
+    assert!(net.initial_marking[&p1].values().all(|t| t.clade.name() == "red"));
+    let t1 = &net.transitions[&net.id_of("T1").unwrap()];
+    assert!(matches!(&t1.guard, Guard::Is(s, c) if s.name() == "x" && c.name() == "red"));
+    assert_eq!(net.clades.len(), 1);
+    // The integer range sort and the place-to-place arc cannot be represented
+    assert_eq!(report.issues.len(), 2);
+    assert!(report.issues.iter().any(|i| i.element == "ints"));
+    assert!(report.issues.iter().any(|i| i.element == "a3"));
+}
+
+#[test]
+pub fn pnml_writes_unfolded_nets() {
+    let robot1 = Clade::new("robot1".into(), None);
+    let robot2 = Clade::new("robot2".into(), None);
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    net.add_clade(Clade::new("robot".into(), Some(vec![robot1.clone(), robot2.clone()])));
+    let idle = net.add_place(Place::new("idle".into()));
+    let busy = net.add_place(Place::new("busy".into()));
+    net.add_token(idle, Token::new("r1".into(), robot1.clone()));
+    net.add_transition(Transition::new(
+        "start".into(),
+        Some(HashMap::from([(idle, Signature::new(HashSet::from(["x".into()])))])),
+        Some(HashMap::from([(busy, Signature::new(HashSet::from(["x".into()])))])),
+        None,
+        None,
+    ));
+
+    let tool = net.add_place(Place::new("tool".into()));
+    net.add_token(tool, Token::new("t1".into(), robot2.clone()));
+    net.add_transition(Transition::new(
+        "load".into(),
+        Some(HashMap::from([(busy, Signature::new(HashSet::from(["x".into()]))), (tool, Signature::new(HashSet::from(["y".into()])))])),
+        Some(HashMap::from([(idle, Signature::new(HashSet::from(["x".into(), "y".into()])))])),
+        None,
+        None,
+    ));
+
+    let (mut read, report) = from_pnml(&pt_to_pnml(&crate::unfold::unfold(&net).unwrap())).unwrap();
+    assert!(report.is_lossless(), "{}", report);
+    assert!(read.validate().is_empty(), "{:?}", read.validate());
+    assert_eq!(read.places.len(), 6);
+    assert_eq!(read.transitions.len(), 2 + 4);
+    assert_eq!(read.initial_marking[&read.id_of("idle.robot1").unwrap()].len(), 1);
+    // The unfolded transition with two inputs still fires
+    for name in ["start[x=robot1]", "load[x=robot1,y=robot2]"] {
+        let (transition, binding) = read.enabled().into_iter().find(|(t, _)| read.transitions[t].name == name).unwrap();
+        read.fire(&transition, &binding).unwrap();
+    }
+    assert_eq!(read.current_marking.count(&read.id_of("idle.robot2").unwrap()), 1);
+    assert_eq!(read.current_marking.count(&read.id_of("tool.robot2").unwrap()), 0);
+}
+
+#[test]
+pub fn pnml_reads_plain_nets() {
+    let xml = r#"<?xml version="1.0"?>
+<pnml xmlns="http://www.pnml.org/version-2009/grammar/pnml">
+  <net id="n1" type="http://www.pnml.org/version-2009/grammar/ptnet">
+    <page id="g">
+      <place id="p1"><initialMarking><text>1</text></initialMarking></place>
+      <place id="p2"><initialMarking><text>1</text></initialMarking></place>
+      <place id="p3"/>
+      <transition id="t1"/>
+      <arc id="a1" source="p1" target="t1"/>
+      <arc id="a2" source="p2" target="t1"/>
+      <arc id="a3" source="t1" target="p3"/>
+    </page>
+  </net>
+</pnml>"#;
+    let (mut net, report) = from_pnml(xml).unwrap();
+    assert!(report.is_lossless(), "{}", report);
+    assert!(net.validate().is_empty(), "{:?}", net.validate());
+    let enabled = net.enabled();
+    assert_eq!(enabled.len(), 1);
+    net.fire(&enabled[0].0, &enabled[0].1).unwrap();
+    assert_eq!(net.current_marking.count(&net.id_of("p3").unwrap()), 1);
+    assert!(net.enabled().is_empty());
+}
@@ -0,0 +1,628 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::clade::Clade;
+use crate::marking::Marking;
+use crate::net::ColoredPetriNet;
+
+/// Comparison operators usable in `count(...)` propositions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    pub fn apply(&self, left: u64, right: u64) -> bool {
+        match self {
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::GreaterOrEqual => left >= right,
+            Comparison::Greater => left > right,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::Greater => ">",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+/// An atomic proposition about a marking, referring to places, transitions and clades by name.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Atom {
+    // The place holds a token of the clade or one of its descendents
+    Has { place: String, clade: String },
+    // The transition has at least one binding
+    Enabled(String),
+    // The number of tokens in the place (optionally only of a clade) compared to a constant
+    Count { place: String, clade: Option<String>, comparison: Comparison, value: u64 },
+    // No transition is enabled
+    Deadlock,
+}
+
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Atom::Has { place, clade } => write!(f, "has({}, {})", place, clade),
+            Atom::Enabled(transition) => write!(f, "enabled({})", transition),
+            Atom::Count { place, clade: Some(clade), comparison, value } => {
+                write!(f, "count({}, {}) {} {}", place, clade, comparison, value)
+            }
+            Atom::Count { place, clade: None, comparison, value } => write!(f, "count({}) {} {}", place, comparison, value),
+            Atom::Deadlock => write!(f, "deadlock"),
+        }
+    }
+}
+
+/// Errors from parsing a query or resolving its names against a net.
+#[derive(Clone, Debug, PartialEq)]
+pub enum QueryError {
+    Syntax { position: usize, message: String },
+    UnknownPlace(String),
+    UnknownTransition(String),
+    UnknownClade(String),
+    // The state space could not be explored completely, so the answer would not be sound
+    Truncated,
+    // The formula is not one that a reduced state space answers as the full one would
+    NotPreserved,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::Syntax { position, message } => write!(f, "syntax error at {}: {}", position, message),
+            QueryError::UnknownPlace(name) => write!(f, "unknown place '{}'", name),
+            QueryError::UnknownTransition(name) => write!(f, "unknown transition '{}'", name),
+            QueryError::UnknownClade(name) => write!(f, "unknown clade '{}'", name),
+            QueryError::Truncated => write!(f, "the state space exceeds the exploration limit"),
+            QueryError::NotPreserved => write!(f, "partial-order reduction only preserves safety and reachability formulas"),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// An atom with its names resolved against a net, ready to be evaluated on markings.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Proposition {
+    Has { place: Uuid, clade: Clade },
+    Enabled(Uuid),
+    Count { place: Uuid, clade: Option<Clade>, comparison: Comparison, value: u64 },
+    Deadlock,
+}
+
+impl Atom {
+    pub fn resolve(&self, net: &ColoredPetriNet) -> Result<Proposition, QueryError> {
+        let place = |name: &String| {
+            net.id_of(name).filter(|id| net.places.contains_key(id)).ok_or_else(|| QueryError::UnknownPlace(name.clone()))
+        };
+        let clade = |name: &String| net.find_clade(name).ok_or_else(|| QueryError::UnknownClade(name.clone()));
+        Ok(match self {
+            Atom::Has { place: p, clade: c } => Proposition::Has { place: place(p)?, clade: clade(c)? },
+            Atom::Enabled(t) => Proposition::Enabled(
+                net.id_of(t)
+                    .filter(|id| net.transitions.contains_key(id))
+                    .ok_or_else(|| QueryError::UnknownTransition(t.clone()))?,
+            ),
+            Atom::Count { place: p, clade: c, comparison, value } => Proposition::Count {
+                place: place(p)?,
+                clade: c.as_ref().map(clade).transpose()?,
+                comparison: *comparison,
+                value: *value,
+            },
+            Atom::Deadlock => Proposition::Deadlock,
+        })
+    }
+}
+
+impl Proposition {
+    pub fn eval(&self, net: &ColoredPetriNet, marking: &Marking) -> bool {
+        match self {
+            Proposition::Has { place, clade } => marking.count_of(place, clade) > 0,
+            Proposition::Enabled(transition) => net.is_enabled(&net.transitions[transition], marking),
+            Proposition::Count { place, clade, comparison, value } => {
+                let count = clade.as_ref().map_or(marking.count(place), |c| marking.count_of(place, c));
+                comparison.apply(count as u64, *value)
+            }
+            Proposition::Deadlock => net.enabled_in(marking).is_empty(),
+        }
+    }
+}
+
+/// A CTL formula, evaluated on the states of the reachability graph.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Ctl {
+    True,
+    False,
+    Atom(Atom),
+    Not(Box<Ctl>),
+    And(Box<Ctl>, Box<Ctl>),
+    Or(Box<Ctl>, Box<Ctl>),
+    EX(Box<Ctl>),
+    AX(Box<Ctl>),
+    EF(Box<Ctl>),
+    AF(Box<Ctl>),
+    EG(Box<Ctl>),
+    AG(Box<Ctl>),
+    EU(Box<Ctl>, Box<Ctl>),
+    AU(Box<Ctl>, Box<Ctl>),
+}
+
+/// An LTL formula, evaluated on the infinite paths of the reachability graph.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Ltl {
+    True,
+    False,
+    Atom(Atom),
+    Not(Box<Ltl>),
+    And(Box<Ltl>, Box<Ltl>),
+    Or(Box<Ltl>, Box<Ltl>),
+    Next(Box<Ltl>),
+    Until(Box<Ltl>, Box<Ltl>),
+    Release(Box<Ltl>, Box<Ltl>),
+    Finally(Box<Ltl>),
+    Globally(Box<Ltl>),
+}
+
+impl Ctl {
+    /// Parses a CTL formula, e.g. `AG !(count(cellA, robot) >= 2)` or `A[!done U has(done, part)]`.
+    pub fn parse(text: &str) -> Result<Ctl, QueryError> {
+        Parser::new(text)?.parse_all()
+    }
+
+    /// Whether the formula is about a single marking, without temporal operators.
+    pub fn is_state(&self) -> bool {
+        match self {
+            Ctl::True | Ctl::False | Ctl::Atom(_) => true,
+            Ctl::Not(f) => f.is_state(),
+            Ctl::And(a, b) | Ctl::Or(a, b) => a.is_state() && b.is_state(),
+            _ => false,
+        }
+    }
+
+    /// Every atom of the formula.
+    pub fn atoms(&self) -> Vec<&Atom> {
+        match self {
+            Ctl::True | Ctl::False => vec![],
+            Ctl::Atom(a) => vec![a],
+            Ctl::Not(f) | Ctl::EX(f) | Ctl::AX(f) | Ctl::EF(f) | Ctl::AF(f) | Ctl::EG(f) | Ctl::AG(f) => f.atoms(),
+            Ctl::And(a, b) | Ctl::Or(a, b) | Ctl::EU(a, b) | Ctl::AU(a, b) => a.atoms().into_iter().chain(b.atoms()).collect(),
+        }
+    }
+}
+
+impl Ltl {
+    /// Parses an LTL formula, e.g. `G (has(request, robot) -> F has(served, robot))`.
+    pub fn parse(text: &str) -> Result<Ltl, QueryError> {
+        Parser::new(text)?.parse_all()
+    }
+
+    /// Every atom of the formula.
+    pub fn atoms(&self) -> Vec<&Atom> {
+        match self {
+            Ltl::True | Ltl::False => vec![],
+            Ltl::Atom(a) => vec![a],
+            Ltl::Not(f) | Ltl::Next(f) | Ltl::Finally(f) | Ltl::Globally(f) => f.atoms(),
+            Ltl::And(a, b) | Ltl::Or(a, b) | Ltl::Until(a, b) | Ltl::Release(a, b) => a.atoms().into_iter().chain(b.atoms()).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Lexeme {
+    Name(String),
+    Number(u64),
+    Open,
+    Close,
+    OpenBracket,
+    CloseBracket,
+    Comma,
+    Not,
+    And,
+    Or,
+    Implies,
+    Compare(Comparison),
+}
+
+pub(crate) fn tokenize(text: &str) -> Result<Vec<(usize, Lexeme)>, QueryError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut lexemes = vec![];
+    let mut i = 0;
+    let is_name = |c: char| c.is_alphanumeric() || "_-./:".contains(c);
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let start = i;
+        let lexeme = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('(', _) => Lexeme::Open,
+            (')', _) => Lexeme::Close,
+            ('[', _) => Lexeme::OpenBracket,
+            (']', _) => Lexeme::CloseBracket,
+            (',', _) => Lexeme::Comma,
+            ('-', Some('>')) => {
+                i += 1;
+                Lexeme::Implies
+            }
+            ('!', Some('=')) => {
+                i += 1;
+                Lexeme::Compare(Comparison::NotEqual)
+            }
+            ('!', _) => Lexeme::Not,
+            ('&', Some('&')) | ('|', Some('|')) => {
+                i += 1;
+                if c == '&' { Lexeme::And } else { Lexeme::Or }
+            }
+            ('&', _) => Lexeme::And,
+            ('|', _) => Lexeme::Or,
+            ('<', Some('=')) | ('>', Some('=')) | ('=', Some('=')) => {
+                i += 1;
+                Lexeme::Compare(match c {
+                    '<' => Comparison::LessOrEqual,
+                    '>' => Comparison::GreaterOrEqual,
+                    _ => Comparison::Equal,
+                })
+            }
+            ('<', _) => Lexeme::Compare(Comparison::Less),
+            ('>', _) => Lexeme::Compare(Comparison::Greater),
+            ('=', _) => Lexeme::Compare(Comparison::Equal),
+            ('"', _) => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|c| *c == '"')
+                    .ok_or(QueryError::Syntax { position: i, message: "unterminated quoted name".into() })?;
+                let name: String = chars[i + 1..i + 1 + end].iter().collect();
+                i += end + 1;
+                Lexeme::Name(name)
+            }
+            (c, _) if c.is_ascii_digit() => {
+                let end = chars[i..].iter().position(|c| !c.is_ascii_digit()).unwrap_or(chars.len() - i);
+                let digits: String = chars[i..i + end].iter().collect();
+                i += end - 1;
+                Lexeme::Number(digits.parse().map_err(|_| QueryError::Syntax { position: start, message: "number too large".into() })?)
+            }
+            (c, _) if is_name(c) => {
+                let end = chars[i..].iter().position(|c| !is_name(*c)).unwrap_or(chars.len() - i);
+                // A trailing '-' belongs to a following '->'
+                let end = if chars.get(i + end) == Some(&'>') && end > 1 && chars[i + end - 1] == '-' { end - 1 } else { end };
+                let name: String = chars[i..i + end].iter().collect();
+                i += end - 1;
+                Lexeme::Name(name)
+            }
+            (c, _) => return Err(QueryError::Syntax { position: i, message: format!("unexpected character '{}'", c) }),
+        };
+        lexemes.push((start, lexeme));
+        i += 1;
+    }
+    Ok(lexemes)
+}
+
+// Formula types the shared recursive descent parser can build
+trait Syntax: Sized {
+    fn constant(value: bool) -> Self;
+    fn atom(atom: Atom) -> Self;
+    fn not(f: Self) -> Self;
+    fn and(a: Self, b: Self) -> Self;
+    fn or(a: Self, b: Self) -> Self;
+    // Parses a temporal prefix operator named `name` and its operand(s), if `name` is one
+    fn prefix(parser: &mut Parser, name: &str) -> Result<Option<Self>, QueryError>;
+    // Names of the binary temporal operators
+    const BINARY: &'static [&'static str];
+    fn binary(name: &str, a: Self, b: Self) -> Self;
+}
+
+impl Syntax for Ctl {
+    fn constant(value: bool) -> Self {
+        if value { Ctl::True } else { Ctl::False }
+    }
+
+    fn atom(atom: Atom) -> Self {
+        Ctl::Atom(atom)
+    }
+
+    fn not(f: Self) -> Self {
+        Ctl::Not(Box::new(f))
+    }
+
+    fn and(a: Self, b: Self) -> Self {
+        Ctl::And(Box::new(a), Box::new(b))
+    }
+
+    fn or(a: Self, b: Self) -> Self {
+        Ctl::Or(Box::new(a), Box::new(b))
+    }
+
+    fn prefix(parser: &mut Parser, name: &str) -> Result<Option<Self>, QueryError> {
+        let unary: fn(Box<Ctl>) -> Ctl = match name {
+            "EX" => Ctl::EX,
+            "AX" => Ctl::AX,
+            "EF" => Ctl::EF,
+            "AF" => Ctl::AF,
+            "EG" => Ctl::EG,
+            "AG" => Ctl::AG,
+            "A" | "E" => {
+                parser.expect(&Lexeme::OpenBracket, "'['")?;
+                let left = parser.parse_implication::<Ctl>()?;
+                parser.expect(&Lexeme::Name("U".into()), "'U'")?;
+                let right = parser.parse_implication::<Ctl>()?;
+                parser.expect(&Lexeme::CloseBracket, "']'")?;
+                let (left, right) = (Box::new(left), Box::new(right));
+                return Ok(Some(if name == "A" { Ctl::AU(left, right) } else { Ctl::EU(left, right) }));
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(unary(Box::new(parser.parse_unary::<Ctl>()?))))
+    }
+
+    // CTL only has the bracketed until forms
+    const BINARY: &'static [&'static str] = &[];
+
+    fn binary(name: &str, _a: Self, _b: Self) -> Self {
+        unreachable!("'{}' is not a CTL operator", name)
+    }
+}
+
+impl Syntax for Ltl {
+    fn constant(value: bool) -> Self {
+        if value { Ltl::True } else { Ltl::False }
+    }
+
+    fn atom(atom: Atom) -> Self {
+        Ltl::Atom(atom)
+    }
+
+    fn not(f: Self) -> Self {
+        Ltl::Not(Box::new(f))
+    }
+
+    fn and(a: Self, b: Self) -> Self {
+        Ltl::And(Box::new(a), Box::new(b))
+    }
+
+    fn or(a: Self, b: Self) -> Self {
+        Ltl::Or(Box::new(a), Box::new(b))
+    }
+
+    fn prefix(parser: &mut Parser, name: &str) -> Result<Option<Self>, QueryError> {
+        let unary: fn(Box<Ltl>) -> Ltl = match name {
+            "X" => Ltl::Next,
+            "F" => Ltl::Finally,
+            "G" => Ltl::Globally,
+            _ => return Ok(None),
+        };
+        Ok(Some(unary(Box::new(parser.parse_unary::<Ltl>()?))))
+    }
+
+    const BINARY: &'static [&'static str] = &["U", "R"];
+
+    fn binary(name: &str, a: Self, b: Self) -> Self {
+        if name == "U" { Ltl::Until(Box::new(a), Box::new(b)) } else { Ltl::Release(Box::new(a), Box::new(b)) }
+    }
+}
+
+// Recursive descent over the lexemes. Precedence, loosest first: '->', '|', '&', binary
+// temporal operators, then prefix operators.
+struct Parser {
+    lexemes: Vec<(usize, Lexeme)>,
+    position: usize,
+    length: usize,
+}
+
+impl Parser {
+    fn new(text: &str) -> Result<Self, QueryError> {
+        Ok(Self { lexemes: tokenize(text)?, position: 0, length: text.len() })
+    }
+
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.position).map(|(_, l)| l)
+    }
+
+    fn offset(&self) -> usize {
+        self.lexemes.get(self.position).map(|(o, _)| *o).unwrap_or(self.length)
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, QueryError> {
+        Err(QueryError::Syntax { position: self.offset(), message })
+    }
+
+    fn advance(&mut self) -> Option<Lexeme> {
+        let lexeme = self.lexemes.get(self.position).map(|(_, l)| l.clone());
+        self.position += 1;
+        lexeme
+    }
+
+    fn expect(&mut self, lexeme: &Lexeme, description: &str) -> Result<(), QueryError> {
+        if self.peek() == Some(lexeme) {
+            self.position += 1;
+            Ok(())
+        } else {
+            self.error(format!("expected {}", description))
+        }
+    }
+
+    fn parse_all<F: Syntax>(&mut self) -> Result<F, QueryError> {
+        let formula = self.parse_implication::<F>()?;
+        if self.peek().is_some() {
+            return self.error("unexpected input after the formula".into());
+        }
+        Ok(formula)
+    }
+
+    fn parse_implication<F: Syntax>(&mut self) -> Result<F, QueryError> {
+        let left = self.parse_or::<F>()?;
+        if self.peek() == Some(&Lexeme::Implies) {
+            self.advance();
+            let right = self.parse_implication::<F>()?;
+            return Ok(F::or(F::not(left), right));
+        }
+        Ok(left)
+    }
+
+    fn parse_or<F: Syntax>(&mut self) -> Result<F, QueryError> {
+        let mut left = self.parse_and::<F>()?;
+        while self.peek() == Some(&Lexeme::Or) {
+            self.advance();
+            left = F::or(left, self.parse_and::<F>()?);
+        }
+        Ok(left)
+    }
+
+    fn parse_and<F: Syntax>(&mut self) -> Result<F, QueryError> {
+        let mut left = self.parse_binary::<F>()?;
+        while self.peek() == Some(&Lexeme::And) {
+            self.advance();
+            left = F::and(left, self.parse_binary::<F>()?);
+        }
+        Ok(left)
+    }
+
+    // Binary temporal operators are right associative
+    fn parse_binary<F: Syntax>(&mut self) -> Result<F, QueryError> {
+        let left = self.parse_unary::<F>()?;
+        if let Some(Lexeme::Name(name)) = self.peek().cloned() {
+            if F::BINARY.contains(&name.as_str()) {
+                self.advance();
+                let right = self.parse_binary::<F>()?;
+                return Ok(F::binary(&name, left, right));
+            }
+        }
+        Ok(left)
+    }
+
+    fn parse_unary<F: Syntax>(&mut self) -> Result<F, QueryError> {
+        match self.peek().cloned() {
+            Some(Lexeme::Not) => {
+                self.advance();
+                Ok(F::not(self.parse_unary::<F>()?))
+            }
+            Some(Lexeme::Open) => {
+                self.advance();
+                let formula = self.parse_implication::<F>()?;
+                self.expect(&Lexeme::Close, "')'")?;
+                Ok(formula)
+            }
+            Some(Lexeme::Name(name)) => {
+                self.advance();
+                if let Some(formula) = F::prefix(self, &name)? {
+                    return Ok(formula);
+                }
+                match name.as_str() {
+                    "true" => Ok(F::constant(true)),
+                    "false" => Ok(F::constant(false)),
+                    _ => {
+                        self.position -= 1;
+                        Ok(F::atom(self.parse_atom()?))
+                    }
+                }
+            }
+            _ => self.error("expected a formula".into()),
+        }
+    }
+
+    fn parse_name(&mut self) -> Result<String, QueryError> {
+        match self.advance() {
+            Some(Lexeme::Name(name)) => Ok(name),
+            _ => {
+                self.position -= 1;
+                self.error("expected a name".into())
+            }
+        }
+    }
+
+    /// Parses `has(place, clade)`, `enabled(transition)`, `count(place[, clade]) op n` or `deadlock`.
+    fn parse_atom(&mut self) -> Result<Atom, QueryError> {
+        let keyword = self.parse_name()?;
+        match keyword.as_str() {
+            "deadlock" => Ok(Atom::Deadlock),
+            "has" => {
+                self.expect(&Lexeme::Open, "'('")?;
+                let place = self.parse_name()?;
+                self.expect(&Lexeme::Comma, "','")?;
+                let clade = self.parse_name()?;
+                self.expect(&Lexeme::Close, "')'")?;
+                Ok(Atom::Has { place, clade })
+            }
+            "enabled" => {
+                self.expect(&Lexeme::Open, "'('")?;
+                let transition = self.parse_name()?;
+                self.expect(&Lexeme::Close, "')'")?;
+                Ok(Atom::Enabled(transition))
+            }
+            "count" => {
+                self.expect(&Lexeme::Open, "'('")?;
+                let place = self.parse_name()?;
+                let clade = if self.peek() == Some(&Lexeme::Comma) {
+                    self.advance();
+                    Some(self.parse_name()?)
+                } else {
+                    None
+                };
+                self.expect(&Lexeme::Close, "')'")?;
+                let comparison = match self.advance() {
+                    Some(Lexeme::Compare(c)) => c,
+                    _ => {
+                        self.position -= 1;
+                        return self.error("expected a comparison".into());
+                    }
+                };
+                let value = match self.advance() {
+                    Some(Lexeme::Number(n)) => n,
+                    _ => {
+                        self.position -= 1;
+                        return self.error("expected a number".into());
+                    }
+                };
+                Ok(Atom::Count { place, clade, comparison, value })
+            }
+            other => {
+                self.position -= 1;
+                self.error(format!("unknown proposition '{}'", other))
+            }
+        }
+    }
+}
+
+#[test]
+pub fn parse_queries() {
+    let ctl = Ctl::parse("AG !(count(\"cell A\", robot) >= 2) & A[!deadlock U has(done,part)]").unwrap();
+    let expected = Ctl::And(
+        Box::new(Ctl::AG(Box::new(Ctl::Not(Box::new(Ctl::Atom(Atom::Count {
+            place: "cell A".into(),
+            clade: Some("robot".into()),
+            comparison: Comparison::GreaterOrEqual,
+            value: 2,
+        })))))),
+        Box::new(Ctl::AU(
+            Box::new(Ctl::Not(Box::new(Ctl::Atom(Atom::Deadlock)))),
+            Box::new(Ctl::Atom(Atom::Has { place: "done".into(), clade: "part".into() })),
+        )),
+    );
+    assert_eq!(ctl, expected);
+
+    let ltl = Ltl::parse("G (enabled(start) -> F !enabled(start)) & a-1 U b").err();
+    assert!(matches!(ltl, Some(QueryError::Syntax { .. })));
+    let ltl = Ltl::parse("G(enabled(start)->X true) | enabled(a) U enabled(b)").unwrap();
+    assert!(matches!(ltl, Ltl::Or(_, ref b) if matches!(**b, Ltl::Until(_, _))));
+    assert!(Ctl::parse("G true").is_err());
+    assert!(Ltl::parse("AG true").is_err());
+    assert!(Ctl::parse("count(p) >").is_err());
+}
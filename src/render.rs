@@ -0,0 +1,293 @@
+use std::collections::{HashMap, HashSet};
+use itertools::Itertools;
+use uuid::Uuid;
+use crate::guard::Guard;
+use crate::net::ColoredPetriNet;
+use crate::signature::Signature;
+
+/// How the tokens of a place are shown inside its circle.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TokenLabel {
+    #[default]
+    Count,
+    Names,
+}
+
+/// Controls what a rendering of a net shows and highlights.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RenderOptions {
+    pub tokens: TokenLabel,
+    // Fill places that hold tokens in the current marking
+    pub highlight_marking: bool,
+    // Outline transitions that have at least one binding in the current marking
+    pub highlight_enabled: bool,
+    // Transitions of a firing sequence, highlighted and numbered by the steps they occur at
+    pub path: Vec<Uuid>,
+}
+
+const MARKED_COLOR: &str = "#cfe8ff";
+const ENABLED_COLOR: &str = "#2e8b57";
+const PATH_COLOR: &str = "#d2691e";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ArcKind {
+    Normal,
+    Read,
+    Inhibitor,
+    Reset,
+}
+
+// Everything both renderers need, resolved once in a stable order
+struct Layout<'a> {
+    places: Vec<(Uuid, String, bool)>,
+    transitions: Vec<(Uuid, String, bool, Vec<usize>)>,
+    // Arcs as (source, target, label, on the path, kind); all but normal arcs lead from a place.
+    // Arcs to places that are not in the net are left out
+    arcs: Vec<(Uuid, Uuid, String, bool, ArcKind)>,
+    net: &'a ColoredPetriNet,
+}
+
+impl<'a> Layout<'a> {
+    fn new(net: &'a ColoredPetriNet, options: &RenderOptions) -> Self {
+        let places = net
+            .places
+            .values()
+            .sorted_by_key(|p| (p.name.clone(), p.id))
+            .map(|p| {
+                let tokens = net.current_marking.get(&p.id).map(|t| t.values().collect_vec()).unwrap_or_default();
+                let contents = match options.tokens {
+                    TokenLabel::Count => tokens.len().to_string(),
+                    TokenLabel::Names => tokens.iter().map(|t| t.name.clone()).sorted().join(", "),
+                };
+                let label = if contents.is_empty() { p.name.clone() } else { format!("{}\n{}", p.name, contents) };
+                (p.id, label, options.highlight_marking && !tokens.is_empty())
+            })
+            .collect();
+        let enabled: HashSet<Uuid> = if options.highlight_enabled {
+            net.enabled().into_iter().map(|(t, _)| t).collect()
+        } else {
+            HashSet::new()
+        };
+        let mut steps: HashMap<Uuid, Vec<usize>> = HashMap::new();
+        for (i, t) in options.path.iter().enumerate() {
+            steps.entry(*t).or_default().push(i + 1);
+        }
+        let transitions = net
+            .transitions
+            .values()
+            .sorted_by_key(|t| (t.name.clone(), t.id))
+            .map(|t| {
+                let mut label = t.name.clone();
+                if t.guard != Guard::Empty {
+                    label.push_str(&format!("\n[{}]", t.guard));
+                }
+                if t.priority != 0 {
+                    label.push_str(&format!("\npriority {}", t.priority));
+                }
+                if let Some(substitution) = &t.substitution {
+                    let subnet = net.subnets.get(&substitution.subnet).map(|n| n.name.clone()).unwrap_or_default();
+                    label.push_str(&format!("\n<<{}>>", subnet));
+                }
+                let steps = steps.get(&t.id).cloned().unwrap_or_default();
+                if !steps.is_empty() {
+                    label.push_str(&format!("\n#{}", steps.iter().join(",")));
+                }
+                (t.id, label, enabled.contains(&t.id), steps)
+            })
+            .collect_vec();
+        let signature_label = |s: &Signature| s.symbols.iter().sorted().join(", ");
+        let arcs = transitions
+            .iter()
+            .flat_map(|(id, _, _, steps)| {
+                let t = &net.transitions[id];
+                let on_path = !steps.is_empty();
+                let into = |arcs: Vec<(Uuid, String, ArcKind)>| {
+                    arcs.into_iter()
+                        .filter(|a| net.places.contains_key(&a.0))
+                        .sorted_by_key(|a| a.0)
+                        .map(move |(p, label, kind)| (p, t.id, label, on_path, kind))
+                };
+                into(t.input.iter().map(|(p, s)| (*p, signature_label(s), ArcKind::Normal)).collect())
+                    .chain(into(t.reads.iter().map(|(p, s)| (*p, signature_label(s), ArcKind::Read)).collect()))
+                    .chain(into(
+                        t.inhibitors.iter().map(|(p, c)| (*p, c.as_ref().map(|c| c.name()).unwrap_or_default(), ArcKind::Inhibitor)).collect(),
+                    ))
+                    .chain(into(t.resets.iter().map(|p| (*p, String::new(), ArcKind::Reset)).collect()))
+                    .chain(
+                        t.output
+                            .iter()
+                            .filter(|(p, _)| net.places.contains_key(p))
+                            .map(move |(p, s)| (t.id, *p, signature_label(s), on_path, ArcKind::Normal))
+                            .sorted_by_key(|a| a.1),
+                    )
+            })
+            .collect();
+        Self { places, transitions, arcs, net }
+    }
+
+    // Short identifiers that are valid in both DOT and Mermaid
+    fn short_ids(&self) -> HashMap<Uuid, String> {
+        self.places
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (p.0, format!("p{}", i)))
+            .chain(self.transitions.iter().enumerate().map(|(i, t)| (t.0, format!("t{}", i))))
+            .collect()
+    }
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Renders a net as a Graphviz DOT digraph.
+///
+/// Places are circles labelled with their name and tokens, transitions are boxes labelled with
+/// their name and guard, and arcs are labelled with the symbols of their signature. Read arcs are
+/// dashed without arrowheads, inhibitor arcs end in a circle and reset arcs in a double arrowhead.
+pub fn to_dot(net: &ColoredPetriNet, options: &RenderOptions) -> String {
+    let layout = Layout::new(net, options);
+    let ids = layout.short_ids();
+    let mut out = format!("digraph \"{}\" {{\n", dot_escape(&layout.net.name));
+    out.push_str("  rankdir=LR;\n");
+    for (id, label, marked) in &layout.places {
+        let fill = if *marked { format!(", style=filled, fillcolor=\"{}\"", MARKED_COLOR) } else { String::new() };
+        out.push_str(&format!("  {} [shape=circle, label=\"{}\"{}];\n", ids[id], dot_escape(label), fill));
+    }
+    for (id, label, enabled, steps) in &layout.transitions {
+        let mut style = String::new();
+        if *enabled {
+            style.push_str(&format!(", color=\"{}\", penwidth=2", ENABLED_COLOR));
+        }
+        if !steps.is_empty() {
+            style.push_str(&format!(", style=filled, fillcolor=\"{}\"", PATH_COLOR));
+        }
+        out.push_str(&format!("  {} [shape=box, label=\"{}\"{}];\n", ids[id], dot_escape(label), style));
+    }
+    for (source, target, label, on_path, kind) in &layout.arcs {
+        let mut style = if *on_path { format!(", color=\"{}\", penwidth=2", PATH_COLOR) } else { String::new() };
+        style.push_str(match kind {
+            ArcKind::Normal => "",
+            ArcKind::Read => ", style=dashed, dir=none",
+            ArcKind::Inhibitor => ", arrowhead=odot",
+            ArcKind::Reset => ", arrowhead=normalnormal",
+        });
+        out.push_str(&format!("  {} -> {} [label=\"{}\"{}];\n", ids[source], ids[target], dot_escape(label), style));
+    }
+    out.push_str("}\n");
+    out
+}
+
+// Mermaid reads labels as HTML, so angle brackets are escaped before line breaks become tags
+fn mermaid_escape(text: &str) -> String {
+    text.replace('"', "#quot;").replace('<', "#lt;").replace('>', "#gt;").replace('\n', "<br/>")
+}
+
+/// Renders a net as a Mermaid flowchart, with the same shapes and labels as [`to_dot`].
+pub fn to_mermaid(net: &ColoredPetriNet, options: &RenderOptions) -> String {
+    let layout = Layout::new(net, options);
+    let ids = layout.short_ids();
+    let mut out = String::from("flowchart LR\n");
+    for (id, label, _) in &layout.places {
+        out.push_str(&format!("  {}((\"{}\"))\n", ids[id], mermaid_escape(label)));
+    }
+    for (id, label, _, _) in &layout.transitions {
+        out.push_str(&format!("  {}[\"{}\"]\n", ids[id], mermaid_escape(label)));
+    }
+    let mut path_links = vec![];
+    for (i, (source, target, label, on_path, kind)) in layout.arcs.iter().enumerate() {
+        let end = match kind {
+            ArcKind::Normal => "-->",
+            ArcKind::Read => "-.-",
+            ArcKind::Inhibitor => "--o",
+            ArcKind::Reset => "--x",
+        };
+        let link = if label.is_empty() {
+            end.to_string()
+        } else if *kind == ArcKind::Read {
+            // Dotted links without a head only take their label between pipes
+            format!("{}|\"{}\"|", end, mermaid_escape(label))
+        } else {
+            format!("-- \"{}\" {}", mermaid_escape(label), end)
+        };
+        out.push_str(&format!("  {} {} {}\n", ids[source], link, ids[target]));
+        if *on_path {
+            path_links.push(i);
+        }
+    }
+    let classes = [
+        ("marked", format!("fill:{}", MARKED_COLOR), layout.places.iter().filter(|p| p.2).map(|p| ids[&p.0].clone()).collect_vec()),
+        ("enabled", format!("stroke:{},stroke-width:3px", ENABLED_COLOR), layout.transitions.iter().filter(|t| t.2).map(|t| ids[&t.0].clone()).collect_vec()),
+        ("path", format!("fill:{}", PATH_COLOR), layout.transitions.iter().filter(|t| !t.3.is_empty()).map(|t| ids[&t.0].clone()).collect_vec()),
+    ];
+    for (class, style, members) in classes {
+        if !members.is_empty() {
+            out.push_str(&format!("  classDef {} {}\n", class, style));
+            out.push_str(&format!("  class {} {}\n", members.join(","), class));
+        }
+    }
+    if !path_links.is_empty() {
+        out.push_str(&format!("  linkStyle {} stroke:{},stroke-width:3px\n", path_links.iter().join(","), PATH_COLOR));
+    }
+    out
+}
+
+#[test]
+pub fn render_net() {
+    use std::collections::HashSet;
+    use crate::clade::Clade;
+    use crate::place::Place;
+    use crate::token::Token;
+    use crate::transition::Transition;
+
+    let robot = Clade::new("robot".into(), None);
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    let idle = net.add_place(Place::new("idle".into()));
+    let busy = net.add_place(Place::new("busy".into()));
+    net.add_token(idle, Token::new("r1".into(), robot.clone()));
+    let start = net.add_transition(Transition::new(
+        "start".into(),
+        Some(HashMap::from([(idle, Signature::new(HashSet::from(["x".into()])))])),
+        Some(HashMap::from([(busy, Signature::new(HashSet::from(["x".into()])))])),
+        Some(Guard::Is("x".into(), robot.clone())),
+        None,
+    ));
+    let options = RenderOptions {
+        tokens: TokenLabel::Names,
+        highlight_marking: true,
+        highlight_enabled: true,
+        path: vec![start],
+    };
+
+    let dot = to_dot(&net, &options);
+    assert!(dot.contains("p1 [shape=circle, label=\"idle\\nr1\", style=filled"));
+    assert!(dot.contains("t0 [shape=box, label=\"start\\n[x = robot]\\n#1\", color="));
+    assert!(dot.contains("p1 -> t0 [label=\"x\""));
+    assert!(dot.contains("t0 -> p0 [label=\"x\""));
+
+    let mermaid = to_mermaid(&net, &options);
+    assert!(mermaid.starts_with("flowchart LR\n"));
+    assert!(mermaid.contains("p1((\"idle<br/>r1\"))"));
+    assert!(mermaid.contains("p1 -- \"x\" --> t0"));
+    assert!(mermaid.contains("class t0 enabled"));
+    assert!(mermaid.contains("linkStyle 0,1 stroke:"));
+
+    // Read arcs are undirected dotted links, labelled between pipes
+    let tool = net.add_place(Place::new("tool".into()));
+    let check = net.add_transition(
+        Transition::new("check".into(), None, None, None, None)
+            .with_read(tool, Signature::new(HashSet::from(["y".into()])))
+            .with_guard(Guard::LessThan("y".into(), robot.clone())),
+    );
+    let ids = Layout::new(&net, &options).short_ids();
+    let read = format!("  {} -.-|\"y\"| {}", ids[&tool], ids[&check]);
+    let mermaid = to_mermaid(&net, &options);
+    assert!(mermaid.lines().any(|l| l == read));
+    // Angle brackets in labels would be read as HTML
+    assert!(mermaid.contains(&format!("  {}[\"check<br/>[y #lt; robot]\"]", ids[&check])));
+
+    // Arcs to places that are not in the net are left out rather than drawn to nowhere
+    net.transitions.get_mut(&check).unwrap().reads.insert(Uuid::new_v4(), Signature::new(HashSet::from(["z".into()])));
+    assert!(!to_dot(&net, &options).contains("\"z\""));
+    assert!(!to_mermaid(&net, &options).contains("\"z\""));
+}
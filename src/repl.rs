@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use crate::guard::Guard;
+use crate::history::History;
+use crate::marking::Marking;
+use crate::net::ColoredPetriNet;
+use crate::symbol::Symbol;
+
+const HELP: &str = "\
+enabled                   List the enabled transitions with their bindings
+fire <number|transition>  Fire a binding from the list, or the only binding of a transition
+marking                   Show the tokens in every place
+eval <guard> [where x=name, ...]
+                          Evaluate a guard, binding symbols to token or clade names
+undo, redo                Take back the last firing, or do it again
+save <file>, load <file>  Save the marking and history of the session, or go back to one
+quit                      Leave the shell";
+
+/// What a session saves: the marking it reached and the firings that led there.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub marking: Marking,
+    pub history: History,
+}
+
+/// An interactive shell for stepping through a net by hand.
+///
+/// Commands are read a line at a time; see [`Repl::execute`]. Firings go through a [`History`],
+/// so they can be undone.
+#[derive(Debug)]
+pub struct Repl {
+    pub net: ColoredPetriNet,
+    pub history: History,
+}
+
+impl Repl {
+    pub fn new(net: ColoredPetriNet) -> Self {
+        Self { net, history: History::new() }
+    }
+
+    /// Runs commands from the input until it ends or a `quit`, prompting for each on the output.
+    pub fn run(&mut self, input: impl BufRead, output: &mut impl Write) -> io::Result<()> {
+        writeln!(output, "{}: type 'help' for the commands", self.net.name)?;
+        write!(output, "> ")?;
+        output.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            if matches!(line.trim(), "quit" | "exit") {
+                return Ok(());
+            }
+            match self.execute(&line) {
+                Ok(text) if text.is_empty() => {}
+                Ok(text) => writeln!(output, "{}", text)?,
+                Err(message) => writeln!(output, "error: {}", message)?,
+            }
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Runs one command, returning what it prints or an error message.
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let line = line.trim();
+        let (command, argument) = line.split_once(char::is_whitespace).map_or((line, ""), |(c, a)| (c, a.trim()));
+        match command {
+            "" => Ok(String::new()),
+            "help" => Ok(HELP.to_string()),
+            "enabled" | "ls" => Ok(self.enabled()),
+            "fire" => self.fire(argument),
+            "marking" | "show" => Ok(self.marking()),
+            "eval" => self.eval(argument),
+            "undo" => match self.history.undo(&mut self.net).map_err(|e| e.to_string())? {
+                Some(transition) => Ok(format!("undid {}", self.net.transitions[&transition].name)),
+                None => Err("nothing to undo".to_string()),
+            },
+            "redo" => match self.history.redo(&mut self.net).map_err(|e| e.to_string())? {
+                Some(transition) => Ok(format!("redid {}", self.net.transitions[&transition].name)),
+                None => Err("nothing to redo".to_string()),
+            },
+            "save" => self.save(argument),
+            "load" => self.load(argument),
+            other => Err(format!("unknown command '{}'; type 'help' for the commands", other)),
+        }
+    }
+
+    fn enabled(&self) -> String {
+        let enabled = self.net.enabled();
+        if enabled.is_empty() {
+            return "no transition is enabled".to_string();
+        }
+        enabled
+            .iter()
+            .enumerate()
+            .map(|(i, (transition, binding))| format!("{}. {}({})", i + 1, self.net.transitions[transition].name, binding))
+            .join("\n")
+    }
+
+    fn fire(&mut self, argument: &str) -> Result<String, String> {
+        let enabled = self.net.enabled();
+        let (transition, binding) = match argument.parse::<usize>() {
+            Ok(number) => enabled.get(number.wrapping_sub(1)).cloned().ok_or_else(|| format!("there is no enabled binding {}", number))?,
+            Err(_) => {
+                let id = self
+                    .net
+                    .id_of(argument)
+                    .filter(|id| self.net.transitions.contains_key(id))
+                    .ok_or_else(|| format!("unknown transition '{}'", argument))?;
+                let numbers = enabled.iter().positions(|(t, _)| *t == id).collect::<Vec<_>>();
+                match numbers[..] {
+                    [] => return Err(format!("{} is not enabled", argument)),
+                    [number] => enabled[number].clone(),
+                    _ => {
+                        let numbers = numbers.iter().map(|n| (n + 1).to_string()).join(", ");
+                        return Err(format!("{} has several bindings; fire one by number: {}", argument, numbers));
+                    }
+                }
+            }
+        };
+        self.history.fire(&mut self.net, &transition, &binding).map_err(|e| e.to_string())?;
+        Ok(format!("fired {}({})", self.net.transitions[&transition].name, binding))
+    }
+
+    fn marking(&self) -> String {
+        self.net
+            .places
+            .iter()
+            .map(|(id, place)| {
+                let tokens = self.net.current_marking.tokens(id).iter().map(|t| format!("{} ({})", t.name, t.clade.name())).join(", ");
+                (place.name.clone(), tokens)
+            })
+            .sorted()
+            .map(|(name, tokens)| format!("{}: {}", name, if tokens.is_empty() { "-".to_string() } else { tokens }))
+            .join("\n")
+    }
+
+    // Symbols are bound to the clade of a token in the current marking, or else to a clade
+    fn eval(&self, argument: &str) -> Result<String, String> {
+        let (text, assignments) = match argument.rsplit_once(" where ") {
+            Some((text, assignments)) => (text, assignments),
+            None => (argument, ""),
+        };
+        let mut candidates = HashMap::new();
+        for assignment in assignments.split(',').map(str::trim).filter(|a| !a.is_empty()) {
+            let (symbol, name) = assignment.split_once('=').ok_or_else(|| format!("expected symbol=name, not '{}'", assignment))?;
+            let name = name.trim();
+            let token = self.net.current_marking.values().flat_map(|tokens| tokens.values()).find(|t| t.name == name);
+            let clade = token.map(|t| t.clade.clone()).or_else(|| self.net.find_clade(name)).ok_or_else(|| format!("no token or clade named '{}'", name))?;
+            candidates.insert(Symbol::new(symbol.trim().to_string()), clade);
+        }
+        let guard = Guard::parse(text, &|name| self.net.find_clade(name)).map_err(|e| e.to_string())?;
+        let unbound = guard.symbols().into_iter().filter(|s| !candidates.contains_key(s)).map(|s| s.to_string()).unique().collect::<Vec<_>>();
+        let value = guard.eval(&candidates);
+        if unbound.is_empty() {
+            Ok(value.to_string())
+        } else {
+            Ok(format!("{} ({} not bound)", value, unbound.join(", ")))
+        }
+    }
+
+    fn save(&self, path: &str) -> Result<String, String> {
+        let session = Session { marking: self.net.current_marking.clone(), history: self.history.clone() };
+        let text = serde_json::to_string_pretty(&session).map_err(|e| e.to_string())?;
+        fs::write(path, text).map_err(|e| format!("cannot write {}: {}", path, e))?;
+        Ok(format!("saved to {}", path))
+    }
+
+    fn load(&mut self, path: &str) -> Result<String, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+        let session: Session = serde_json::from_str(&text).map_err(|e| format!("{} is not a saved session: {}", path, e))?;
+        let unknown = session.marking.places().into_iter().any(|p| !self.net.places.contains_key(&p))
+            || session.history.steps.iter().any(|s| !self.net.transitions.contains_key(&s.transition));
+        if unknown {
+            return Err(format!("{} was saved from a different net", path));
+        }
+        self.net.current_marking = session.marking;
+        self.history = session.history;
+        Ok(format!("loaded {}", path))
+    }
+}
+
+#[test]
+pub fn scripted_session() {
+    use std::collections::HashSet;
+    use crate::clade::Clade;
+    use crate::place::Place;
+    use crate::signature::Signature;
+    use crate::token::Token;
+    use crate::transition::Transition;
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    net.add_clade(Clade::new("robot".into(), Some(vec![robot1.clone()])));
+    let idle = net.add_place(Place::new("idle".into()));
+    let done = net.add_place(Place::new("done".into()));
+    net.add_token(idle, Token::new("r1".into(), robot1.clone()));
+    net.add_token(idle, Token::new("r2".into(), robot1.clone()));
+    let sig = || Signature::new(HashSet::from(["x".into()]));
+    net.add_transition(Transition::new("work".into(), Some(HashMap::from([(idle, sig())])), Some(HashMap::from([(done, sig())])), None, None));
+
+    let mut repl = Repl::new(net);
+    assert_eq!(repl.execute("enabled").unwrap(), "1. work(x=r1)\n2. work(x=r2)");
+    assert_eq!(repl.execute("fire work"), Err("work has several bindings; fire one by number: 1, 2".into()));
+    assert_eq!(repl.execute("fire 2").unwrap(), "fired work(x=r2)");
+    assert_eq!(repl.execute("marking").unwrap(), "done: r2 (robot1)\nidle: r1 (robot1)");
+    assert_eq!(repl.execute("eval x = robot1 and not (y > robot) where x=r1, y=robot").unwrap(), "true");
+    assert_eq!(repl.execute("eval x <= robot | y = robot1 where x=r2").unwrap(), "true (y not bound)");
+    assert!(repl.execute("eval x = drone where x=r1").unwrap_err().contains("unknown clade 'drone'"));
+
+    let path = std::env::temp_dir().join(format!("colorpnet-session-{}.json", std::process::id()));
+    let path = path.to_string_lossy().to_string();
+    repl.execute(&format!("save {}", path)).unwrap();
+    assert_eq!(repl.execute("fire work").unwrap(), "fired work(x=r1)");
+    assert_eq!(repl.execute("enabled").unwrap(), "no transition is enabled");
+    assert_eq!(repl.execute("undo").unwrap(), "undid work");
+    assert_eq!(repl.execute("redo").unwrap(), "redid work");
+    repl.execute(&format!("load {}", path)).unwrap();
+    assert_eq!(repl.net.current_marking.count(&done), 1);
+    assert_eq!(repl.execute("undo").unwrap(), "undid work");
+    assert_eq!(repl.execute("undo"), Err("nothing to undo".into()));
+    fs::remove_file(&path).unwrap();
+
+    let mut output = vec![];
+    repl.run("fire 1\nbogus\nquit\nenabled\n".as_bytes(), &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("fired work(x=r1)"));
+    assert!(output.contains("error: unknown command 'bogus'"));
+    assert!(!output.contains("1. work"));
+}
@@ -0,0 +1,171 @@
+use std::fmt;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use uuid::Uuid;
+use crate::binding::Binding;
+use crate::enabling::Enabling;
+use crate::net::ColoredPetriNet;
+use crate::transition::FiringError;
+
+/// Picks the index of one of the candidate firings.
+pub type Chooser = Box<dyn FnMut(&ColoredPetriNet, &[(Uuid, Binding)]) -> usize>;
+
+/// Chooses which of several enabled firings happens. Priorities are resolved before the policy
+/// is asked, so it only breaks ties within the highest enabled priority.
+pub enum ConflictPolicy {
+    // Uniformly at random, reproducibly for a given seed
+    Random(Box<StdRng>),
+    // The first binding of the transition declared first
+    DeclarationOrder,
+    // A callback returning the index of the chosen firing, which must be in range
+    Callback(Chooser),
+}
+
+impl ConflictPolicy {
+    pub fn random(seed: u64) -> Self {
+        ConflictPolicy::Random(Box::new(StdRng::seed_from_u64(seed)))
+    }
+
+    pub fn callback(choose: impl FnMut(&ColoredPetriNet, &[(Uuid, Binding)]) -> usize + 'static) -> Self {
+        ConflictPolicy::Callback(Box::new(choose))
+    }
+
+    /// Picks one of the candidates, which must not be empty, returning its index.
+    pub fn choose(&mut self, net: &ColoredPetriNet, candidates: &[(Uuid, Binding)]) -> usize {
+        match self {
+            ConflictPolicy::Random(rng) => rng.gen_range(0..candidates.len()),
+            ConflictPolicy::DeclarationOrder => {
+                let declared = net.declared_transitions();
+                let position = |t: &Uuid| declared.iter().position(|d| d.id == *t).unwrap_or(declared.len());
+                (0..candidates.len()).min_by_key(|i| (position(&candidates[*i].0), *i)).unwrap_or(0)
+            }
+            ConflictPolicy::Callback(choose) => choose(net, candidates),
+        }
+    }
+}
+
+impl fmt::Debug for ConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConflictPolicy::Random(_) => write!(f, "Random"),
+            ConflictPolicy::DeclarationOrder => write!(f, "DeclarationOrder"),
+            ConflictPolicy::Callback(_) => write!(f, "Callback"),
+        }
+    }
+}
+
+/// Fires transitions of a net one at a time, resolving conflicts with a policy, and records the
+/// firings.
+///
+/// Enabled transitions are tracked incrementally from one step to the next; changes to the
+/// marking between steps are picked up, but the transitions of the net should stay the same.
+#[derive(Debug)]
+pub struct Simulation {
+    pub policy: ConflictPolicy,
+    pub trace: Vec<(Uuid, Binding)>,
+    enabling: Option<Enabling>,
+}
+
+impl Simulation {
+    pub fn new(policy: ConflictPolicy) -> Self {
+        Self { policy, trace: vec![], enabling: None }
+    }
+
+    /// Fires one enabled transition of the current marking, or returns `None` in a deadlock.
+    pub fn step(&mut self, net: &mut ColoredPetriNet) -> Result<Option<(Uuid, Binding)>, FiringError> {
+        let enabling = match &mut self.enabling {
+            Some(enabling) => {
+                enabling.sync(net);
+                enabling
+            }
+            None => self.enabling.insert(Enabling::new(net)),
+        };
+        let candidates = enabling.enabled();
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+        let (transition, binding) = candidates[self.policy.choose(net, &candidates)].clone();
+        enabling.fire(net, &transition, &binding)?;
+        self.trace.push((transition, binding.clone()));
+        Ok(Some((transition, binding)))
+    }
+
+    /// Takes up to `steps` steps, stopping early in a deadlock, and returns how many were taken.
+    pub fn run(&mut self, net: &mut ColoredPetriNet, steps: usize) -> Result<usize, FiringError> {
+        for taken in 0..steps {
+            if self.step(net)?.is_none() {
+                return Ok(taken);
+            }
+        }
+        Ok(steps)
+    }
+}
+
+#[test]
+pub fn priorities_and_policies() {
+    use std::collections::{HashMap, HashSet};
+    use crate::clade::Clade;
+    use crate::place::Place;
+    use crate::signature::Signature;
+    use crate::statespace::StateSpace;
+    use crate::token::Token;
+    use crate::transition::Transition;
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    net.add_clade(Clade::new("robot".into(), Some(vec![robot1.clone()])));
+    let idle = net.add_place(Place::new("idle".into()));
+    let busy = net.add_place(Place::new("busy".into()));
+    let alarm = net.add_place(Place::new("alarm".into()));
+    let stopped = net.add_place(Place::new("stopped".into()));
+    net.add_token(idle, Token::new("r1".into(), robot1.clone()));
+    let sig = || Signature::new(HashSet::from(["x".into()]));
+    let arcs = |from, to| (Some(HashMap::from([(from, sig())])), Some(HashMap::from([(to, sig())])));
+    // Declared out of name order, so declaration order and name order differ
+    let (i, o) = arcs(idle, busy);
+    let work = net.add_transition(Transition::new("work".into(), i, o, None, None));
+    let (i, o) = arcs(idle, busy);
+    let assist = net.add_transition(Transition::new("assist".into(), i, o, None, None));
+    let (i, o) = arcs(busy, idle);
+    net.add_transition(Transition::new("finish".into(), i, o, None, None));
+    let (i, o) = arcs(busy, stopped);
+    let stop = net.add_transition(
+        Transition::new("stop".into(), i, o, None, None).with_read(alarm, Signature::new(HashSet::from(["a".into()]))).with_priority(1),
+    );
+
+    let mut simulation = Simulation::new(ConflictPolicy::DeclarationOrder);
+    assert_eq!(simulation.step(&mut net).unwrap().unwrap().0, work);
+    net.reset();
+    let mut simulation = Simulation::new(ConflictPolicy::callback(|_, candidates| candidates.len() - 1));
+    assert_eq!(simulation.step(&mut net).unwrap().unwrap().0, work);
+    net.reset();
+    let mut simulation = Simulation::new(ConflictPolicy::callback(|_, _| 0));
+    assert_eq!(simulation.step(&mut net).unwrap().unwrap().0, assist);
+
+    // Seeded runs repeat exactly
+    let runs = (0..2)
+        .map(|_| {
+            net.reset();
+            let mut simulation = Simulation::new(ConflictPolicy::random(7));
+            assert_eq!(simulation.run(&mut net, 20).unwrap(), 20);
+            simulation.trace
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(runs[0], runs[1]);
+
+    // Once the alarm is raised, stopping takes precedence over finishing the task
+    net.reset();
+    net.add_token(alarm, Token::new("a".into(), robot1.clone()));
+    let mut simulation = Simulation::new(ConflictPolicy::DeclarationOrder);
+    simulation.step(&mut net).unwrap();
+    assert_eq!(net.enabled().iter().map(|(t, _)| *t).collect::<Vec<_>>(), vec![stop]);
+    let finish = net.id_of("finish").unwrap();
+    let binding = net.transitions[&finish].bindings(&net.current_marking)[0].clone();
+    assert_eq!(net.fire(&finish, &binding), Err(FiringError::Preempted(stop)));
+    assert_eq!(simulation.run(&mut net, 5).unwrap(), 1);
+
+    net.reset();
+    let space = StateSpace::explore(&net, None);
+    assert_eq!(space.markings.len(), 3);
+    assert!(space.edges.iter().all(|e| e.transition != finish));
+}
@@ -0,0 +1,586 @@
+//! A compact binary encoding of state spaces, for graphs too large to store as JSON.
+//!
+//! A snapshot is written as a stream: markings and edges are appended as they are found, and a
+//! footer with the interning tables and an index of the markings is written at the end. Clades,
+//! tokens, places, transitions and symbols are interned, so records only hold small integers,
+//! written as LEB128 varints. A marking is stored as its difference from a base marking (its
+//! parent in the exploration), with a full marking at least every [`MAX_CHAIN`] steps.
+//!
+//! [`Snapshot`] reads a snapshot from its bytes without decoding it all up front: only the
+//! footer is read when it is opened, and each marking is decoded when asked for. The reader
+//! needs all of the bytes in memory, but they take far less room than the decoded state space;
+//! [`Snapshot::to_state_space`] decodes everything at once.
+//!
+//! All fixed-width integers are little endian. The layout is:
+//!
+//! ```text
+//! "CPNS" version:u8
+//! record*            tag:u8 length:varint payload
+//!   marking (tag 1)  base:varint (0 for none, else index + 1) removed:pairs added:pairs
+//!   edge (tag 2)     source:varint target:varint transition:varint count:varint (symbol place token)*
+//! footer             markings:varint edges:varint expanded:varint clades tokens places transitions symbols
+//! offsets            offset:u64 for each marking
+//! trailer            footer:u64 offsets:u64
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, Write};
+use uuid::Uuid;
+use crate::binding::Binding;
+use crate::clade::Clade;
+use crate::marking::Marking;
+use crate::net::ColoredPetriNet;
+use crate::statespace::{Edge, StateSpace};
+use crate::symbol::Symbol;
+use crate::token::Token;
+
+const MAGIC: &[u8; 4] = b"CPNS";
+const VERSION: u8 = 1;
+const MARKING: u8 = 1;
+const EDGE: u8 = 2;
+
+// Tokens of a marking as (place, token) indices, sorted
+type Pairs = Vec<(usize, usize)>;
+
+/// The most markings decoded to reach any one marking; a marking whose base is this far from a
+/// full marking is written in full.
+pub const MAX_CHAIN: u32 = 32;
+
+/// Reasons bytes cannot be read as a snapshot.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SnapshotError {
+    NotASnapshot,
+    UnsupportedVersion(u8),
+    // The bytes end before the data they announce, or refer to entries that do not exist
+    Corrupt(String),
+    NoSuchMarking(usize),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::NotASnapshot => write!(f, "not a state space snapshot"),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "snapshot version {} is not supported", version),
+            SnapshotError::Corrupt(message) => write!(f, "corrupt snapshot: {}", message),
+            SnapshotError::NoSuchMarking(index) => write!(f, "the snapshot has no marking {}", index),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_str(out: &mut Vec<u8>, text: &str) {
+    write_varint(out, text.len() as u64);
+    out.extend_from_slice(text.as_bytes());
+}
+
+fn write_pairs(out: &mut Vec<u8>, pairs: &[(usize, usize)]) {
+    write_varint(out, pairs.len() as u64);
+    for (place, token) in pairs {
+        write_varint(out, *place as u64);
+        write_varint(out, *token as u64);
+    }
+}
+
+// Interned elements, numbered in the order they were first seen
+#[derive(Debug, Default)]
+struct Tables {
+    clades: Vec<(Uuid, String, Option<usize>, bool)>,
+    tokens: Vec<(Uuid, String, usize)>,
+    places: Vec<Uuid>,
+    transitions: Vec<Uuid>,
+    symbols: Vec<Symbol>,
+    indices: HashMap<Uuid, usize>,
+    symbol_indices: HashMap<Symbol, usize>,
+}
+
+impl Tables {
+    // Places, transitions, tokens and clades have distinct ids, so one map serves them all
+    fn intern(indices: &mut HashMap<Uuid, usize>, list: &mut Vec<Uuid>, id: Uuid) -> usize {
+        *indices.entry(id).or_insert_with(|| {
+            list.push(id);
+            list.len() - 1
+        })
+    }
+
+    fn clade(&mut self, clade: &Clade, parent: Option<usize>) -> usize {
+        if let Some(index) = self.indices.get(&clade.id()) {
+            return *index;
+        }
+        let index = self.clades.len();
+        self.clades.push((clade.id(), clade.name(), parent, clade.children().is_some()));
+        self.indices.insert(clade.id(), index);
+        for child in clade.children().into_iter().flatten() {
+            self.clade(child, Some(index));
+        }
+        index
+    }
+
+    fn token(&mut self, token: &Token) -> usize {
+        if let Some(index) = self.indices.get(&token.id) {
+            return *index;
+        }
+        let clade = self.clade(&token.clade, None);
+        self.tokens.push((token.id, token.name.clone(), clade));
+        self.indices.insert(token.id, self.tokens.len() - 1);
+        self.tokens.len() - 1
+    }
+
+    fn place(&mut self, place: Uuid) -> usize {
+        Self::intern(&mut self.indices, &mut self.places, place)
+    }
+
+    fn transition(&mut self, transition: Uuid) -> usize {
+        Self::intern(&mut self.indices, &mut self.transitions, transition)
+    }
+
+    fn symbol(&mut self, symbol: &Symbol) -> usize {
+        *self.symbol_indices.entry(symbol.clone()).or_insert_with(|| {
+            self.symbols.push(symbol.clone());
+            self.symbols.len() - 1
+        })
+    }
+
+    fn pairs(&mut self, marking: &Marking) -> Pairs {
+        let mut pairs = vec![];
+        for place in marking.places() {
+            let place_index = self.place(place);
+            for token in marking.tokens(&place) {
+                pairs.push((place_index, self.token(token)));
+            }
+        }
+        pairs.sort();
+        pairs
+    }
+}
+
+/// Writes a snapshot as a stream of markings and edges, finished by [`SnapshotWriter::finish`].
+#[derive(Debug)]
+pub struct SnapshotWriter<W: Write> {
+    out: W,
+    position: u64,
+    tables: Tables,
+    offsets: Vec<u64>,
+    // Number of deltas between each marking and a full marking
+    chains: Vec<u32>,
+    edges: usize,
+}
+
+impl<W: Write> SnapshotWriter<W> {
+    /// Starts a snapshot of the state space of a net. The clades of the net are interned first,
+    /// so that tokens refer to them within their taxonomies.
+    pub fn new(mut out: W, net: &ColoredPetriNet) -> io::Result<Self> {
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        let mut tables = Tables::default();
+        for clade in &net.clades {
+            tables.clade(clade, None);
+        }
+        Ok(Self { out, position: MAGIC.len() as u64 + 1, tables, offsets: vec![], chains: vec![], edges: 0 })
+    }
+
+    fn record(&mut self, tag: u8, payload: &[u8]) -> io::Result<()> {
+        let mut header = vec![tag];
+        write_varint(&mut header, payload.len() as u64);
+        self.out.write_all(&header)?;
+        self.out.write_all(payload)?;
+        self.position += (header.len() + payload.len()) as u64;
+        Ok(())
+    }
+
+    /// Appends the next marking, as the difference from a marking written before (given with
+    /// its index), and returns its index.
+    pub fn marking(&mut self, marking: &Marking, base: Option<(usize, &Marking)>) -> io::Result<usize> {
+        let pairs = self.tables.pairs(marking);
+        let base = base.filter(|(index, _)| self.chains.get(*index).is_some_and(|chain| *chain < MAX_CHAIN));
+        let mut payload = vec![];
+        match base {
+            Some((index, base)) => {
+                let before = self.tables.pairs(base);
+                let removed = before.iter().filter(|p| pairs.binary_search(p).is_err()).copied().collect::<Vec<_>>();
+                let added = pairs.iter().filter(|p| before.binary_search(p).is_err()).copied().collect::<Vec<_>>();
+                write_varint(&mut payload, index as u64 + 1);
+                write_pairs(&mut payload, &removed);
+                write_pairs(&mut payload, &added);
+                self.chains.push(self.chains[index] + 1);
+            }
+            None => {
+                write_varint(&mut payload, 0);
+                write_pairs(&mut payload, &[]);
+                write_pairs(&mut payload, &pairs);
+                self.chains.push(0);
+            }
+        }
+        self.offsets.push(self.position);
+        self.record(MARKING, &payload)?;
+        Ok(self.offsets.len() - 1)
+    }
+
+    /// Appends an edge. Its markings need not have been written yet.
+    pub fn edge(&mut self, edge: &Edge) -> io::Result<()> {
+        let mut payload = vec![];
+        write_varint(&mut payload, edge.source as u64);
+        write_varint(&mut payload, edge.target as u64);
+        write_varint(&mut payload, self.tables.transition(edge.transition) as u64);
+        write_varint(&mut payload, edge.binding.tokens.len() as u64);
+        for (symbol, (place, token)) in &edge.binding.tokens {
+            write_varint(&mut payload, self.tables.symbol(symbol) as u64);
+            write_varint(&mut payload, self.tables.place(*place) as u64);
+            write_varint(&mut payload, self.tables.token(token) as u64);
+        }
+        self.edges += 1;
+        self.record(EDGE, &payload)
+    }
+
+    /// Writes the footer, given how many markings were expanded, and returns the output.
+    pub fn finish(mut self, expanded: usize) -> io::Result<W> {
+        let tables = &self.tables;
+        let mut footer = vec![];
+        write_varint(&mut footer, self.offsets.len() as u64);
+        write_varint(&mut footer, self.edges as u64);
+        write_varint(&mut footer, expanded as u64);
+        write_varint(&mut footer, tables.clades.len() as u64);
+        for (id, name, parent, branch) in &tables.clades {
+            footer.extend_from_slice(id.as_bytes());
+            write_str(&mut footer, name);
+            write_varint(&mut footer, parent.map_or(0, |p| p as u64 + 1));
+            footer.push(*branch as u8);
+        }
+        write_varint(&mut footer, tables.tokens.len() as u64);
+        for (id, name, clade) in &tables.tokens {
+            footer.extend_from_slice(id.as_bytes());
+            write_str(&mut footer, name);
+            write_varint(&mut footer, *clade as u64);
+        }
+        for ids in [&tables.places, &tables.transitions] {
+            write_varint(&mut footer, ids.len() as u64);
+            for id in ids {
+                footer.extend_from_slice(id.as_bytes());
+            }
+        }
+        write_varint(&mut footer, tables.symbols.len() as u64);
+        for symbol in &tables.symbols {
+            write_str(&mut footer, symbol.name());
+        }
+        let footer_start = self.position;
+        let offsets_start = footer_start + footer.len() as u64;
+        for offset in &self.offsets {
+            footer.extend_from_slice(&offset.to_le_bytes());
+        }
+        footer.extend_from_slice(&footer_start.to_le_bytes());
+        footer.extend_from_slice(&offsets_start.to_le_bytes());
+        self.out.write_all(&footer)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Writes a whole state space, encoding each marking against the one it was first reached from.
+pub fn write<W: Write>(space: &StateSpace, net: &ColoredPetriNet, out: W) -> io::Result<W> {
+    let mut parents = vec![None; space.markings.len()];
+    for edge in &space.edges {
+        if edge.source < edge.target && parents[edge.target].is_none() {
+            parents[edge.target] = Some(edge.source);
+        }
+    }
+    let mut writer = SnapshotWriter::new(out, net)?;
+    for (marking, parent) in space.markings.iter().zip(parents) {
+        writer.marking(marking, parent.map(|p| (p, &space.markings[p])))?;
+    }
+    for edge in &space.edges {
+        writer.edge(edge)?;
+    }
+    writer.finish(space.expanded)
+}
+
+// Reads varints and fixed-width values from a position in the snapshot
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], SnapshotError> {
+        let end = self.position.checked_add(count).filter(|end| *end <= self.bytes.len());
+        let end = end.ok_or_else(|| SnapshotError::Corrupt("unexpected end of data".into()))?;
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn varint(&mut self) -> Result<u64, SnapshotError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                return Ok(value);
+            }
+        }
+        Err(SnapshotError::Corrupt("varint too long".into()))
+    }
+
+    fn index(&mut self, length: usize, what: &str) -> Result<usize, SnapshotError> {
+        let index = self.varint()? as usize;
+        if index < length {
+            Ok(index)
+        } else {
+            Err(SnapshotError::Corrupt(format!("{} {} does not exist", what, index)))
+        }
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().expect("8 bytes")))
+    }
+
+    fn uuid(&mut self) -> Result<Uuid, SnapshotError> {
+        Ok(Uuid::from_bytes(self.take(16)?.try_into().expect("16 bytes")))
+    }
+
+    fn string(&mut self) -> Result<String, SnapshotError> {
+        let length = self.varint()? as usize;
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| SnapshotError::Corrupt("invalid UTF-8".into()))
+    }
+
+    fn pairs(&mut self, places: usize, tokens: usize) -> Result<Pairs, SnapshotError> {
+        let count = self.varint()? as usize;
+        (0..count).map(|_| Ok((self.index(places, "place")?, self.index(tokens, "token")?))).collect()
+    }
+}
+
+/// A snapshot read from bytes, decoding markings and edges as they are asked for.
+#[derive(Debug)]
+pub struct Snapshot<'a> {
+    bytes: &'a [u8],
+    markings: usize,
+    edges: usize,
+    pub expanded: usize,
+    tokens: Vec<Token>,
+    places: Vec<Uuid>,
+    transitions: Vec<Uuid>,
+    symbols: Vec<Symbol>,
+    footer: usize,
+    offsets: usize,
+}
+
+impl<'a> Snapshot<'a> {
+    /// Opens a snapshot, reading only its footer.
+    pub fn read(bytes: &'a [u8]) -> Result<Self, SnapshotError> {
+        if bytes.len() < MAGIC.len() + 1 + 16 || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(SnapshotError::NotASnapshot);
+        }
+        if bytes[MAGIC.len()] != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(bytes[MAGIC.len()]));
+        }
+        let mut trailer = Reader { bytes, position: bytes.len() - 16 };
+        let (footer, offsets) = (trailer.u64()? as usize, trailer.u64()? as usize);
+        let mut reader = Reader { bytes, position: footer };
+        let markings = reader.varint()? as usize;
+        let edges = reader.varint()? as usize;
+        let expanded = reader.varint()? as usize;
+        if offsets.checked_add(markings.saturating_mul(8)) != Some(bytes.len() - 16) {
+            return Err(SnapshotError::Corrupt("the marking index does not fit the data".into()));
+        }
+
+        // Parents come before their children, so clades are built from the last one up
+        let count = reader.varint()? as usize;
+        let mut clades = vec![];
+        for index in 0..count {
+            let (id, name) = (reader.uuid()?, reader.string()?);
+            let parent = match reader.varint()? as usize {
+                0 => None,
+                p if p <= index => Some(p - 1),
+                _ => return Err(SnapshotError::Corrupt("a clade comes before its parent".into())),
+            };
+            clades.push((id, name, parent, reader.take(1)?[0] == 1));
+        }
+        let mut built: Vec<Option<Clade>> = vec![None; clades.len()];
+        let mut children: BTreeMap<usize, Vec<Clade>> = BTreeMap::new();
+        for (index, (uuid, name, parent, branch)) in clades.into_iter().enumerate().rev() {
+            let clade = if branch {
+                let mut own = children.remove(&index).unwrap_or_default();
+                own.reverse();
+                Clade::Branch { uuid, name, children: own }
+            } else {
+                Clade::Leaf { uuid, name }
+            };
+            if let Some(parent) = parent {
+                children.entry(parent).or_default().push(clade.clone());
+            }
+            built[index] = Some(clade);
+        }
+        let clades = built.into_iter().flatten().collect::<Vec<_>>();
+
+        let count = reader.varint()? as usize;
+        let mut tokens = vec![];
+        for _ in 0..count {
+            let (id, name) = (reader.uuid()?, reader.string()?);
+            tokens.push(Token { id, name, clade: clades[reader.index(clades.len(), "clade")?].clone() });
+        }
+        let mut ids = || -> Result<Vec<Uuid>, SnapshotError> { (0..reader.varint()?).map(|_| reader.uuid()).collect() };
+        let (places, transitions) = (ids()?, ids()?);
+        let symbols = (0..reader.varint()?).map(|_| Ok(Symbol::new(reader.string()?))).collect::<Result<_, SnapshotError>>()?;
+        Ok(Self { bytes, markings, edges, expanded, tokens, places, transitions, symbols, footer, offsets })
+    }
+
+    /// The number of markings.
+    pub fn len(&self) -> usize {
+        self.markings
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.markings == 0
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.edges
+    }
+
+    // The base of a marking record and the pairs it removes and adds
+    fn record(&self, index: usize) -> Result<(Option<usize>, Pairs, Pairs), SnapshotError> {
+        let offset = Reader { bytes: self.bytes, position: self.offsets + index * 8 }.u64()? as usize;
+        let mut reader = Reader { bytes: self.bytes, position: offset };
+        if reader.take(1)?[0] != MARKING {
+            return Err(SnapshotError::Corrupt(format!("marking {} is not at its offset", index)));
+        }
+        reader.varint()?;
+        let base = match reader.varint()? as usize {
+            0 => None,
+            b if b <= index => Some(b - 1),
+            _ => return Err(SnapshotError::Corrupt(format!("marking {} is based on a later marking", index))),
+        };
+        let (places, tokens) = (self.places.len(), self.tokens.len());
+        Ok((base, reader.pairs(places, tokens)?, reader.pairs(places, tokens)?))
+    }
+
+    /// Decodes a marking, applying the deltas from the nearest full marking.
+    pub fn marking(&self, index: usize) -> Result<Marking, SnapshotError> {
+        if index >= self.markings {
+            return Err(SnapshotError::NoSuchMarking(index));
+        }
+        let mut chain = vec![];
+        let mut current = Some(index);
+        while let Some(index) = current {
+            let (base, removed, added) = self.record(index)?;
+            chain.push((removed, added));
+            current = base;
+        }
+        let mut pairs: Pairs = vec![];
+        for (removed, added) in chain.into_iter().rev() {
+            pairs.retain(|p| !removed.contains(p));
+            pairs.extend(added);
+        }
+        Ok(pairs.into_iter().map(|(place, token)| (self.places[place], self.tokens[token].clone())).collect())
+    }
+
+    /// Decodes every edge, in the order they were written.
+    pub fn edges(&self) -> impl Iterator<Item = Result<Edge, SnapshotError>> + '_ {
+        let mut reader = Reader { bytes: self.bytes, position: MAGIC.len() + 1 };
+        std::iter::from_fn(move || {
+            while reader.position < self.footer {
+                let record = (|| {
+                    let tag = reader.take(1)?[0];
+                    let length = reader.varint()? as usize;
+                    let mut payload = Reader { bytes: reader.take(length)?, position: 0 };
+                    if tag != EDGE {
+                        return Ok(None);
+                    }
+                    let source = payload.index(self.markings, "marking")?;
+                    let target = payload.index(self.markings, "marking")?;
+                    let transition = self.transitions[payload.index(self.transitions.len(), "transition")?];
+                    let mut tokens = BTreeMap::new();
+                    for _ in 0..payload.varint()? {
+                        let symbol = self.symbols[payload.index(self.symbols.len(), "symbol")?].clone();
+                        let place = self.places[payload.index(self.places.len(), "place")?];
+                        let token = self.tokens[payload.index(self.tokens.len(), "token")?].clone();
+                        tokens.insert(symbol, (place, token));
+                    }
+                    Ok(Some(Edge { source, target, transition, binding: Binding::new(tokens) }))
+                })();
+                match record {
+                    Ok(None) => continue,
+                    Ok(Some(edge)) => return Some(Ok(edge)),
+                    Err(error) => {
+                        reader.position = self.footer;
+                        return Some(Err(error));
+                    }
+                }
+            }
+            None
+        })
+    }
+
+    /// Decodes the whole state space.
+    pub fn to_state_space(&self) -> Result<StateSpace, SnapshotError> {
+        Ok(StateSpace {
+            markings: (0..self.markings).map(|m| self.marking(m)).collect::<Result<_, _>>()?,
+            edges: self.edges().collect::<Result<_, _>>()?,
+            expanded: self.expanded,
+        })
+    }
+}
+
+#[test]
+pub fn snapshot_round_trip() {
+    use std::collections::HashSet;
+    use crate::place::Place;
+    use crate::signature::Signature;
+    use crate::transition::Transition;
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    net.add_clade(Clade::new("robot".into(), Some(vec![robot1.clone(), Clade::new("robot2".into(), None)])));
+    let idle = net.add_place(Place::new("idle".into()));
+    let busy = net.add_place(Place::new("busy".into()));
+    let done = net.add_place(Place::new("done".into()));
+    for i in 0..5 {
+        net.add_token(idle, Token::new(format!("r{}", i), robot1.clone()));
+    }
+    let sig = || Signature::new(HashSet::from(["x".into()]));
+    let arcs = |from, to| (Some(HashMap::from([(from, sig())])), Some(HashMap::from([(to, sig())])));
+    for (name, from, to) in [("start", idle, busy), ("pause", busy, idle), ("finish", busy, done)] {
+        let (input, output) = arcs(from, to);
+        net.add_transition(Transition::new(name.into(), input, output, None, None));
+    }
+
+    let space = StateSpace::explore(&net, None);
+    assert_eq!(space.markings.len(), 243);
+    let bytes = write(&space, &net, vec![]).unwrap();
+    let json = serde_json::to_string(&space).unwrap();
+    assert!(bytes.len() * 10 < json.len());
+
+    let snapshot = Snapshot::read(&bytes).unwrap();
+    assert_eq!(snapshot.len(), space.markings.len());
+    assert_eq!(snapshot.marking(242).unwrap(), space.markings[242]);
+    assert_eq!(snapshot.to_state_space().unwrap(), space);
+    assert_eq!(snapshot.marking(243), Err(SnapshotError::NoSuchMarking(243)));
+    // Tokens keep their clades within the taxonomy
+    let token = snapshot.marking(0).unwrap().tokens(&idle)[0].clone();
+    assert_eq!(token.clade, robot1);
+
+    // Long chains of deltas are broken by full markings
+    let mut writer = SnapshotWriter::new(vec![], &net).unwrap();
+    let mut previous = net.current_marking.clone();
+    writer.marking(&previous, None).unwrap();
+    for index in 0..100 {
+        let next = net.firings(&previous).into_iter().next().map_or(previous.clone(), |(_, _, next)| next);
+        writer.marking(&next, Some((index, &previous))).unwrap();
+        previous = next;
+    }
+    assert!(writer.chains.iter().all(|chain| *chain <= MAX_CHAIN));
+    assert!(writer.chains.contains(&0) && writer.chains[MAX_CHAIN as usize + 1] == 0);
+    let bytes = writer.finish(0).unwrap();
+    assert_eq!(Snapshot::read(&bytes).unwrap().marking(100).unwrap(), previous);
+
+    assert_eq!(Snapshot::read(b"{\"markings\": []}").unwrap_err(), SnapshotError::NotASnapshot);
+    assert!(matches!(Snapshot::read(&bytes[..bytes.len() - 1]), Err(SnapshotError::Corrupt(_))));
+}
@@ -0,0 +1,381 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::binding::Binding;
+use crate::marking::Marking;
+use crate::net::{ColoredPetriNet, Firing};
+
+/// An edge of the reachability graph: firing `transition` with `binding` in marking `source`
+/// leads to marking `target`. Markings are referred to by their index in the state space.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Edge {
+    pub source: usize,
+    pub target: usize,
+    pub transition: Uuid,
+    pub binding: Binding,
+}
+
+/// The reachability graph of a net, explored breadth first from its current marking.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StateSpace {
+    // Reachable markings, with the starting marking at index 0
+    pub markings: Vec<Marking>,
+    pub edges: Vec<Edge>,
+    // Markings with an index below this have had all of their successors computed
+    pub expanded: usize,
+}
+
+// 64-bit FNV-1a, which unlike the std hashers is guaranteed to be stable between builds
+fn fnv1a(bytes: impl Iterator<Item = u8>) -> u64 {
+    bytes.fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// A compact, single-line summary of a marking, listing the tokens of each marked place.
+pub fn marking_summary(net: &ColoredPetriNet, marking: &Marking) -> String {
+    let places = marking
+        .iter()
+        .filter(|(_, tokens)| !tokens.is_empty())
+        .map(|(place, tokens)| {
+            let name = net.places.get(place).map(|p| p.name.clone()).unwrap_or_else(|| place.to_string());
+            (name, tokens.values().map(|t| t.name.clone()).sorted().join(","))
+        })
+        .sorted()
+        .map(|(name, tokens)| format!("{}{{{}}}", name, tokens))
+        .collect_vec();
+    if places.is_empty() {
+        "empty".to_string()
+    } else {
+        places.join(" ")
+    }
+}
+
+/// Assigns each node of a graph, given by its successor lists, to its strongly connected
+/// component with Tarjan's algorithm. Components are numbered in reverse topological order.
+pub fn strongly_connected(successors: &[Vec<usize>]) -> Vec<usize> {
+    let n = successors.len();
+    let mut index = vec![usize::MAX; n];
+    let mut low = vec![0; n];
+    let mut on_stack = vec![false; n];
+    let mut stack = vec![];
+    let mut component = vec![usize::MAX; n];
+    let mut next_index = 0;
+    let mut next_component = 0;
+    for root in 0..n {
+        if index[root] != usize::MAX {
+            continue;
+        }
+        // Iterative depth-first search; each frame is (node, next successor to visit)
+        let mut frames = vec![(root, 0)];
+        index[root] = next_index;
+        low[root] = next_index;
+        next_index += 1;
+        stack.push(root);
+        on_stack[root] = true;
+        while let Some((node, i)) = frames.pop() {
+            if i < successors[node].len() {
+                frames.push((node, i + 1));
+                let next = successors[node][i];
+                if index[next] == usize::MAX {
+                    index[next] = next_index;
+                    low[next] = next_index;
+                    next_index += 1;
+                    stack.push(next);
+                    on_stack[next] = true;
+                    frames.push((next, 0));
+                } else if on_stack[next] {
+                    low[node] = low[node].min(index[next]);
+                }
+                continue;
+            }
+            if low[node] == index[node] {
+                while let Some(member) = stack.pop() {
+                    on_stack[member] = false;
+                    component[member] = next_component;
+                    if member == node {
+                        break;
+                    }
+                }
+                next_component += 1;
+            }
+            if let Some((parent, _)) = frames.last() {
+                low[*parent] = low[*parent].min(low[node]);
+            }
+        }
+    }
+    component
+}
+
+/// Explores breadth first from the current marking of the net, stopping once `limit` markings
+/// have been found (if given). Markings with the same `key` are taken as one, represented by the
+/// first of them found. Markings are expanded in the order they were found, each with the
+/// firings `successors` gives for its index, seeing the markings found so far and their index
+/// by key.
+pub(crate) fn explore_with<K: Hash + Eq>(
+    net: &ColoredPetriNet,
+    limit: Option<usize>,
+    key: impl Fn(&Marking) -> K,
+    mut successors: impl FnMut(usize, &[Marking], &HashMap<K, usize>) -> Vec<Firing>,
+) -> StateSpace {
+    let mut space = StateSpace::default();
+    let mut index = HashMap::from([(key(&net.current_marking), 0)]);
+    space.markings.push(net.current_marking.clone());
+    while space.expanded < space.markings.len() && limit.is_none_or(|l| space.markings.len() < l) {
+        let source = space.expanded;
+        for (transition, binding, next) in successors(source, &space.markings, &index) {
+            let target = *index.entry(key(&next)).or_insert_with(|| {
+                space.markings.push(next);
+                space.markings.len() - 1
+            });
+            space.edges.push(Edge { source, target, transition, binding });
+        }
+        space.expanded = source + 1;
+    }
+    space
+}
+
+impl StateSpace {
+    /// Explores the markings reachable from the current marking of the net, stopping once
+    /// `limit` markings have been found (if given). Only the firings of the highest enabled
+    /// priority are followed from each marking.
+    pub fn explore(net: &ColoredPetriNet, limit: Option<usize>) -> Self {
+        explore_with(net, limit, Marking::clone, |source, markings, _| net.firings(&markings[source]))
+    }
+
+    /// True if exploration stopped before every reachable marking had been expanded.
+    pub fn truncated(&self) -> bool {
+        self.expanded < self.markings.len()
+    }
+
+    /// Identifier of a marking that depends only on its contents, not on the exploration order.
+    pub fn marking_id(&self, marking: usize) -> String {
+        let key = self.markings[marking].key();
+        let bytes = key.iter().flat_map(|(place, tokens)| {
+            place.as_bytes().iter().copied().chain(tokens.iter().flat_map(|t| t.as_bytes().iter().copied())).chain([0xff])
+        });
+        format!("m{:016x}", fnv1a(bytes))
+    }
+
+    pub fn successors(&self, marking: usize) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(move |e| e.source == marking)
+    }
+
+    /// Expanded markings without any enabled transition.
+    pub fn dead(&self) -> Vec<usize> {
+        let live: HashSet<usize> = self.edges.iter().map(|e| e.source).collect();
+        (0..self.expanded).filter(|m| !live.contains(m)).collect()
+    }
+
+    /// Markings satisfying a predicate.
+    pub fn find(&self, predicate: impl Fn(&Marking) -> bool) -> Vec<usize> {
+        (0..self.markings.len()).filter(|m| predicate(&self.markings[*m])).collect()
+    }
+
+    /// Assigns each marking to its strongly connected component.
+    /// Components are numbered in reverse topological order.
+    pub fn components(&self) -> Vec<usize> {
+        let mut successors: Vec<Vec<usize>> = vec![vec![]; self.markings.len()];
+        for edge in &self.edges {
+            successors[edge.source].push(edge.target);
+        }
+        strongly_connected(&successors)
+    }
+
+    // Nodes and edges as they are exported, optionally with components collapsed into single nodes
+    fn export_graph(&self, net: &ColoredPetriNet, options: &GraphOptions) -> ExportGraph {
+        let dead: HashSet<usize> = self.dead().into_iter().collect();
+        let groups: Vec<Vec<usize>> = if options.collapse_components {
+            let components = self.components();
+            let count = components.iter().max().map(|m| m + 1).unwrap_or(0);
+            let mut groups = vec![vec![]; count];
+            for (marking, component) in components.iter().enumerate() {
+                groups[*component].push(marking);
+            }
+            // Present components in the order their first marking was discovered
+            groups.sort_by_key(|g| g[0]);
+            groups
+        } else {
+            (0..self.markings.len()).map(|m| vec![m]).collect()
+        };
+        let mut node_of = vec![0; self.markings.len()];
+        for (node, group) in groups.iter().enumerate() {
+            for marking in group {
+                node_of[*marking] = node;
+            }
+        }
+        let nodes = groups
+            .iter()
+            .map(|group| ExportNode {
+                id: if group.len() == 1 {
+                    self.marking_id(group[0])
+                } else {
+                    format!("c{:016x}", fnv1a(group.iter().flat_map(|m| self.marking_id(*m).into_bytes())))
+                },
+                markings: group.iter().map(|m| self.marking_id(*m)).collect(),
+                summary: group.iter().map(|m| marking_summary(net, &self.markings[*m])).collect(),
+                initial: group.contains(&0),
+                dead: group.iter().any(|m| dead.contains(m)),
+                goal: group.iter().any(|m| options.goals.contains(m)),
+            })
+            .collect_vec();
+        let mut edges: Vec<ExportEdge> = vec![];
+        let mut seen: HashSet<(usize, usize, Uuid, String)> = HashSet::new();
+        for edge in &self.edges {
+            let (source, target) = (node_of[edge.source], node_of[edge.target]);
+            if options.collapse_components && source == target {
+                continue;
+            }
+            let binding = edge.binding.to_string();
+            if !seen.insert((source, target, edge.transition, binding)) {
+                continue;
+            }
+            edges.push(ExportEdge {
+                source: nodes[source].id.clone(),
+                target: nodes[target].id.clone(),
+                transition: net.transitions.get(&edge.transition).map(|t| t.name.clone()).unwrap_or_default(),
+                transition_id: edge.transition,
+                binding: edge
+                    .binding
+                    .tokens
+                    .iter()
+                    .map(|(symbol, (_, token))| (symbol.to_string(), token.name.clone()))
+                    .collect(),
+            });
+        }
+        ExportGraph { nodes, edges, truncated: self.truncated() }
+    }
+
+    /// Renders the reachability graph as a Graphviz DOT digraph.
+    ///
+    /// Nodes are labelled with a marking summary and edges with the transition and binding.
+    /// The initial marking is drawn with a double border, dead markings in red and goal
+    /// markings in green.
+    pub fn to_dot(&self, net: &ColoredPetriNet, options: &GraphOptions) -> String {
+        let graph = self.export_graph(net, options);
+        let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+        let mut out = format!("digraph \"{}\" {{\n", escape(&net.name));
+        out.push_str("  node [shape=box];\n");
+        for node in &graph.nodes {
+            let label = if node.summary.len() == 1 {
+                node.summary[0].clone()
+            } else {
+                format!("{} markings\n{}", node.summary.len(), node.summary.join("\n"))
+            };
+            let mut style = vec![];
+            if node.initial {
+                style.push("peripheries=2".to_string());
+            }
+            if node.goal {
+                style.push("style=filled, fillcolor=\"#b7e4c7\"".to_string());
+            } else if node.dead {
+                style.push("style=filled, fillcolor=\"#f4acb7\"".to_string());
+            }
+            let style = style.iter().map(|s| format!(", {}", s)).join("");
+            out.push_str(&format!("  {} [label=\"{}\"{}];\n", node.id, escape(&label), style));
+        }
+        for edge in &graph.edges {
+            let binding = edge.binding.iter().map(|(s, t)| format!("{}={}", s, t)).join(", ");
+            out.push_str(&format!(
+                "  {} -> {} [label=\"{}({})\"];\n",
+                edge.source,
+                edge.target,
+                escape(&edge.transition),
+                escape(&binding)
+            ));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Serializes the reachability graph as JSON, with content-derived marking ids.
+    pub fn to_json(&self, net: &ColoredPetriNet, options: &GraphOptions) -> String {
+        serde_json::to_string_pretty(&self.export_graph(net, options)).unwrap_or_default()
+    }
+}
+
+/// Controls how a reachability graph is exported.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GraphOptions {
+    // Indices of markings to highlight as goals
+    pub goals: HashSet<usize>,
+    // Merge each strongly connected component into a single node
+    pub collapse_components: bool,
+}
+
+#[derive(Serialize)]
+struct ExportNode {
+    id: String,
+    markings: Vec<String>,
+    summary: Vec<String>,
+    initial: bool,
+    dead: bool,
+    goal: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportEdge {
+    source: String,
+    target: String,
+    transition: String,
+    transition_id: Uuid,
+    binding: BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct ExportGraph {
+    nodes: Vec<ExportNode>,
+    edges: Vec<ExportEdge>,
+    truncated: bool,
+}
+
+#[test]
+pub fn explore_and_export() {
+    use crate::clade::Clade;
+    use crate::place::Place;
+    use crate::signature::Signature;
+    use crate::token::Token;
+    use crate::transition::Transition;
+
+    let robot = Clade::new("robot".into(), None);
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    let idle = net.add_place(Place::new("idle".into()));
+    let busy = net.add_place(Place::new("busy".into()));
+    let done = net.add_place(Place::new("done".into()));
+    net.add_token(idle, Token::new("r1".into(), robot.clone()));
+    let sig = || Signature::new(HashSet::from(["x".into()]));
+    net.add_transition(Transition::new("start".into(), Some(HashMap::from([(idle, sig())])), Some(HashMap::from([(busy, sig())])), None, None));
+    net.add_transition(Transition::new("pause".into(), Some(HashMap::from([(busy, sig())])), Some(HashMap::from([(idle, sig())])), None, None));
+    net.add_transition(Transition::new("finish".into(), Some(HashMap::from([(busy, sig())])), Some(HashMap::from([(done, sig())])), None, None));
+
+    let space = StateSpace::explore(&net, None);
+    assert_eq!(space.markings.len(), 3);
+    assert_eq!(space.edges.len(), 3);
+    assert!(!space.truncated());
+    let finished = space.find(|m| m.get(&done).is_some_and(|t| !t.is_empty()));
+    assert_eq!(space.dead(), finished);
+
+    // Exploring again gives the same marking ids
+    let again = StateSpace::explore(&net, None);
+    assert_eq!(space.marking_id(2), again.marking_id(2));
+
+    let components = space.components();
+    assert_eq!(components[0], components[1]);
+    assert_ne!(components[0], components[2]);
+
+    let options = GraphOptions { goals: finished.iter().copied().collect(), collapse_components: true };
+    let dot = space.to_dot(&net, &options);
+    assert!(dot.contains("2 markings"));
+    assert!(dot.contains("done{r1}\", style=filled, fillcolor=\"#b7e4c7\""));
+    assert_eq!(dot.matches("->").count(), 1);
+    let json: serde_json::Value = serde_json::from_str(&space.to_json(&net, &GraphOptions::default())).unwrap();
+    assert_eq!(json["nodes"].as_array().unwrap().len(), 3);
+    assert_eq!(json["nodes"][2]["id"], space.marking_id(2));
+    assert_eq!(json["nodes"][2]["dead"], true);
+    assert_eq!(json["edges"][0]["binding"]["x"], "r1");
+
+    let partial = StateSpace::explore(&net, Some(2));
+    assert!(partial.truncated());
+    assert!(partial.dead().is_empty());
+}
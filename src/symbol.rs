@@ -1,4 +1,27 @@
+use std::fmt;
 use serde::{Serialize, Deserialize};
 
-#[derive(Clone, Debug, Hash, Serialize, PartialEq, Eq, Deserialize)]
-pub struct Symbol(String);
\ No newline at end of file
+#[derive(Clone, Debug, Hash, Serialize, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+pub struct Symbol(String);
+
+impl Symbol {
+    pub fn new(name: String) -> Self {
+        Self(name)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Self(name.to_string())
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
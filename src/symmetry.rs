@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use uuid::Uuid;
+use crate::marking::Marking;
+use crate::net::{ColoredPetriNet, Firing};
+use crate::statespace::{explore_with, StateSpace};
+
+/// What is left of a marking once tokens of the same clade are taken as interchangeable: how
+/// many tokens of each clade are in each marked place.
+pub type SymmetryKey = Vec<(Uuid, BTreeMap<Uuid, usize>)>;
+
+/// The canonical form of a marking under permutations of tokens of the same clade. Two markings
+/// have the same key exactly when renaming tokens of equal clades turns one into the other.
+pub fn symmetry_key(marking: &Marking) -> SymmetryKey {
+    let mut places = marking.places();
+    places.sort();
+    places.into_iter().map(|place| (place, marking.clades(&place))).collect()
+}
+
+/// Explores the reachable markings as [`StateSpace::explore`] does, but keeps only one marking
+/// of each class of markings that differ just in which tokens of a clade are where.
+///
+/// Guards, inhibitors, colors and capacities only look at the clades of tokens, so markings of
+/// a class enable the same transitions and lead to the same classes, and the reduced graph
+/// answers queries as the full one would. Each class is represented by the first of its
+/// markings found, and an edge may lead to a marking of the target class other than its
+/// representative; [`concretize`] recovers the concrete firings of a path.
+pub fn explore(net: &ColoredPetriNet, limit: Option<usize>) -> StateSpace {
+    explore_with(net, limit, symmetry_key, |source, markings, _| net.firings(&markings[source]))
+}
+
+/// Replays a path of edges of a (possibly reduced) state space from the current marking of the
+/// net, returning the concrete firing taken for each edge: one of the same transition that
+/// leads to the same class of markings, with the same clades bound when possible. Returns
+/// `None` if some edge cannot be followed, i.e. the state space is not one of this net.
+///
+/// The cycle of a lasso can be replayed once after its prefix, but may not come back to the
+/// same concrete marking, only to one of the same class.
+pub fn concretize(net: &ColoredPetriNet, space: &StateSpace, path: &[usize]) -> Option<Vec<Firing>> {
+    let mut current = net.current_marking.clone();
+    let mut firings = vec![];
+    for edge in path.iter().map(|e| space.edges.get(*e)) {
+        let edge = edge?;
+        let target = symmetry_key(space.markings.get(edge.target)?);
+        let clades = edge.binding.clades();
+        let candidates = net
+            .firings(&current)
+            .into_iter()
+            .filter(|(transition, _, next)| *transition == edge.transition && symmetry_key(next) == target)
+            .collect::<Vec<_>>();
+        let chosen = candidates.iter().position(|(_, binding, _)| binding.clades() == clades).unwrap_or(0);
+        let firing = candidates.into_iter().nth(chosen)?;
+        current = firing.2.clone();
+        firings.push(firing);
+    }
+    Some(firings)
+}
+
+#[test]
+pub fn symmetric_robots() {
+    use std::collections::{HashMap, HashSet};
+    use crate::clade::Clade;
+    use crate::modelcheck::ModelChecker;
+    use crate::place::Place;
+    use crate::query::Ctl;
+    use crate::signature::Signature;
+    use crate::token::Token;
+    use crate::transition::Transition;
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let robot2 = Clade::new("robot2".into(), None);
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    net.add_clade(Clade::new("robot".into(), Some(vec![robot1.clone(), robot2.clone()])));
+    let idle = net.add_place(Place::new("idle".into()));
+    let busy = net.add_place(Place::new("busy".into()));
+    let done = net.add_place(Place::new("done".into()));
+    for i in 0..4 {
+        net.add_token(idle, Token::new(format!("a{}", i), robot1.clone()));
+    }
+    net.add_token(idle, Token::new("b0".into(), robot2.clone()));
+    let sig = || Signature::new(HashSet::from(["x".into()]));
+    let arcs = |from, to| (Some(HashMap::from([(from, sig())])), Some(HashMap::from([(to, sig())])));
+    for (name, from, to) in [("start", idle, busy), ("pause", busy, idle), ("finish", busy, done)] {
+        let (input, output) = arcs(from, to);
+        net.add_transition(Transition::new(name.into(), input, output, None, None));
+    }
+
+    // 3^5 concrete markings, but only 15 ways to spread four robot1 over three places, times 3
+    // places for the robot2
+    let full = StateSpace::explore(&net, None);
+    let reduced = explore(&net, None);
+    assert_eq!(full.markings.len(), 243);
+    assert_eq!(reduced.markings.len(), 45);
+    assert_eq!(reduced.dead().len(), 1);
+    let keys = |space: &StateSpace| space.markings.iter().map(symmetry_key).collect::<HashSet<_>>();
+    assert_eq!(keys(&reduced), keys(&full));
+
+    // A witness found in the reduced graph replays with concrete robots
+    let checker = ModelChecker::from_space(&net, reduced.clone()).unwrap();
+    let goal = Ctl::parse("EF (count(done, robot1) >= 3 & count(busy, robot2) == 1)").unwrap();
+    let trace = checker.check_ctl(&goal).unwrap().trace.unwrap();
+    let firings = concretize(&net, &reduced, &trace.prefix).unwrap();
+    assert_eq!(firings.len(), 7);
+    let mut replay = net.clone();
+    for (transition, binding, _) in &firings {
+        replay.fire(transition, binding).unwrap();
+    }
+    assert_eq!(replay.current_marking.count_of(&done, &robot1), 3);
+    assert_eq!(replay.current_marking.count_of(&busy, &robot2), 1);
+    assert_eq!(symmetry_key(&replay.current_marking), symmetry_key(&reduced.markings[reduced.edges[*trace.prefix.last().unwrap()].target]));
+
+    let other = ColoredPetriNet::new("other".into(), None, None, None);
+    assert_eq!(concretize(&other, &reduced, &trace.prefix), None);
+}
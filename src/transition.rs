@@ -1,13 +1,58 @@
 // #[cfg(test)]
+use crate::binding::Binding;
+use crate::clade::Clade;
 use crate::function::Function;
 use crate::guard::Guard;
+use crate::hierarchy::Substitution;
+use crate::marking::Marking;
+use crate::place::PlaceViolation;
 use crate::signature::Signature;
+use crate::symbol::Symbol;
 use itertools::Itertools;
 use log::warn;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use uuid::Uuid;
 
+/// Reasons a transition cannot fire with a given binding.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FiringError {
+    UnknownTransition(Uuid),
+    // An input symbol has no token in the binding
+    UnboundInput(Symbol),
+    // The bound token is not (or no longer) in the place it is taken from
+    MissingToken { symbol: Symbol, place: Uuid },
+    GuardNotSatisfied,
+    // An output symbol is neither bound by an input nor produced by the function
+    UnboundOutput(Symbol),
+    // The tokens produced break the colors or capacity of an output place
+    PlaceViolation { place: Uuid, violation: PlaceViolation },
+    // A place behind an inhibitor arc holds a token of the inhibiting clade
+    Inhibited(Uuid),
+    // A transition of higher priority is enabled
+    Preempted(Uuid),
+}
+
+impl fmt::Display for FiringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FiringError::UnknownTransition(id) => write!(f, "no transition with id {}", id),
+            FiringError::UnboundInput(symbol) => write!(f, "input symbol '{}' is not bound", symbol),
+            FiringError::MissingToken { symbol, place } => {
+                write!(f, "the token bound to '{}' is not in place {}", symbol, place)
+            }
+            FiringError::GuardNotSatisfied => write!(f, "the binding does not satisfy the guard"),
+            FiringError::UnboundOutput(symbol) => write!(f, "output symbol '{}' is not bound by any input", symbol),
+            FiringError::PlaceViolation { place, violation } => write!(f, "in place {}, {}", place, violation),
+            FiringError::Inhibited(place) => write!(f, "place {} holds an inhibiting token", place),
+            FiringError::Preempted(transition) => write!(f, "transition {} has a higher priority and is enabled", transition),
+        }
+    }
+}
+
+impl std::error::Error for FiringError {}
+
 /// A struct representing transitions between places in a Petri net.
 /// 
 /// The transition encodes the input and output signatures of the transition's tokens
@@ -31,6 +76,24 @@ pub struct Transition {
     pub guard: Guard,
     // Specifies the function result at each outgoing edge, hashed by the id of the target place
     pub function: Function,
+    // Variables bound to tokens that are looked at but not consumed, hashed by the id of the place
+    #[serde(default)]
+    pub reads: HashMap<Uuid, Signature>,
+    // Places that must hold no token of the given clade (or no token at all, when None)
+    #[serde(default)]
+    pub inhibitors: HashMap<Uuid, Option<Clade>>,
+    // Places emptied when the transition fires, before its outputs are produced
+    #[serde(default)]
+    pub resets: HashSet<Uuid>,
+    // While a transition of higher priority is enabled, transitions of lower priority are not
+    #[serde(default)]
+    pub priority: i32,
+    // The subnet this transition stands for, if it is a substitution transition
+    #[serde(default)]
+    pub substitution: Option<Substitution>,
+    // Transitions sharing a label are synchronized when nets are composed
+    #[serde(default)]
+    pub label: Option<String>,
 }
 
 impl Transition {
@@ -112,9 +175,189 @@ impl Transition {
             output: final_output,
             guard: final_guard,
             function: final_function,
+            reads: HashMap::new(),
+            inhibitors: HashMap::new(),
+            resets: HashSet::new(),
+            priority: 0,
+            substitution: None,
+            label: None,
         };
     }
 
+    /// Adds a read arc: tokens bound to its symbols must be in the place, and stay there.
+    pub fn with_read(mut self, place: Uuid, signature: Signature) -> Self {
+        self.reads.insert(place, signature);
+        self
+    }
+
+    /// Adds an inhibitor arc, disabling the transition while the place holds a token of the
+    /// clade (or any token, when no clade is given).
+    pub fn with_inhibitor(mut self, place: Uuid, clade: Option<Clade>) -> Self {
+        self.inhibitors.insert(place, clade);
+        self
+    }
+
+    /// Adds a reset arc, emptying the place whenever the transition fires.
+    pub fn with_reset(mut self, place: Uuid) -> Self {
+        self.resets.insert(place);
+        self
+    }
+
+    /// Makes this a substitution transition standing for an instance of a subnet.
+    pub fn with_substitution(mut self, substitution: Substitution) -> Self {
+        self.substitution = Some(substitution);
+        self
+    }
+
+    /// Sets the label by which the transition synchronizes with transitions of other nets.
+    pub fn with_label(mut self, label: String) -> Self {
+        self.label = Some(label);
+        self
+    }
+
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Sets the guard, which may also refer to symbols of read arcs. As in `new`, a guard with
+    /// symbols no arc binds is replaced by an empty guard.
+    pub fn with_guard(mut self, guard: Guard) -> Self {
+        let bound = self.input.values().chain(self.reads.values()).flat_map(|s| s.symbols.iter()).collect::<HashSet<_>>();
+        if guard.symbols().iter().all(|symbol| bound.contains(symbol)) {
+            self.guard = guard;
+        } else {
+            warn!("Transition {} has guard symbols not bound by its arcs. Using an empty guard.", self.name);
+            self.guard = Guard::Empty;
+        }
+        self
+    }
+
+    /// Whether a place holds a token that an inhibitor arc forbids.
+    pub fn inhibited(&self, marking: &Marking) -> Option<Uuid> {
+        self.inhibitors.iter().sorted_by_key(|(place, _)| **place).find_map(|(place, clade)| {
+            let tokens = marking.get(place)?;
+            let blocking = match clade {
+                Some(clade) => tokens.values().any(|t| clade.descendent(&t.clade.id())),
+                None => !tokens.is_empty(),
+            };
+            blocking.then_some(*place)
+        })
+    }
+
+    /// Enumerates every binding of the input and read symbols to distinct tokens of the given
+    /// marking that satisfies the guard, unless an inhibitor arc disables the transition.
+    ///
+    /// Places and tokens are visited in a fixed order (by id, and by name then id), so the
+    /// bindings are returned in the same order for equal markings. A transition without inputs
+    /// has exactly one, empty, binding. Substitution transitions stand for their subnet and have
+    /// none; flatten the net to fire the transitions of the subnet.
+    pub fn bindings(&self, marking: &Marking) -> Vec<Binding> {
+        if self.substitution.is_some() || self.inhibited(marking).is_some() {
+            return vec![];
+        }
+        let empty = HashMap::new();
+        let mut symbols_by_place: BTreeMap<Uuid, Vec<&Symbol>> = BTreeMap::new();
+        for (place, signature) in self.input.iter().chain(self.reads.iter()) {
+            symbols_by_place.entry(*place).or_default().extend(signature.symbols.iter());
+        }
+        let per_place = symbols_by_place
+            .into_iter()
+            .map(|(place, symbols)| {
+                let symbols = symbols.into_iter().sorted().collect_vec();
+                let tokens = marking
+                    .get(&place)
+                    .unwrap_or(&empty)
+                    .values()
+                    .sorted_by_key(|t| (t.name.clone(), t.id))
+                    .collect_vec();
+                tokens
+                    .into_iter()
+                    .permutations(symbols.len())
+                    .map(|chosen| {
+                        symbols
+                            .iter()
+                            .zip(chosen)
+                            .map(|(symbol, token)| ((*symbol).clone(), (place, token.clone())))
+                            .collect_vec()
+                    })
+                    .collect_vec()
+            })
+            .collect_vec();
+        if per_place.is_empty() {
+            let binding = Binding::default();
+            return if self.guard.eval(&binding.clades()) { vec![binding] } else { vec![] };
+        }
+        per_place
+            .into_iter()
+            .multi_cartesian_product()
+            .map(|assignments| Binding::new(assignments.into_iter().flatten().collect::<BTreeMap<_, _>>()))
+            .filter(|binding| self.guard.eval(&binding.clades()))
+            .collect()
+    }
+
+    /// True if at least one binding of the marking satisfies the input signatures and the guard.
+    pub fn is_enabled(&self, marking: &Marking) -> bool {
+        !self.bindings(marking).is_empty()
+    }
+
+    /// Computes the marking that results from firing the transition with a binding.
+    ///
+    /// The bound input tokens are removed from their places, reset places are emptied, and every
+    /// output symbol places its bound token in the corresponding output place, so tokens keep
+    /// their identity as they move. Tokens bound by read arcs only need to be present.
+    pub fn fire(
+        &self,
+        marking: &Marking,
+        binding: &Binding,
+    ) -> Result<Marking, FiringError> {
+        if let Some(place) = self.inhibited(marking) {
+            return Err(FiringError::Inhibited(place));
+        }
+        for (place, signature) in self.reads.iter().sorted_by_key(|(place, _)| **place) {
+            for symbol in signature.symbols.iter().sorted() {
+                let (_, token) = binding
+                    .tokens
+                    .get(symbol)
+                    .filter(|(from, _)| from == place)
+                    .ok_or_else(|| FiringError::UnboundInput(symbol.clone()))?;
+                if !marking.get(place).is_some_and(|tokens| tokens.contains_key(&token.id)) {
+                    return Err(FiringError::MissingToken { symbol: symbol.clone(), place: *place });
+                }
+            }
+        }
+        let mut next = marking.clone();
+        for (place, signature) in self.input.iter().sorted_by_key(|(place, _)| **place) {
+            for symbol in signature.symbols.iter().sorted() {
+                let (_, token) = binding
+                    .tokens
+                    .get(symbol)
+                    .filter(|(from, _)| from == place)
+                    .ok_or_else(|| FiringError::UnboundInput(symbol.clone()))?;
+                next.remove_token(place, &token.id)
+                    .ok_or_else(|| FiringError::MissingToken { symbol: symbol.clone(), place: *place })?;
+            }
+        }
+        if !self.guard.eval(&binding.clades()) {
+            return Err(FiringError::GuardNotSatisfied);
+        }
+        for place in &self.resets {
+            next.remove(place);
+        }
+        for (place, signature) in self.output.iter().sorted_by_key(|(place, _)| **place) {
+            for symbol in signature.symbols.iter().sorted() {
+                // Read tokens stay where they are, so only consumed tokens can be produced
+                let token = binding
+                    .token(symbol)
+                    .filter(|_| self.input.values().any(|s| s.symbols.contains(symbol)))
+                    .ok_or_else(|| FiringError::UnboundOutput(symbol.clone()))?;
+                next.add_token(*place, token.clone());
+            }
+        }
+        next.retain(|_, tokens| !tokens.is_empty());
+        Ok(next)
+    }
+
     // pub fn get_time(&self, signature: &Signature) -> Time {
     //     self.function.get_time(signature).map(|t| *t).unwrap_or(0)
     // }
@@ -205,3 +448,107 @@ pub fn verify_signature() {
 pub fn verify_guard() {
 
 }
+
+#[test]
+pub fn transition_bindings() {
+    use crate::token::Token;
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let robot2 = Clade::new("robot2".into(), None);
+    let robot = Clade::new("robot".into(), Some(vec![robot1.clone(), robot2.clone()]));
+    let place = Uuid::new_v4();
+    let token1 = Token::new("r1".into(), robot1.clone());
+    let token2 = Token::new("r2".into(), robot2.clone());
+    let marking: Marking = [(place, token1.clone()), (place, token2.clone())].into_iter().collect();
+
+    let pair = Transition::new(
+        "pair".into(),
+        Some(HashMap::from([(place, Signature::new(HashSet::from(["x".into(), "y".into()])))])),
+        None,
+        Some(Guard::All(vec![Guard::LessThan("x".into(), robot.clone()), Guard::Is("y".into(), robot2.clone())])),
+        None,
+    );
+    let bindings = pair.bindings(&marking);
+    assert_eq!(bindings.len(), 1);
+    assert_eq!(bindings[0].token(&"x".into()), Some(&token1));
+    assert_eq!(bindings[0].token(&"y".into()), Some(&token2));
+
+    let triple = Transition::new(
+        "triple".into(),
+        Some(HashMap::from([(place, Signature::new(HashSet::from(["x".into(), "y".into(), "z".into()])))])),
+        None,
+        None,
+        None,
+    );
+    assert!(!triple.is_enabled(&marking));
+    assert_eq!(Transition::new("source".into(), None, None, None, None).bindings(&marking).len(), 1);
+}
+
+#[test]
+pub fn read_inhibitor_and_reset_arcs() {
+    use crate::incidence::Incidence;
+    use crate::net::ColoredPetriNet;
+    use crate::place::Place;
+    use crate::pnml::{from_pnml, to_pnml};
+    use crate::statespace::StateSpace;
+    use crate::token::Token;
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let robot2 = Clade::new("robot2".into(), None);
+    let robot = Clade::new("robot".into(), Some(vec![robot1.clone(), robot2.clone()]));
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    net.add_clade(robot.clone());
+    let idle = net.add_place(Place::new("idle".into()));
+    let busy = net.add_place(Place::new("busy".into()));
+    let permit = net.add_place(Place::new("permit".into()));
+    let log = net.add_place(Place::new("log".into()));
+    net.add_token(idle, Token::new("r1".into(), robot1.clone()));
+    net.add_token(idle, Token::new("r2".into(), robot2.clone()));
+    net.add_token(permit, Token::new("p".into(), robot1.clone()));
+    let sig = |s: &str| Signature::new(HashSet::from([s.into()]));
+    // A robot may start while the permit names its clade, and only while robot2 is not busy
+    let start = net.add_transition(
+        Transition::new("start".into(), Some(HashMap::from([(idle, sig("x"))])), Some(HashMap::from([(busy, sig("x"))])), None, None)
+            .with_read(permit, sig("p"))
+            .with_inhibitor(busy, Some(robot2.clone()))
+            .with_guard(Guard::LessThanOrEqual("x".into(), robot.clone())),
+    );
+    let stop = net.add_transition(
+        Transition::new("stop".into(), Some(HashMap::from([(busy, sig("x"))])), Some(HashMap::from([(idle, sig("x"))])), None, None)
+            .with_reset(log),
+    );
+
+    let bindings = net.transitions[&start].bindings(&net.current_marking);
+    assert_eq!(bindings.len(), 2);
+    assert!(bindings.iter().all(|b| b.tokens[&Symbol::from("p")].0 == permit));
+    net.fire(&start, &bindings[1]).unwrap();
+    assert_eq!(net.current_marking[&permit].len(), 1);
+    // robot2 is busy, which inhibits starting robot1
+    assert!(net.transitions[&start].bindings(&net.current_marking).is_empty());
+    assert_eq!(net.fire(&start, &bindings[0]), Err(FiringError::Inhibited(busy)));
+    net.current_marking.entry(log).or_default().extend([Token::new("l".into(), robot1.clone())].map(|t| (t.id, t)));
+    let (_, binding) = net.enabled().into_iter().find(|(t, _)| *t == stop).unwrap();
+    net.fire(&stop, &binding).unwrap();
+    assert!(!net.current_marking.contains_key(&log));
+
+    let json = serde_json::to_string(&net.transitions[&start]).unwrap();
+    assert_eq!(serde_json::from_str::<Transition>(&json).unwrap(), net.transitions[&start]);
+    let (xml, report) = to_pnml(&net);
+    assert_eq!(report.issues.iter().filter(|i| i.message.contains("tool-specific")).count(), 2);
+    let (read, _) = from_pnml(&xml).unwrap();
+    assert_eq!(read.transitions[&start], net.transitions[&start]);
+    assert_eq!(read.transitions[&stop], net.transitions[&stop]);
+
+    // Read arcs unfold into self-loops and inhibitor arcs are dropped, but reset arcs cannot be
+    // unfolded
+    let incidence = Incidence::new(&net);
+    assert_eq!(incidence.unsupported, vec![stop]);
+    net.transitions.get_mut(&stop).unwrap().resets.clear();
+    let incidence = Incidence::new(&net);
+    assert!(incidence.unsupported.is_empty());
+    let permit_row = incidence.places.iter().position(|(p, c)| *p == permit && *c == robot1).unwrap();
+    assert!(incidence.matrix()[permit_row].iter().all(|n| *n == 0));
+    assert!(incidence.pre[permit_row].contains(&1));
+    net.reset();
+    assert_eq!(StateSpace::explore(&net, None).markings.len(), 4);
+}
@@ -0,0 +1,194 @@
+use std::collections::{BTreeMap, HashMap};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::clade::Clade;
+use crate::marking::Marking;
+use crate::incidence::{Incidence, Unsupported};
+use crate::net::ColoredPetriNet;
+use crate::symbol::Symbol;
+
+/// A place of an unfolded net, standing for the tokens of one clade in one colored place.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PtPlace {
+    pub name: String,
+    pub place: Uuid,
+    pub clade: Clade,
+}
+
+/// A transition of an unfolded net, standing for one colored transition fired with input
+/// symbols bound to tokens of the given clades.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PtTransition {
+    pub name: String,
+    pub transition: Uuid,
+    pub assignment: BTreeMap<Symbol, Clade>,
+    // Arc weights, by index of the place in the unfolded net
+    pub input: BTreeMap<usize, u64>,
+    pub output: BTreeMap<usize, u64>,
+}
+
+/// An uncoloured place/transition net, with a mapping from each of its elements back to the
+/// colored net it was unfolded from.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PtNet {
+    pub name: String,
+    pub places: Vec<PtPlace>,
+    pub transitions: Vec<PtTransition>,
+    pub initial_marking: Vec<u64>,
+}
+
+/// Unfolds a colored net into a place/transition net that over-approximates it.
+///
+/// There is one place per (place, leaf clade) pair and one transition per (transition,
+/// assignment) pair whose assignment satisfies the guard, so guards disappear from the result.
+/// Place colors are respected in the same way, but capacities and priorities are not carried
+/// over, and neither are inhibitor arcs, so the unfolded net may reach more markings. It also
+/// counts tokens where the colored marking holds each token at most once per place: a
+/// transition that sends a symbol to two outputs, or puts a token where it already is, adds a
+/// token on every firing of the unfolded net but not of the colored one. Nets with reset arcs
+/// cannot be unfolded.
+pub fn unfold(net: &ColoredPetriNet) -> Result<PtNet, Unsupported> {
+    let incidence = Incidence::new(net);
+    incidence.check()?;
+    let places = incidence
+        .places
+        .iter()
+        .enumerate()
+        .map(|(i, (place, clade))| PtPlace { name: incidence.place_name(net, i), place: *place, clade: clade.clone() })
+        .collect();
+    let weights = |matrix: &Vec<Vec<i64>>, column: usize| -> BTreeMap<usize, u64> {
+        matrix.iter().enumerate().filter(|(_, row)| row[column] > 0).map(|(i, row)| (i, row[column] as u64)).collect()
+    };
+    let transitions = incidence
+        .transitions
+        .iter()
+        .enumerate()
+        .map(|(j, (transition, assignment))| PtTransition {
+            name: incidence.transition_name(net, j),
+            transition: *transition,
+            assignment: assignment.clone(),
+            input: weights(&incidence.pre, j),
+            output: weights(&incidence.post, j),
+        })
+        .collect();
+    Ok(PtNet {
+        name: net.name.clone(),
+        places,
+        transitions,
+        initial_marking: incidence.marking(&net.initial_marking).into_iter().map(|n| n as u64).collect(),
+    })
+}
+
+impl PtNet {
+    pub fn is_enabled(&self, marking: &[u64], transition: usize) -> bool {
+        self.transitions[transition].input.iter().all(|(p, w)| marking[*p] >= *w)
+    }
+
+    pub fn enabled(&self, marking: &[u64]) -> Vec<usize> {
+        (0..self.transitions.len()).filter(|t| self.is_enabled(marking, *t)).collect()
+    }
+
+    /// The marking after firing a transition, if it is enabled.
+    pub fn fire(&self, marking: &[u64], transition: usize) -> Option<Vec<u64>> {
+        if !self.is_enabled(marking, transition) {
+            return None;
+        }
+        let mut next = marking.to_vec();
+        for (p, w) in &self.transitions[transition].input {
+            next[*p] -= w;
+        }
+        for (p, w) in &self.transitions[transition].output {
+            next[*p] += w;
+        }
+        Some(next)
+    }
+
+    /// Indices of the unfolded places standing for a colored place.
+    pub fn places_of(&self, place: &Uuid) -> Vec<usize> {
+        (0..self.places.len()).filter(|i| self.places[*i].place == *place).collect()
+    }
+
+    /// Indices of the unfolded transitions standing for a colored transition.
+    pub fn transitions_of(&self, transition: &Uuid) -> Vec<usize> {
+        (0..self.transitions.len()).filter(|i| self.transitions[*i].transition == *transition).collect()
+    }
+
+    /// Counts the tokens of a colored marking in each unfolded place.
+    pub fn marking_of(&self, marking: &Marking) -> Vec<u64> {
+        self.places
+            .iter()
+            .map(|p| {
+                marking
+                    .get(&p.place)
+                    .map(|tokens| tokens.values().filter(|t| t.clade.id() == p.clade.id()).count() as u64)
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+
+    /// Maps an unfolded marking back to token counts per clade in each colored place.
+    pub fn project(&self, marking: &[u64]) -> HashMap<Uuid, HashMap<Uuid, u64>> {
+        let mut projected: HashMap<Uuid, HashMap<Uuid, u64>> = HashMap::new();
+        for (place, count) in self.places.iter().zip(marking) {
+            if *count > 0 {
+                *projected.entry(place.place).or_default().entry(place.clade.id()).or_default() += count;
+            }
+        }
+        projected
+    }
+}
+
+#[test]
+pub fn unfolding_preserves_behaviour() {
+    use std::collections::{HashSet, VecDeque};
+    use crate::guard::Guard;
+    use crate::place::Place;
+    use crate::signature::Signature;
+    use crate::statespace::StateSpace;
+    use crate::token::Token;
+    use crate::transition::Transition;
+
+    let robot1 = Clade::new("robot1".into(), None);
+    let robot2 = Clade::new("robot2".into(), None);
+    let robot = Clade::new("robot".into(), Some(vec![robot1.clone(), robot2.clone()]));
+    let mut net = ColoredPetriNet::new("cell".into(), None, None, None);
+    net.add_clade(robot.clone());
+    let idle = net.add_place(Place::new("idle".into()));
+    let busy = net.add_place(Place::new("busy".into()));
+    net.add_token(idle, Token::new("r1".into(), robot1.clone()));
+    net.add_token(idle, Token::new("r2".into(), robot2.clone()));
+    let sig = || Signature::new(HashSet::from(["x".into()]));
+    let start = net.add_transition(Transition::new(
+        "start".into(),
+        Some(HashMap::from([(idle, sig())])),
+        Some(HashMap::from([(busy, sig())])),
+        Some(Guard::Is("x".into(), robot1.clone())),
+        None,
+    ));
+    net.add_transition(Transition::new("stop".into(), Some(HashMap::from([(busy, sig())])), Some(HashMap::from([(idle, sig())])), None, None));
+
+    let pt = unfold(&net).unwrap();
+    assert_eq!(pt.places.len(), 4);
+    // The guard leaves a single instance of start, while stop has one per robot
+    assert_eq!(pt.transitions_of(&start).len(), 1);
+    assert_eq!(pt.transitions.len(), 3);
+    assert_eq!(pt.places_of(&idle).len(), 2);
+    assert_eq!(pt.initial_marking, pt.marking_of(&net.initial_marking));
+
+    let mut seen = HashSet::from([pt.initial_marking.clone()]);
+    let mut queue = VecDeque::from([pt.initial_marking.clone()]);
+    while let Some(marking) = queue.pop_front() {
+        for t in pt.enabled(&marking) {
+            let next = pt.fire(&marking, t).unwrap();
+            if seen.insert(next.clone()) {
+                queue.push_back(next);
+            }
+        }
+    }
+    let space = StateSpace::explore(&net, None);
+    assert_eq!(seen.len(), space.markings.len());
+    for marking in &space.markings {
+        assert!(seen.contains(&pt.marking_of(marking)));
+    }
+    assert_eq!(pt.project(&pt.initial_marking)[&idle][&robot2.id()], 1);
+}